@@ -0,0 +1,93 @@
+// Benchmarks of the render path
+//
+// Run headless with `cargo bench`, no window is opened. Each benchmark is warmed up and then
+// run for a fixed time, the mean time per iteration is printed. Pass a name to run only the
+// benchmarks that contain it, e.g. `cargo bench -- clear`.
+
+#![allow(clippy::needless_return)]
+
+extern crate nalgebra as na;
+extern crate renderer;
+
+use na::{Vector2, Vector3, Vector4};
+use renderer::core::{DisplayBuffer, Mesh};
+use renderer::rasterization::get_barycentric;
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const WARM_UP: Duration = Duration::from_millis(200);
+const MEASURE: Duration = Duration::from_secs(1);
+
+/// Run a benchmark and print the mean time per iteration
+fn bench<F: FnMut()>(filter: &Option<String>, name: &str, mut f: F) {
+    if filter
+        .as_ref()
+        .is_some_and(|filter| !name.contains(filter.as_str()))
+    {
+        return;
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < WARM_UP {
+        f();
+    }
+
+    let mut iterations: u32 = 0;
+    let start = Instant::now();
+    while start.elapsed() < MEASURE {
+        f();
+        iterations += 1;
+    }
+    let per_iteration = start.elapsed() / iterations;
+    println!(
+        "{:<24} {:>12.3} us/iter ({} iterations)",
+        name,
+        per_iteration.as_secs_f64() * 1e6,
+        iterations
+    );
+}
+
+fn main() {
+    // `cargo bench` passes --bench, the first other argument filters the benchmarks
+    let filter = env::args().skip(1).find(|a| !a.starts_with("--"));
+
+    let mut sphere = Mesh::uv_sphere(32, 64);
+    sphere.position = Vector4::new(0.0, 0.0, -3.0, 1.0);
+    let eye = Vector3::new(0.0, 0.0, 0.0);
+    let lookat = Vector3::new(0.0, 0.0, -1.0);
+    let mut buffer = DisplayBuffer::new(640, 480, 4);
+
+    bench(&filter, "mesh_render_sphere", || {
+        buffer.clear();
+        sphere.render(eye, lookat, &mut buffer);
+        black_box(&buffer);
+    });
+
+    sphere.cache_transforms = true;
+    bench(&filter, "mesh_render_sphere_cached", || {
+        buffer.clear();
+        sphere.render(eye, lookat, &mut buffer);
+        black_box(&buffer);
+    });
+
+    bench(&filter, "display_buffer_clear", || {
+        buffer.clear();
+        black_box(&buffer);
+    });
+
+    let (a, b, c) = (
+        Vector2::new(0.0, 0.0),
+        Vector2::new(10.0, 1.0),
+        Vector2::new(2.0, 8.0),
+    );
+    let p = Vector2::new(3.0, 3.0);
+    bench(&filter, "get_barycentric", || {
+        black_box(get_barycentric(
+            black_box(a),
+            black_box(b),
+            black_box(c),
+            black_box(p),
+        ));
+    });
+}
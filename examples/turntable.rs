@@ -0,0 +1,93 @@
+// Render a model turning once around its vertical axis into an animated GIF
+//
+// No window is opened, the frames are drawn into a display buffer and encoded as they are
+// rendered:
+//
+//     cargo run --release --example turntable -- model.obj 36 turntable.gif
+//
+// The model can be an OBJ, PLY or STL file, the default triangle is drawn without one. The
+// frame count defaults to 36 and the output path to `turntable.gif`.
+
+#![allow(clippy::needless_return)]
+
+extern crate nalgebra as na;
+extern crate renderer;
+
+use na::{Vector3, Vector4};
+use renderer::core::{default_triangle, DisplayBuffer, Mesh};
+use renderer::export::GifEncoder;
+use renderer::{ply, stl, wavefront};
+use std::env;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::process;
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 240;
+
+/// Time to show each frame in hundredths of a second
+const FRAME_DELAY: u16 = 4;
+
+fn load_model(path: &str) -> io::Result<Mesh> {
+    let extension = Path::new(path).extension().and_then(|e| e.to_str());
+    let mut model = match extension.map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("ply") => ply::load_ply(path)?,
+        Some("stl") => stl::load_stl(path)?,
+        _ => wavefront::load_obj(path)?,
+    };
+    model.center_and_normalize();
+    return Ok(model);
+}
+
+fn render_turntable(model: &mut Mesh, frames: u32, output: &str) -> io::Result<()> {
+    let out = BufWriter::new(File::create(output)?);
+    let mut encoder = GifEncoder::new(out, WIDTH, HEIGHT)?;
+    let mut buffer = DisplayBuffer::new(WIDTH, HEIGHT, 4);
+    let eye = Vector3::new(0.0, 0.0, 0.0);
+    let lookat = Vector3::new(0.0, 0.0, -1.0);
+
+    for frame in 0..frames {
+        model.angle.y = 2.0 * PI * frame as f32 / frames as f32;
+        buffer.clear();
+        model.render(eye, lookat, &mut buffer);
+        encoder.write_frame(&buffer, FRAME_DELAY)?;
+    }
+    encoder.finish()?;
+    return Ok(());
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut model = match args.get(1) {
+        Some(path) => match load_model(path) {
+            Ok(model) => model,
+            Err(e) => {
+                eprintln!("Error: Could not load file {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => {
+            println!("Usage: turntable [FILE.obj|FILE.ply|FILE.stl [FRAMES [OUTPUT.gif]]]");
+            println!("No model file given. Rendering the default model");
+            default_triangle()
+        }
+    };
+    let frames = match args.get(2).map(|a| a.parse::<u32>()) {
+        None => 36,
+        Some(Ok(frames)) if frames > 0 => frames,
+        Some(_) => {
+            eprintln!("Error: The frame count must be a positive number");
+            process::exit(1);
+        }
+    };
+    let output = args.get(3).map_or("turntable.gif", |a| a.as_str());
+    model.position = Vector4::new(0.0, 0.0, -2.0, 1.0);
+
+    if let Err(e) = render_turntable(&mut model, frames, output) {
+        eprintln!("Error: Could not write {}: {}", output, e);
+        process::exit(1);
+    }
+    println!("Wrote {} frames to {}", frames, output);
+}
@@ -0,0 +1,87 @@
+// Render a spinning triangle into a browser canvas
+//
+// Build the example without the window of the test app:
+//
+//     cargo build --release --example wasm_canvas --no-default-features \
+//         --target wasm32-unknown-unknown
+//
+// The module exports plain functions, so it can be loaded without any bindings generator.
+// Each frame the host calls `render` and copies the RGBA bytes from the linear memory into
+// an `ImageData` of the same size:
+//
+//     const { instance } = await WebAssembly.instantiateStreaming(fetch("wasm_canvas.wasm"));
+//     const { memory, render, frame_len } = instance.exports;
+//     const ptr = render(canvas.width, canvas.height, performance.now() / 1000);
+//     const bytes = new Uint8ClampedArray(memory.buffer, ptr, frame_len());
+//     ctx.putImageData(new ImageData(bytes, canvas.width, canvas.height), 0, 0);
+//
+// The rows of the frame are stored top-down like the rows of `ImageData`.
+
+#![allow(clippy::needless_return)]
+
+extern crate nalgebra as na;
+extern crate renderer;
+
+use na::{Vector2, Vector3, Vector4};
+use renderer::core::{Face, FloatColor, Mesh, Vertex, NO_MATERIAL};
+use renderer::scene::{render_to_rgba, Scene};
+use std::cell::RefCell;
+
+thread_local! {
+    /// The last rendered frame, kept alive for the host to read
+    static FRAME: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+fn triangle(angle: f32) -> Mesh {
+    let vertex = |x: f32, y: f32, r: f32, g: f32, b: f32| Vertex {
+        position: Vector4::new(x, y, 0.0, 1.0),
+        color: FloatColor { r, g, b, a: 1.0 },
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        uv: Vector2::new(x, y),
+        tangent: Vector3::zeros(),
+    };
+    let mut mesh = Mesh::new();
+    mesh.faces.push(Face {
+        v0: vertex(0.0, 0.5, 1.0, 0.0, 0.0),
+        v1: vertex(-0.5, -0.5, 0.0, 1.0, 0.0),
+        v2: vertex(0.5, -0.5, 0.0, 0.0, 1.0),
+        material_id: NO_MATERIAL,
+    });
+    mesh.double_sided = true;
+    mesh.position = Vector4::new(0.0, 0.0, -2.0, 1.0);
+    mesh.angle.y = angle;
+    return mesh;
+}
+
+/// Render a frame and return a pointer to its RGBA bytes
+///
+/// The bytes stay valid until the next call.
+///
+/// # Arguments
+///
+/// * `width` - Width of the canvas in pixels
+/// * `height` - Height of the canvas in pixels
+/// * `time` - Animation time in seconds
+#[no_mangle]
+pub extern "C" fn render(width: u32, height: u32, time: f32) -> *const u8 {
+    let mut scene = Scene::new();
+    scene.meshes.push(triangle(time));
+    let rgba = render_to_rgba(
+        &scene,
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+        width as usize,
+        height as usize,
+    );
+
+    return FRAME.with(|frame| {
+        *frame.borrow_mut() = rgba;
+        frame.borrow().as_ptr()
+    });
+}
+
+/// Number of bytes in the last rendered frame
+#[no_mangle]
+pub extern "C" fn frame_len() -> usize {
+    return FRAME.with(|frame| frame.borrow().len());
+}
@@ -0,0 +1,215 @@
+// Keyframe animation of mesh transforms
+//
+// An animation is a list of keyframes ordered by time. Sampling the animation at time t
+// interpolates between the two keyframes surrounding t: translation linearly and rotation
+// spherically (through quaternions) so that the interpolated rotation does not suffer from
+// gimbal issues of the Euler angles stored in the mesh.
+
+use core::Mesh;
+use na::{UnitQuaternion, Vector3, Vector4};
+
+/// Transform of a mesh at a point in time
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Keyframe {
+    /// Time of the keyframe in seconds
+    pub time: f32,
+    /// World position of the center of the mesh
+    pub translation: Vector3<f32>,
+    /// Rotation of the mesh around all 3 axis vectors in radians (same as `Mesh::angle`)
+    pub rotation: Vector3<f32>,
+}
+
+/// Convert mesh rotation angles to a quaternion
+///
+/// The mesh model matrix rotates by the x angle around the x axis, then the y angle around
+/// the y axis and the z angle around the z axis, all counterclockwise (right-handed)
+fn to_quaternion(angle: Vector3<f32>) -> UnitQuaternion<f32> {
+    return UnitQuaternion::from_euler_angles(angle.x, angle.y, angle.z);
+}
+
+/// Convert a quaternion to mesh rotation angles, inverse of `to_quaternion`
+fn from_quaternion(q: UnitQuaternion<f32>) -> Vector3<f32> {
+    let (roll, pitch, yaw) = q.euler_angles();
+    return Vector3::new(roll, pitch, yaw);
+}
+
+/// Animation that drives the transform of a mesh with keyframes
+pub struct Animation {
+    /// Keyframes ordered by time
+    keyframes: Vec<Keyframe>,
+}
+
+impl Default for Animation {
+    fn default() -> Animation {
+        return Animation::new();
+    }
+}
+
+impl Animation {
+    pub fn new() -> Animation {
+        return Animation {
+            keyframes: Vec::new(),
+        };
+    }
+
+    /// Add a keyframe to the animation
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - Time of the keyframe in seconds
+    /// * `translation` - World position of the center of the mesh
+    /// * `rotation` - Rotation around each cartesian axis in radians
+    pub fn add_keyframe(&mut self, time: f32, translation: Vector3<f32>, rotation: Vector3<f32>) {
+        let index = self
+            .keyframes
+            .iter()
+            .position(|k| k.time > time)
+            .unwrap_or(self.keyframes.len());
+        self.keyframes.insert(
+            index,
+            Keyframe {
+                time,
+                translation,
+                rotation,
+            },
+        );
+    }
+
+    /// Time of the last keyframe in seconds
+    pub fn duration(&self) -> f32 {
+        return self.keyframes.last().map_or(0.0, |k| k.time);
+    }
+
+    /// Interpolate the transform at a point in time
+    ///
+    /// Times before the first keyframe or after the last keyframe are clamped. Returns `None`
+    /// if the animation has no keyframes.
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - Time in seconds
+    pub fn sample(&self, t: f32) -> Option<Keyframe> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if t <= first.time {
+            return Some(Keyframe { time: t, ..*first });
+        }
+        if t >= last.time {
+            return Some(Keyframe { time: t, ..*last });
+        }
+
+        let next = self.keyframes.iter().position(|k| k.time > t)?;
+        let a = &self.keyframes[next - 1];
+        let b = &self.keyframes[next];
+        let s = (t - a.time) / (b.time - a.time);
+
+        // Orientations 180 degrees apart have no unique shortest path, fall back to
+        // interpolating the angles directly
+        let rotation = match to_quaternion(a.rotation).try_slerp(
+            &to_quaternion(b.rotation),
+            s,
+            f32::EPSILON,
+        ) {
+            Some(q) => from_quaternion(q),
+            None => a.rotation.lerp(&b.rotation, s),
+        };
+
+        return Some(Keyframe {
+            time: t,
+            translation: a.translation.lerp(&b.translation, s),
+            rotation,
+        });
+    }
+
+    /// Set the transform of a mesh to the interpolated transform at a point in time
+    ///
+    /// The mesh is not modified if the animation has no keyframes.
+    ///
+    /// # Arguments
+    ///
+    /// * `t` - Time in seconds
+    /// * `mesh` - Mesh to transform
+    pub fn apply(&self, t: f32, mesh: &mut Mesh) {
+        if let Some(k) = self.sample(t) {
+            mesh.position = Vector4::new(k.translation.x, k.translation.y, k.translation.z, 1.0);
+            mesh.angle = k.rotation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::rotation_matrix;
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).norm() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_quaternion_matches_mesh_rotation() {
+        let angle = Vector3::new(0.3, -0.7, 1.1);
+
+        let q = to_quaternion(angle);
+
+        let difference = q.to_homogeneous() - rotation_matrix(angle);
+        assert!(difference.norm() < 1e-5, "{}", difference);
+        assert_near(from_quaternion(q), angle);
+    }
+
+    #[test]
+    fn test_sample_interpolates_translation() {
+        let mut animation = Animation::new();
+        animation.add_keyframe(2.0, Vector3::new(4.0, 0.0, -2.0), Vector3::zeros());
+        animation.add_keyframe(0.0, Vector3::new(0.0, 0.0, 0.0), Vector3::zeros());
+
+        let k = animation.sample(0.5).unwrap();
+
+        assert_near(k.translation, Vector3::new(1.0, 0.0, -0.5));
+        assert_eq!(animation.duration(), 2.0);
+    }
+
+    #[test]
+    fn test_sample_clamps_outside_keyframes() {
+        let mut animation = Animation::new();
+        animation.add_keyframe(1.0, Vector3::new(1.0, 0.0, 0.0), Vector3::zeros());
+        animation.add_keyframe(2.0, Vector3::new(2.0, 0.0, 0.0), Vector3::zeros());
+
+        assert_near(
+            animation.sample(0.0).unwrap().translation,
+            Vector3::new(1.0, 0.0, 0.0),
+        );
+        assert_near(
+            animation.sample(5.0).unwrap().translation,
+            Vector3::new(2.0, 0.0, 0.0),
+        );
+        assert!(Animation::new().sample(0.0).is_none());
+    }
+
+    #[test]
+    fn test_sample_interpolates_rotation() {
+        let mut animation = Animation::new();
+        animation.add_keyframe(0.0, Vector3::zeros(), Vector3::zeros());
+        animation.add_keyframe(1.0, Vector3::zeros(), Vector3::new(0.0, 1.0, 0.0));
+
+        let k = animation.sample(0.25).unwrap();
+
+        assert_near(k.rotation, Vector3::new(0.0, 0.25, 0.0));
+    }
+
+    #[test]
+    fn test_apply_sets_mesh_transform() {
+        let mut animation = Animation::new();
+        animation.add_keyframe(
+            0.0,
+            Vector3::new(0.0, 1.0, -6.0),
+            Vector3::new(0.5, 0.0, 0.0),
+        );
+        let mut mesh = Mesh::new();
+
+        animation.apply(0.0, &mut mesh);
+
+        assert_eq!(mesh.position, Vector4::new(0.0, 1.0, -6.0, 1.0));
+        assert_near(mesh.angle, Vector3::new(0.5, 0.0, 0.0));
+    }
+}
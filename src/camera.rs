@@ -0,0 +1,75 @@
+// Camera placement and visibility queries
+//
+// The render calls take the eye and the focus point of the camera as separate arguments. A
+// `Camera` keeps the two together for the queries that user code makes about what the
+// camera sees, e.g. to skip work for objects that are out of view.
+
+use clipping::inside_frustum;
+use core::{build_view_matrix, DisplayBuffer};
+use na::{Matrix4, Vector3, Vector4};
+
+/// Camera looking from an eye position towards a focus point, with the y axis up
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Camera {
+    /// Position of the camera eye in world space
+    pub eye: Vector3<f32>,
+    /// Focus point of the eye in world space
+    pub lookat: Vector3<f32>,
+}
+
+impl Camera {
+    /// Create a camera
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye in world space
+    /// * `lookat` - Focus point of the eye in world space
+    pub fn new(eye: Vector3<f32>, lookat: Vector3<f32>) -> Camera {
+        return Camera { eye, lookat };
+    }
+
+    /// Matrix that transforms world space to the right-handed camera space, see
+    /// `DisplayBuffer::view_matrix` for the camera space of a buffer
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        return build_view_matrix(self.eye, self.lookat, Vector3::new(0.0, 1.0, 0.0));
+    }
+
+    /// Check whether a world point is inside the view frustum
+    ///
+    /// The point is visible when it is inside all six clipping planes, whether it is hidden
+    /// behind other geometry is not considered.
+    ///
+    /// # Arguments
+    ///
+    /// * `point` - Point in world space
+    /// * `buffer` - Display buffer whose projection and handedness the camera renders with
+    pub fn is_visible(&self, point: Vector3<f32>, buffer: &DisplayBuffer) -> bool {
+        let view_projection =
+            buffer.projection_matrix() * buffer.view_matrix(self.eye, self.lookat);
+        let clip = view_projection * Vector4::new(point.x, point.y, point.z, 1.0);
+        return inside_frustum(&clip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Handedness;
+
+    #[test]
+    fn test_points_in_front_are_visible_and_behind_are_not() {
+        let camera = Camera::new(Vector3::new(1.0, 0.0, 2.0), Vector3::new(1.0, 0.0, 0.0));
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+
+        assert!(camera.is_visible(Vector3::new(1.0, 0.0, -3.0), &buffer));
+        assert!(!camera.is_visible(Vector3::new(1.0, 0.0, 5.0), &buffer));
+        assert!(!camera.is_visible(Vector3::new(1.0, 0.0, -200.0), &buffer));
+        assert!(!camera.is_visible(Vector3::new(20.0, 0.0, -3.0), &buffer));
+
+        // The left-handed camera space also looks towards the focus point
+        buffer.state.handedness = Handedness::LeftHanded;
+        let camera = Camera::new(Vector3::zeros(), Vector3::new(0.0, 0.0, -1.0));
+        assert!(camera.is_visible(Vector3::new(0.0, 0.0, -3.0), &buffer));
+        assert!(!camera.is_visible(Vector3::new(0.0, 0.0, 3.0), &buffer));
+    }
+}
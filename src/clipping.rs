@@ -0,0 +1,231 @@
+// Clipping of triangles against the view frustum
+//
+// Triangles are clipped in homogeneous clip space, before the perspective divide, against the
+// six planes of the frustum -w <= x, y, z <= w (Sutherland-Hodgman). Every plane can cut off a
+// corner of the polygon and add a vertex, so a clipped triangle becomes a convex polygon of up
+// to 9 vertices. The vertex attributes are linear in clip space, so the new vertices on the
+// planes are interpolated linearly.
+//
+// A user clip plane given in clip space is clipped against after the frustum planes. Its
+// distance is linear in clip space as well, so it is handled the same way.
+
+use core::{Face, Vertex};
+use na::Vector4;
+
+/// Number of clipping planes of the view frustum
+const NUM_PLANES: usize = 6;
+
+/// Signed distance of a clip space position to a frustum plane, positive inside the frustum
+///
+/// Planes are ordered left, right, bottom, top, near, far.
+fn plane_distance(p: &Vector4<f32>, plane: usize) -> f32 {
+    return match plane {
+        0 => p.w + p.x,
+        1 => p.w - p.x,
+        2 => p.w + p.y,
+        3 => p.w - p.y,
+        4 => p.w + p.z,
+        _ => p.w - p.z,
+    };
+}
+
+/// Interpolate all attributes of two vertices, t = 0 gives `a` and t = 1 gives `b`
+fn lerp_vertex(a: &Vertex<Vector4<f32>>, b: &Vertex<Vector4<f32>>, t: f32) -> Vertex<Vector4<f32>> {
+    return Vertex {
+        position: a.position + (b.position - a.position) * t,
+        color: a.color + (b.color - a.color) * t,
+        normal: a.normal + (b.normal - a.normal) * t,
+        uv: a.uv + (b.uv - a.uv) * t,
+        tangent: a.tangent + (b.tangent - a.tangent) * t,
+    };
+}
+
+/// Clip a triangle in clip space against the view frustum
+///
+/// Returns the vertices of the visible part of the triangle as a convex polygon in the same
+/// winding order as the triangle, or no vertices if the triangle is completely outside.
+///
+/// # Arguments
+///
+/// * `face` - Triangle in homogeneous clip space (before the perspective divide)
+/// * `user_plane` - Additional plane in clip space, the part of the triangle at positions
+///   `p` with `plane.dot(p) < 0` is cut off
+pub fn clip_triangle(
+    face: &Face<Vector4<f32>>,
+    user_plane: Option<Vector4<f32>>,
+) -> Vec<Vertex<Vector4<f32>>> {
+    let mut polygon = vec![face.v0, face.v1, face.v2];
+    let distance = |p: &Vector4<f32>, plane: usize| match user_plane {
+        Some(ref user_plane) if plane == NUM_PLANES => user_plane.dot(p),
+        _ => plane_distance(p, plane),
+    };
+    let planes = NUM_PLANES + user_plane.map_or(0, |_| 1);
+
+    for plane in 0..planes {
+        let input = polygon;
+        polygon = Vec::with_capacity(input.len() + 1);
+
+        for (i, current) in input.iter().enumerate() {
+            let next = &input[(i + 1) % input.len()];
+            let d_current = distance(&current.position, plane);
+            let d_next = distance(&next.position, plane);
+
+            if d_current >= 0.0 {
+                polygon.push(*current);
+            }
+            if (d_current >= 0.0) != (d_next >= 0.0) {
+                polygon.push(lerp_vertex(current, next, d_current / (d_current - d_next)));
+            }
+        }
+
+        if polygon.is_empty() {
+            break;
+        }
+    }
+
+    return polygon;
+}
+
+/// Check whether all points in clip space are outside of the same frustum plane
+///
+/// A convex shape with these points as corners (e.g. a bounding box) is then completely
+/// outside of the view frustum and can be skipped without clipping its faces. Shapes that
+/// are outside but cross several planes are not detected, they are left to the clipping.
+///
+/// # Arguments
+///
+/// * `points` - Points in homogeneous clip space (before the perspective divide)
+pub fn outside_frustum(points: &[Vector4<f32>]) -> bool {
+    return (0..NUM_PLANES).any(|plane| points.iter().all(|p| plane_distance(p, plane) < 0.0));
+}
+
+/// Check whether a point in clip space is inside all planes of the view frustum
+///
+/// # Arguments
+///
+/// * `p` - Point in homogeneous clip space (before the perspective divide)
+pub fn inside_frustum(p: &Vector4<f32>) -> bool {
+    return (0..NUM_PLANES).all(|plane| plane_distance(p, plane) >= 0.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{FloatColor, NO_MATERIAL};
+    use na::{Vector2, Vector3};
+
+    fn vertex(x: f32, y: f32, z: f32, r: f32) -> Vertex<Vector4<f32>> {
+        return Vertex {
+            position: Vector4::new(x, y, z, 1.0),
+            color: FloatColor {
+                r,
+                g: 0.0,
+                b: 0.0,
+                a: 1.0,
+            },
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            uv: Vector2::new(x, y),
+            tangent: Vector3::zeros(),
+        };
+    }
+
+    fn face(
+        v0: Vertex<Vector4<f32>>,
+        v1: Vertex<Vector4<f32>>,
+        v2: Vertex<Vector4<f32>>,
+    ) -> Face<Vector4<f32>> {
+        return Face {
+            v0,
+            v1,
+            v2,
+            material_id: NO_MATERIAL,
+        };
+    }
+
+    #[test]
+    fn test_outside_frustum_needs_one_plane_for_all_points() {
+        let left = [
+            Vector4::new(-2.0, 0.0, 0.0, 1.0),
+            Vector4::new(-3.0, 5.0, 0.0, 1.0),
+        ];
+        let crossing = [
+            Vector4::new(-2.0, 0.0, 0.0, 1.0),
+            Vector4::new(0.5, 0.0, 0.0, 1.0),
+        ];
+        // Outside of the left and the top plane, but not all of them outside of either
+        let corner = [
+            Vector4::new(-2.0, 0.0, 0.0, 1.0),
+            Vector4::new(0.0, 2.0, 0.0, 1.0),
+        ];
+
+        assert!(outside_frustum(&left));
+        assert!(!outside_frustum(&crossing));
+        assert!(!outside_frustum(&corner));
+    }
+
+    #[test]
+    fn test_clip_triangle_inside_is_unchanged() {
+        let f = face(
+            vertex(-0.5, -0.5, 0.0, 0.0),
+            vertex(0.5, -0.5, 0.0, 0.0),
+            vertex(0.0, 0.5, 0.0, 0.0),
+        );
+
+        let polygon = clip_triangle(&f, None);
+
+        assert_eq!(polygon.len(), 3);
+        assert_eq!(polygon[1].position, f.v1.position);
+    }
+
+    #[test]
+    fn test_clip_triangle_outside_is_empty() {
+        let f = face(
+            vertex(2.0, -0.5, 0.0, 0.0),
+            vertex(3.0, -0.5, 0.0, 0.0),
+            vertex(2.5, 0.5, 0.0, 0.0),
+        );
+
+        assert!(clip_triangle(&f, None).is_empty());
+    }
+
+    #[test]
+    fn test_clip_triangle_interpolates_attributes_on_plane() {
+        let f = face(
+            vertex(0.0, -0.5, 0.0, 0.0),
+            vertex(2.0, -0.5, 0.0, 1.0),
+            vertex(0.0, 0.5, 0.0, 0.0),
+        );
+
+        let polygon = clip_triangle(&f, None);
+
+        assert_eq!(polygon.len(), 4);
+        for v in polygon.iter() {
+            assert!(v.position.x <= v.position.w);
+        }
+        let cut = polygon
+            .iter()
+            .find(|v| v.position.y == -0.5 && v.position.x == 1.0)
+            .expect("No vertex on the right plane");
+        assert_eq!(cut.color.r, 0.5);
+        assert_eq!(cut.uv, Vector2::new(1.0, -0.5));
+    }
+
+    #[test]
+    fn test_clip_triangle_against_near_plane() {
+        let mut behind = vertex(0.0, 0.0, -3.0, 0.0);
+        behind.position.w = -1.0;
+        let f = face(
+            vertex(-0.5, 0.0, 0.0, 0.0),
+            vertex(0.5, 0.0, 0.0, 0.0),
+            behind,
+        );
+
+        let polygon = clip_triangle(&f, None);
+
+        assert!(polygon.len() >= 3);
+        for v in polygon.iter() {
+            assert!(v.position.w > 0.0);
+            assert!(v.position.z >= -v.position.w - 1e-6);
+        }
+    }
+}
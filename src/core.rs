@@ -7,8 +7,34 @@
 // 3) Camera to homogeneous clip space (PROJECTION matrix 4x4), w = 1
 // 4) Clipping + perspective divide (normalization) => NDC space [-1, 1]
 // 5) Viewport transform => raster space [0, W-1, 0, H-1]
+//
+// Coordinate system
+//
+// World, model and camera spaces are right-handed: x points right, y up and z toward the
+// viewer, so the camera looks down its -z axis. Positive rotation angles turn counter-clockwise
+// when looking from the positive end of the axis toward the origin. The projection flips z,
+// in normalized device coordinates smaller depth is nearer (-1 at the near plane) and the
+// depth test keeps the smallest value. Raster y grows upwards from the bottom row, while the
+// pixel data and the depth buffer store the rows from the top down.
+//
+// Determinism
+//
+// Rendering the same scene into the same buffer always writes the same bytes. The faces are
+// drawn in the order of the meshes and the faces, floats are converted to bytes by explicit
+// rounding or truncation and there are no threads, random numbers or hash map iteration in
+// the pipeline. Results agree across platforms as far as their sin, cos and tan agree, which
+// are only used for building the matrices. The golden image test in `export` checks this.
 
-use na::{Matrix3x4, Matrix4, RowVector4, Vector3, Vector4};
+use clipping::{clip_triangle, outside_frustum};
+use na::{Matrix3, Matrix4, RowVector4, Vector2, Vector3, Vector4};
+use rasterization::{FragmentInput, Uniforms};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Add, Mul, Sub};
+use std::rc::Rc;
+use texture::Texture;
 
 /// Renderable represents any model that can be drawn to a display buffer
 pub trait Renderable {
@@ -21,13 +47,34 @@ pub trait Renderable {
 #[derive(Copy, Clone)]
 pub struct Vertex<T: Copy> {
     /// Color of the vertex
-    pub color: Color,
+    pub color: FloatColor,
     /// Position of the vertex
     pub position: T,
     /// Normal vector of the vertex
     pub normal: Vector3<f32>,
+    /// Texture coordinates of the vertex
+    pub uv: Vector2<f32>,
+    /// Unit vector along the direction of growing u texture coordinate, perpendicular to
+    /// the normal, for normal mapping. Zero when not computed, see `Mesh::compute_tangents`
+    pub tangent: Vector3<f32>,
+}
+
+impl Vertex<Vector4<f32>> {
+    /// Drop the w coordinate of the position, keeping the other attributes
+    ///
+    /// The position is not divided by w, use this for points with w = 1 (e.g. world space).
+    pub fn to_3d(&self) -> Vertex<Vector3<f32>> {
+        return Vertex {
+            position: self.position.xyz(),
+            color: self.color,
+            normal: self.normal,
+            uv: self.uv,
+            tangent: self.tangent,
+        };
+    }
 }
 
+#[derive(Copy, Clone)]
 pub struct Face<T: Copy> {
     /// Vertex of a triangle
     pub v0: Vertex<T>,
@@ -35,43 +82,237 @@ pub struct Face<T: Copy> {
     pub v1: Vertex<T>,
     /// Vertex of a triangle
     pub v2: Vertex<T>,
+    /// Index of the surface material in the materials of the mesh, the vertex colors are used
+    /// when there is no material with the index (such as `NO_MATERIAL`)
+    pub material_id: usize,
 }
 
 impl Face<Vector4<f32>> {
+    /// Drop the w coordinates of the vertex positions, see `Vertex::to_3d`
+    pub fn to_3d(&self) -> Face<Vector3<f32>> {
+        return Face {
+            v0: self.v0.to_3d(),
+            v1: self.v1.to_3d(),
+            v2: self.v2.to_3d(),
+            material_id: self.material_id,
+        };
+    }
+
     /// Perform a linear transformation to all vertices of the triangle
+    ///
+    /// The tangents are transformed like the positions and then made perpendicular to the
+    /// transformed normals again.
     pub fn transform(&self, m: Matrix4<f32>) -> Face<Vector4<f32>> {
-        // Normal vectors cannot simply be transformed with the matrix m like
-        // vertex coordinates. Instead the scales must be inverted. So when we
-        // scale the vertices by factor x in any axis, we must scale the normals
-        // by 1/x. This is achieved by transforming the normals using the
-        // inverse transpose of matrix m
-        let m_normal = m
+        let m_normal = normal_matrix(&m);
+        let m_tangent = m
             .fixed_slice::<nalgebra::U3, nalgebra::U3>(0, 0)
-            .try_inverse()
-            .expect("Could not invert matrix")
-            .transpose();
+            .into_owned();
+        let transform = |v: &Vertex<Vector4<f32>>| {
+            let normal = m_normal * v.normal;
+            return Vertex {
+                position: m * v.position,
+                color: v.color,
+                normal,
+                uv: v.uv,
+                tangent: orthonormal_tangent(m_tangent * v.tangent, normal),
+            };
+        };
 
         Face {
-            v0: Vertex {
-                position: m * self.v0.position,
-                color: self.v0.color,
-                normal: m_normal * self.v0.normal,
-            },
-            v1: Vertex {
-                position: m * self.v1.position,
-                color: self.v1.color,
-                normal: m_normal * self.v1.normal,
-            },
-            v2: Vertex {
-                position: m * self.v2.position,
-                color: self.v2.color,
-                normal: m_normal * self.v2.normal,
-            },
+            v0: transform(&self.v0),
+            v1: transform(&self.v1),
+            v2: transform(&self.v2),
+            material_id: self.material_id,
+        }
+    }
+}
+
+/// Line segment between two points, drawn with the same transforms as the faces
+#[derive(Copy, Clone)]
+pub struct Line3D {
+    /// Start point in homogeneous coordinates
+    pub a: Vector4<f32>,
+    /// End point in homogeneous coordinates
+    pub b: Vector4<f32>,
+    /// Color of the line
+    pub color: Color,
+}
+
+impl Line3D {
+    /// Perform a linear transformation to both end points of the line
+    pub fn transform(&self, m: Matrix4<f32>) -> Line3D {
+        return Line3D {
+            a: m * self.a,
+            b: m * self.b,
+            color: self.color,
+        };
+    }
+}
+
+/// Matrix that transforms the normal vectors of vertices transformed with a matrix
+pub(crate) fn normal_matrix(m: &Matrix4<f32>) -> Matrix3<f32> {
+    // Normal vectors cannot simply be transformed with the matrix m like
+    // vertex coordinates. Instead the scales must be inverted. So when we
+    // scale the vertices by factor x in any axis, we must scale the normals
+    // by 1/x. This is achieved by transforming the normals using the
+    // inverse transpose of matrix m
+    return m
+        .fixed_slice::<nalgebra::U3, nalgebra::U3>(0, 0)
+        .try_inverse()
+        .expect("Could not invert matrix")
+        .transpose();
+}
+
+/// Make a tangent a unit vector perpendicular to a normal (Gram-Schmidt)
+///
+/// Returns zero for a zero tangent or a tangent parallel to the normal.
+pub(crate) fn orthonormal_tangent(tangent: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+    let n = normal
+        .try_normalize(f32::EPSILON)
+        .unwrap_or_else(Vector3::zeros);
+    return (tangent - n * n.dot(&tangent))
+        .try_normalize(f32::EPSILON)
+        .unwrap_or_else(Vector3::zeros);
+}
+
+/// Compute the geometric normal of a triangle from the positions of its vertices
+///
+/// # Arguments
+///
+/// * `p0`, `p1`, `p2` - Positions of the triangle vertices
+/// * `winding` - Vertex order of the front side of the triangle
+pub(crate) fn triangle_normal(
+    p0: Vector3<f32>,
+    p1: Vector3<f32>,
+    p2: Vector3<f32>,
+    winding: Winding,
+) -> Vector3<f32> {
+    let n = (p1 - p0).cross(&(p2 - p0));
+    return match winding {
+        Winding::CounterClockwise => n,
+        Winding::Clockwise => -n,
+    };
+}
+
+/// Compute the lit color of a vertex
+///
+/// The specular highlight assumes that the light vector and the view vector are the same,
+/// which holds for the default light at the eye.
+///
+/// # Arguments
+///
+/// * `color` - Vertex color, used when there is no material
+/// * `material` - Material of the face the vertex belongs to
+/// * `brightness` - Dot product of the vertex normal and the light vector
+fn shade(color: FloatColor, material: Option<&Material>, brightness: f32) -> FloatColor {
+    let m = match material {
+        Some(m) => m,
+        None => {
+            return FloatColor {
+                a: color.a,
+                ..color * brightness
+            };
         }
+    };
+
+    // When the light vector equals the view vector, the cosine of the angle between the
+    // reflected light and the view vector is cos(2a) = 2cos^2(a) - 1
+    let diffuse = brightness.max(0.0);
+    let specular = if brightness > 0.0 {
+        (2.0 * brightness * brightness - 1.0)
+            .max(0.0)
+            .powf(m.shininess)
+    } else {
+        0.0
+    };
+    let lit = FloatColor::from(m.ambient)
+        + diffuse * FloatColor::from(m.diffuse)
+        + specular * FloatColor::from(m.specular);
+
+    return FloatColor {
+        a: m.diffuse.a as f32 / 255.0,
+        ..lit
+    };
+}
+
+/// Opaque color of a face in `RenderMode::FaceIds`
+///
+/// The index is hashed so that neighbouring faces, which usually have consecutive indices,
+/// get clearly different colors.
+pub(crate) fn face_id_color(index: usize) -> FloatColor {
+    // Integer finalizer of MurmurHash3, every input bit affects every output bit
+    let mut h = index as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    let channel = |shift: u32| ((h >> shift) & 0xff) as f32 / 255.0;
+    return FloatColor {
+        r: channel(0),
+        g: channel(8),
+        b: channel(16),
+        a: 1.0,
+    };
+}
+
+/// Color whose bytes in RGBA order are the little-endian bytes of an ID
+///
+/// The color is exact when written to a buffer with the default tone mapping and color
+/// space, because interpolating the same color at all vertices gives the same color.
+pub(crate) fn id_color(id: u32) -> FloatColor {
+    let [r, g, b, a] = id.to_le_bytes();
+    return FloatColor::from(Color { r, g, b, a });
+}
+
+/// Distance from the eye to the near clipping plane
+pub(crate) const NEAR_PLANE: f32 = 0.1;
+/// Distance from the eye to the far clipping plane
+///
+/// Geometry beyond it is clipped away, so it has to cover the scenes of the test app, whose
+/// model sways 6 units from the camera. Set `RenderState::clip_planes` to a tighter range
+/// for more depth precision.
+pub(crate) const FAR_PLANE: f32 = 100.0;
+/// Horizontal angle of view in degrees
+pub(crate) const ANGLE_OF_VIEW: f32 = 78.0;
+
+/// Convention of the camera space and the clip space of a projection matrix
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Handedness {
+    /// OpenGL style: the camera looks towards -z and the clip space z runs from -w at the
+    /// near plane to w at the far plane. The renderer clips and depth tests in this space
+    RightHanded,
+    /// Direct3D style: the camera looks towards +z and the clip space z runs from 0 at the
+    /// near plane to w at the far plane
+    LeftHanded,
+}
+
+impl Handedness {
+    /// Distance in front of the camera of a point with the camera space z coordinate
+    pub(crate) fn view_depth(self, z: f32) -> f32 {
+        return match self {
+            Handedness::RightHanded => -z,
+            Handedness::LeftHanded => z,
+        };
     }
 }
 
-fn build_perspective_matrix(n: f32, f: f32, angle_of_view: f32, aspect_ratio: f32) -> Matrix4<f32> {
+/// Build a perspective projection matrix from camera space to clip space
+///
+/// # Arguments
+///
+/// * `n` - Distance from the eye to the near clipping plane
+/// * `f` - Distance from the eye to the far clipping plane
+/// * `angle_of_view` - Horizontal angle of view in degrees
+/// * `aspect_ratio` - Width divided by the height of the view
+/// * `handedness` - Convention of the camera space and the clip space
+pub fn build_perspective_matrix(
+    n: f32,
+    f: f32,
+    angle_of_view: f32,
+    aspect_ratio: f32,
+    handedness: Handedness,
+) -> Matrix4<f32> {
     let deg_to_rad = ::std::f32::consts::PI / 180.0;
     let size = n * (deg_to_rad * angle_of_view / 2.0).tan();
     let l = -size;
@@ -79,21 +320,35 @@ fn build_perspective_matrix(n: f32, f: f32, angle_of_view: f32, aspect_ratio: f3
     let b = -size / aspect_ratio;
     let t = size / aspect_ratio;
 
-    return Matrix4::from_rows(&[
-        RowVector4::new(2.0 * n / (r - l), 0.0, (r + l) / (r - l), 0.0),
-        RowVector4::new(0.0, 2.0 * n / (t - b), (t + b) / (t - b), 0.0),
-        RowVector4::new(0.0, 0.0, -(f + n) / (f - n), -(2.0 * f * n) / (f - n)),
-        RowVector4::new(0.0, 0.0, -1.0, 0.0),
-    ]);
+    return match handedness {
+        Handedness::RightHanded => Matrix4::from_rows(&[
+            RowVector4::new(2.0 * n / (r - l), 0.0, (r + l) / (r - l), 0.0),
+            RowVector4::new(0.0, 2.0 * n / (t - b), (t + b) / (t - b), 0.0),
+            RowVector4::new(0.0, 0.0, -(f + n) / (f - n), -(2.0 * f * n) / (f - n)),
+            RowVector4::new(0.0, 0.0, -1.0, 0.0),
+        ]),
+        // Depth along +z is w, so the signs of the terms that multiply z flip
+        Handedness::LeftHanded => Matrix4::from_rows(&[
+            RowVector4::new(2.0 * n / (r - l), 0.0, -(r + l) / (r - l), 0.0),
+            RowVector4::new(0.0, 2.0 * n / (t - b), -(t + b) / (t - b), 0.0),
+            RowVector4::new(0.0, 0.0, f / (f - n), -(f * n) / (f - n)),
+            RowVector4::new(0.0, 0.0, 1.0, 0.0),
+        ]),
+    };
 }
 
-fn build_view_matrix(eye: Vector3<f32>, lookat: Vector3<f32>, up: Vector3<f32>) -> Matrix4<f32> {
-    // Rotate so that the line of sight from the eye position to the target maps to the z axis.
+pub(crate) fn build_view_matrix(
+    eye: Vector3<f32>,
+    lookat: Vector3<f32>,
+    up: Vector3<f32>,
+) -> Matrix4<f32> {
+    // Rotate so that the line of sight from the eye position to the target maps to the
+    // negative z axis (the camera looks towards -z like the perspective matrix expects).
     // Camera up direction maps to y axis. x- axis is defined from the other two by cross
     // product
 
     // Unit vectors in camera space
-    let z = (lookat - eye).normalize();
+    let z = (eye - lookat).normalize();
     let x = (up.cross(&z)).normalize();
     let y = (z.cross(&x)).normalize();
 
@@ -123,7 +378,7 @@ fn build_view_matrix(eye: Vector3<f32>, lookat: Vector3<f32>, up: Vector3<f32>)
 }
 
 /// Color in RGBA8888 format
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Color {
     /// Red component intensity
     pub r: u8,
@@ -135,299 +390,4398 @@ pub struct Color {
     pub a: u8,
 }
 
+/// Error from parsing a color from a hex string
+#[derive(Debug, PartialEq)]
+pub enum ParseColorError {
+    /// The string does not have 6 or 8 hex digits, the number of digits is included
+    InvalidLength(usize),
+    /// The string has a character that is not a hex digit
+    InvalidDigit(char),
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match *self {
+            ParseColorError::InvalidLength(n) => {
+                write!(f, "expected 6 or 8 hex digits, found {}", n)
+            }
+            ParseColorError::InvalidDigit(c) => write!(f, "invalid hex digit {:?}", c),
+        };
+    }
+}
+
+impl Error for ParseColorError {}
+
 impl Color {
+    /// Transparent black, the default background of a buffer
+    pub const TRANSPARENT: Color = Color::from_hex(0x0000_0000);
+    /// Opaque black
+    pub const BLACK: Color = Color::from_hex(0x0000_00FF);
+    /// Opaque white
+    pub const WHITE: Color = Color::from_hex(0xFFFF_FFFF);
+    /// Opaque middle gray
+    pub const GRAY: Color = Color::from_hex(0x8080_80FF);
+    /// Opaque red
+    pub const RED: Color = Color::from_hex(0xFF00_00FF);
+    /// Opaque green
+    pub const GREEN: Color = Color::from_hex(0x00FF_00FF);
+    /// Opaque blue
+    pub const BLUE: Color = Color::from_hex(0x0000_FFFF);
+    /// Opaque yellow
+    pub const YELLOW: Color = Color::from_hex(0xFFFF_00FF);
+    /// Opaque cyan
+    pub const CYAN: Color = Color::from_hex(0x00FF_FFFF);
+    /// Opaque magenta
+    pub const MAGENTA: Color = Color::from_hex(0xFF00_FFFF);
+
+    /// Color from a number with the channels in the order red, green, blue, alpha from the
+    /// highest byte, e.g. `0xFF8800FF` for opaque orange
+    pub const fn from_hex(rgba: u32) -> Color {
+        return Color {
+            r: (rgba >> 24) as u8,
+            g: (rgba >> 16) as u8,
+            b: (rgba >> 8) as u8,
+            a: rgba as u8,
+        };
+    }
+
+    /// Parse a color from hex digits in the order red, green, blue and optionally alpha
+    ///
+    /// The digits may start with `#`, e.g. `"#ff8800"` or `"FF880080"`. The color is opaque
+    /// when the alpha digits are left out.
+    pub fn from_hex_str(s: &str) -> Result<Color, ParseColorError> {
+        let digits = s.strip_prefix('#').unwrap_or(s);
+        if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(ParseColorError::InvalidDigit(c));
+        }
+        let value = u32::from_str_radix(digits, 16);
+        return match (digits.len(), value) {
+            (6, Ok(rgb)) => Ok(Color::from_hex(rgb << 8 | 0xFF)),
+            (8, Ok(rgba)) => Ok(Color::from_hex(rgba)),
+            (n, _) => Err(ParseColorError::InvalidLength(n)),
+        };
+    }
+
     pub fn to_u32(&self) -> u32 {
         return ((self.a as u32) << 24) | ((self.b as u32) << 16) | ((self.g as u32) << 8) | (self.r as u32);
     }
 }
 
-/// Display buffer defines a memory area that is used for rendering a raw image
-pub struct DisplayBuffer {
-    /// Width of the display area in pixels
-    pub width: usize,
-    /// Height of the display area in pixels
-    pub height: usize,
-    /// Bytes per pixel
-    pub bpp: usize,
-    /// Contents of the buffer (pixel data)
-    pub data: Box<[u8]>,
-    /// Z/depth buffer
-    pub z_buffer: Box<[f32]>,
+/// Channel-wise sum, saturating at full intensity
+impl Add for Color {
+    type Output = Color;
+
+    fn add(self, other: Color) -> Color {
+        return Color {
+            r: self.r.saturating_add(other.r),
+            g: self.g.saturating_add(other.g),
+            b: self.b.saturating_add(other.b),
+            a: self.a.saturating_add(other.a),
+        };
+    }
 }
 
-impl DisplayBuffer {
-    pub fn new(width: usize, height: usize, bpp: usize) -> DisplayBuffer {
-        return DisplayBuffer {
-            height: height,
-            width: width,
-            bpp: bpp,
-            data: vec![0; width * height * bpp].into_boxed_slice(),
-            z_buffer: vec![std::f32::MIN; width * height].into_boxed_slice(),
+/// Channel-wise difference, saturating at zero intensity
+impl Sub for Color {
+    type Output = Color;
+
+    fn sub(self, other: Color) -> Color {
+        return Color {
+            r: self.r.saturating_sub(other.r),
+            g: self.g.saturating_sub(other.g),
+            b: self.b.saturating_sub(other.b),
+            a: self.a.saturating_sub(other.a),
         };
     }
+}
 
-    /// return the size of the buffer in bytes
-    pub fn size(&self) -> usize {
-        return self.height * self.width * self.bpp;
+/// Scale all channels (including alpha) by a factor, rounding to the nearest intensity
+impl Mul<f32> for Color {
+    type Output = Color;
+
+    fn mul(self, k: f32) -> Color {
+        return Color {
+            r: (self.r as f32 * k).round() as u8,
+            g: (self.g as f32 * k).round() as u8,
+            b: (self.b as f32 * k).round() as u8,
+            a: (self.a as f32 * k).round() as u8,
+        };
     }
+}
 
-    /// return the number of pixels
-    pub fn num_pixels(&self) -> usize {
-        return self.height * self.width;
+impl Mul<Color> for f32 {
+    type Output = Color;
+
+    fn mul(self, color: Color) -> Color {
+        return color * self;
     }
+}
 
-    /// Reset the contents of the buffer so that all pixels are black
-    pub fn clear(&mut self) {
-        self.data = vec![0; self.width * self.height * self.bpp].into_boxed_slice();
-        // this takes a lot of time when the initialization value is not 0.0
-        self.z_buffer = vec![std::f32::MIN; self.width * self.height].into_boxed_slice();
+/// Color with floating point channels used for shading, 1.0 corresponds to full intensity
+///
+/// Channels are not limited to [0, 1] so that light can accumulate beyond full intensity
+/// before being tone mapped and converted to a `Color`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FloatColor {
+    /// Red component intensity
+    pub r: f32,
+    /// Green component intensity
+    pub g: f32,
+    /// Blue component intensity
+    pub b: f32,
+    /// Alpha value (0.0 - fully transparent, 1.0 - fully opaque)
+    pub a: f32,
+}
+
+impl FloatColor {
+    /// Opaque black
+    pub const BLACK: FloatColor = FloatColor {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    /// Opaque white
+    pub const WHITE: FloatColor = FloatColor {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+        a: 1.0,
+    };
+
+    /// Convert to a `Color`, clamping each channel to [0, 1] and rounding to the nearest byte
+    pub fn into_color(self) -> Color {
+        let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        return Color {
+            r: channel(self.r),
+            g: channel(self.g),
+            b: channel(self.b),
+            a: channel(self.a),
+        };
     }
+}
 
-    /// Set a single pixel to a desired color
-    ///
-    /// # Arguments
-    ///
-    /// * `x` - X coordinate in pixels, value 0 corresponds to left edge
-    /// * `y` - Y coordinate in pixels, value 0 correspoonds to bottom edge
-    /// * 'color' - Color of the pixel
-    pub fn set_pixel(&mut self, x: usize, y: usize, z: f32, color: Color) {
-        assert!(x < self.width);
-        assert!(y < self.height);
-        let index: usize = (self.height - y - 1) * self.width + x;
+impl From<Color> for FloatColor {
+    fn from(color: Color) -> FloatColor {
+        return FloatColor {
+            r: color.r as f32 / 255.0,
+            g: color.g as f32 / 255.0,
+            b: color.b as f32 / 255.0,
+            a: color.a as f32 / 255.0,
+        };
+    }
+}
 
-        if index < self.num_pixels() {
-            if self.z_buffer[index] < z {
-                self.z_buffer[index] = z;
-                self.data[index * self.bpp] = color.r;
-                self.data[index * self.bpp + 1] = color.g;
-                self.data[index * self.bpp + 2] = color.b;
-                self.data[index * self.bpp + 3] = color.a;
-            }
-        }
+impl Add for FloatColor {
+    type Output = FloatColor;
+
+    fn add(self, other: FloatColor) -> FloatColor {
+        return FloatColor {
+            r: self.r + other.r,
+            g: self.g + other.g,
+            b: self.b + other.b,
+            a: self.a + other.a,
+        };
     }
 }
 
-/// A mesh is a collection of triangles that form a 3D surface
-pub struct Mesh {
-    /// World position of the center of the mesh
-    pub position: Vector4<f32>,
-    /// Rotation of the mesh around all 3 axis vectors
-    pub angle: Vector3<f32>,
-    /// Triangle faces that make up the mesh surface
-    pub faces: Vec<Face<Vector4<f32>>>,
+impl Sub for FloatColor {
+    type Output = FloatColor;
+
+    fn sub(self, other: FloatColor) -> FloatColor {
+        return FloatColor {
+            r: self.r - other.r,
+            g: self.g - other.g,
+            b: self.b - other.b,
+            a: self.a - other.a,
+        };
+    }
 }
 
-impl Mesh {
-    pub fn new() -> Mesh {
-        return Mesh {
-            position: Vector4::new(0.0, 0.0, 0.0, 1.0),
-            angle: Vector3::new(0.0, 0.0, 0.0),
-            faces: Vec::new(),
+/// Scale all channels (including alpha) by a factor
+impl Mul<f32> for FloatColor {
+    type Output = FloatColor;
+
+    fn mul(self, k: f32) -> FloatColor {
+        return FloatColor {
+            r: self.r * k,
+            g: self.g * k,
+            b: self.b * k,
+            a: self.a * k,
         };
     }
+}
 
-    /// Render a mesh into a display buffer
-    ///
-    /// # Arguments
-    ///
-    /// * `eye` - Position of the camera eye
-    /// * 'lookat' - Focus point of the eye
-    /// * `buffer` - Display buffer (render target)
-    pub fn render(
-        self: &Mesh,
-        eye: Vector3<f32>,
-        lookat: Vector3<f32>,
-        buffer: &mut DisplayBuffer,
-    ) {
-        let m_rot_x = Matrix4::from_rows(&[
-            RowVector4::new(1.0, 0.0, 0.0, 0.0),
-            RowVector4::new(0.0, self.angle.x.cos(), self.angle.x.sin(), 0.0),
-            RowVector4::new(0.0, -self.angle.x.sin(), self.angle.x.cos(), 0.0),
-            RowVector4::new(0.0, 0.0, 0.0, 1.0),
-        ]);
-        let m_rot_y = Matrix4::from_rows(&[
-            RowVector4::new(self.angle.y.cos(), 0.0, -self.angle.y.sin(), 0.0),
-            RowVector4::new(0.0, 1.0, 0.0, 0.0),
-            RowVector4::new(self.angle.y.sin(), 0.0, self.angle.y.cos(), 0.0),
-            RowVector4::new(0.0, 0.0, 0.0, 1.0),
-        ]);
-        let m_rot_z = Matrix4::from_rows(&[
-            RowVector4::new(self.angle.z.cos(), -self.angle.z.sin(), 0.0, 0.0),
-            RowVector4::new(self.angle.z.sin(), self.angle.z.cos(), 0.0, 0.0),
-            RowVector4::new(0.0, 0.0, 1.0, 0.0),
-            RowVector4::new(0.0, 0.0, 0.0, 1.0),
-        ]);
+impl Mul<FloatColor> for f32 {
+    type Output = FloatColor;
 
-        let m_trans = Matrix4::from_rows(&[
-            RowVector4::new(1.0, 0.0, 0.0, self.position.x),
-            RowVector4::new(0.0, 1.0, 0.0, self.position.y),
-            RowVector4::new(0.0, 0.0, 1.0, self.position.z),
-            RowVector4::new(0.0, 0.0, 0.0, 1.0),
-        ]);
+    fn mul(self, color: FloatColor) -> FloatColor {
+        return color * self;
+    }
+}
 
-        let model = m_trans * m_rot_z * m_rot_y * m_rot_x;
-        let aspect_ratio = (buffer.width as f32) / (buffer.height as f32);
-        let view: Matrix4<f32> = build_view_matrix(eye, lookat, Vector3::new(0.0, 1.0, 0.0));
-        let projection: Matrix4<f32> = build_perspective_matrix(0.1, 5.0, 78.0, aspect_ratio);
-
-        for t in self.faces.iter() {
-            let face_world = t.transform(model);
-            let reduce_dim = Matrix3x4::from_rows(&[
-                RowVector4::new(1.0, 0.0, 0.0, 0.0),
-                RowVector4::new(0.0, 1.0, 0.0, 0.0),
-                RowVector4::new(0.0, 0.0, 1.0, 0.0),
-            ]);
-            let triangle_world_3d = Face {
-                v0: Vertex {
-                    position: reduce_dim * face_world.v0.position,
-                    color: face_world.v0.color,
-                    normal: face_world.v0.normal,
-                },
-                v1: Vertex {
-                    position: reduce_dim * face_world.v1.position,
-                    color: face_world.v1.color,
-                    normal: face_world.v1.normal,
-                },
-                v2: Vertex {
-                    position: reduce_dim * face_world.v2.position,
-                    color: face_world.v2.color,
-                    normal: face_world.v2.normal,
-                },
-            };
+/// Weight of a color in a blend equation
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BlendFactor {
+    /// 0 for all channels
+    Zero,
+    /// 1 for all channels
+    One,
+    /// Alpha of the source color
+    SrcAlpha,
+    /// 1 - alpha of the source color
+    OneMinusSrcAlpha,
+    /// Alpha of the destination color
+    DstAlpha,
+    /// 1 - alpha of the destination color
+    OneMinusDstAlpha,
+    /// Each channel weighted by the same channel of the source color
+    SrcColor,
+    /// Each channel weighted by the same channel of the destination color
+    DstColor,
+}
 
-            // Light vector is a unit vector from the mesh to the light source.
-            let brightness_v0 = (eye - triangle_world_3d.v0.position)
-                .normalize()
-                .dot(&triangle_world_3d.v0.normal);
-            let brightness_v1 = (eye - triangle_world_3d.v1.position)
-                .normalize()
-                .dot(&triangle_world_3d.v1.normal);
-            let brightness_v2 = (eye - triangle_world_3d.v2.position)
-                .normalize()
-                .dot(&triangle_world_3d.v2.normal);
-            assert!(brightness_v0 <= 1.0);
-            assert!(brightness_v1 <= 1.0);
-            assert!(brightness_v2 <= 1.0);
-
-            // If the dot product is positive, the light is hitting the outer
-            // surface of the mesh. In this case the value of the dot product
-            // determines the intensity of the reflected light. If the dot
-            // product is negative, the light is hitting the inner surface of
-            // the mesh and we can simply ignore the triangle (not render it)
-            if brightness_v0 > 0.0 || brightness_v1 > 0.0 || brightness_v2 > 0.0 {
-                // Step 2: World to camera space
-                let triangle_view = face_world.transform(view);
-
-                // Step 3: Camera to clip space
-                let triangle_camera = triangle_view.transform(projection);
-
-                // Step 4.2: PERSPECTIVE DIVIDE (normalization)
-                // Perspective division, far away points moved closer to origin
-                // To screen space. All visible points between [-1, 1].
-                let t_ndc = Face {
-                    v0: Vertex {
-                        position: Vector3::new(
-                            triangle_camera.v0.position.x / triangle_camera.v0.position.w,
-                            triangle_camera.v0.position.y / triangle_camera.v0.position.w,
-                            triangle_camera.v0.position.z,
-                        ),
-                        color: Color {
-                            r: (triangle_camera.v0.color.r as f32 * brightness_v0) as u8,
-                            g: (triangle_camera.v0.color.g as f32 * brightness_v0) as u8,
-                            b: (triangle_camera.v0.color.b as f32 * brightness_v0) as u8,
-                            a: (triangle_camera.v0.color.a as f32 * brightness_v0) as u8,
-                        },
-                        normal: triangle_camera.v0.normal,
-                    },
-                    v1: Vertex {
-                        position: Vector3::new(
-                            triangle_camera.v1.position.x / triangle_camera.v1.position.w,
-                            triangle_camera.v1.position.y / triangle_camera.v1.position.w,
-                            triangle_camera.v1.position.z,
-                        ),
-                        color: Color {
-                            r: (triangle_camera.v1.color.r as f32 * brightness_v1) as u8,
-                            g: (triangle_camera.v1.color.g as f32 * brightness_v1) as u8,
-                            b: (triangle_camera.v1.color.b as f32 * brightness_v1) as u8,
-                            a: (triangle_camera.v1.color.a as f32 * brightness_v1) as u8,
-                        },
-                        normal: triangle_camera.v1.normal,
-                    },
-                    v2: Vertex {
-                        position: Vector3::new(
-                            triangle_camera.v2.position.x / triangle_camera.v2.position.w,
-                            triangle_camera.v2.position.y / triangle_camera.v2.position.w,
-                            triangle_camera.v2.position.z,
-                        ),
-                        color: Color {
-                            r: (triangle_camera.v2.color.r as f32 * brightness_v2) as u8,
-                            g: (triangle_camera.v2.color.g as f32 * brightness_v2) as u8,
-                            b: (triangle_camera.v2.color.b as f32 * brightness_v2) as u8,
-                            a: (triangle_camera.v2.color.a as f32 * brightness_v2) as u8,
-                        },
-                        normal: triangle_camera.v2.normal,
-                    },
-                };
+impl BlendFactor {
+    /// Channel weights for a source and a destination color
+    fn weights(&self, src: FloatColor, dst: FloatColor) -> FloatColor {
+        let all = |k: f32| FloatColor {
+            r: k,
+            g: k,
+            b: k,
+            a: k,
+        };
+        return match *self {
+            BlendFactor::Zero => all(0.0),
+            BlendFactor::One => all(1.0),
+            BlendFactor::SrcAlpha => all(src.a),
+            BlendFactor::OneMinusSrcAlpha => all(1.0 - src.a),
+            BlendFactor::DstAlpha => all(dst.a),
+            BlendFactor::OneMinusDstAlpha => all(1.0 - dst.a),
+            BlendFactor::SrcColor => src,
+            BlendFactor::DstColor => dst,
+        };
+    }
+}
 
-                // Step 5: Viewport transform
-                let t_viewport = Face {
-                    v0: Vertex {
-                        position: Vector3::new(
-                            (1.0 + t_ndc.v0.position.x) * 0.5 * buffer.width as f32,
-                            (1.0 + t_ndc.v0.position.y) * 0.5 * buffer.height as f32,
-                            t_ndc.v0.position.z,
-                        ),
-                        color: t_ndc.v0.color,
-                        normal: t_ndc.v0.normal,
-                    },
-                    v1: Vertex {
-                        position: Vector3::new(
-                            (1.0 + t_ndc.v1.position.x) * 0.5 * buffer.width as f32,
-                            (1.0 + t_ndc.v1.position.y) * 0.5 * buffer.height as f32,
-                            t_ndc.v1.position.z,
-                        ),
-                        color: t_ndc.v1.color,
-                        normal: t_ndc.v1.normal,
-                    },
-                    v2: Vertex {
-                        position: Vector3::new(
-                            (1.0 + t_ndc.v2.position.x) * 0.5 * buffer.width as f32,
-                            (1.0 + t_ndc.v2.position.y) * 0.5 * buffer.height as f32,
-                            t_ndc.v2.position.z,
-                        ),
-                        color: t_ndc.v2.color,
-                        normal: t_ndc.v2.normal,
-                    },
-                };
+/// How the weighted source and destination colors are combined
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BlendOp {
+    /// Source + destination
+    Add,
+    /// Source - destination
+    Subtract,
+    /// Destination - source
+    ReverseSubtract,
+}
 
-                t_viewport.render(buffer);
-            }
+/// Blend equation op(src * src_factor, dst * dst_factor) for writing a color over another
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Blend {
+    /// Weight of the color being written
+    pub src_factor: BlendFactor,
+    /// Weight of the color already in the buffer
+    pub dst_factor: BlendFactor,
+    /// Combination of the weighted colors
+    pub op: BlendOp,
+}
+
+impl Blend {
+    /// Source over destination with the source alpha as the opacity
+    pub const ALPHA: Blend = Blend {
+        src_factor: BlendFactor::SrcAlpha,
+        dst_factor: BlendFactor::OneMinusSrcAlpha,
+        op: BlendOp::Add,
+    };
+    /// Source over destination for premultiplied colors, src + dst * (1 - src alpha), see
+    /// `AlphaMode::Premultiplied`
+    pub const PREMULTIPLIED_ALPHA: Blend = Blend {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::OneMinusSrcAlpha,
+        op: BlendOp::Add,
+    };
+    /// Sum of the colors, e.g. for glowing particles
+    pub const ADDITIVE: Blend = Blend {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        op: BlendOp::Add,
+    };
+    /// Product of the colors, e.g. for darkening with a tint
+    pub const MULTIPLY: Blend = Blend {
+        src_factor: BlendFactor::DstColor,
+        dst_factor: BlendFactor::Zero,
+        op: BlendOp::Add,
+    };
+}
+
+impl Blend {
+    /// Whether a fully transparent color (alpha 0) leaves the destination unchanged
+    pub fn ignores_transparent(&self) -> bool {
+        let src_zero = matches!(self.src_factor, BlendFactor::Zero | BlendFactor::SrcAlpha);
+        let dst_kept = matches!(
+            self.dst_factor,
+            BlendFactor::One | BlendFactor::OneMinusSrcAlpha
+        );
+        return src_zero && dst_kept && self.op != BlendOp::Subtract;
+    }
+}
+
+/// How the alpha channel relates to the color channels of the pixels
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AlphaMode {
+    /// The color channels are independent of the alpha channel
+    Straight,
+    /// The color channels are multiplied by the alpha channel. The colors written are
+    /// premultiplied before blending, `Blend::PREMULTIPLIED_ALPHA` then composites them
+    /// without the dark fringes that straight alpha leaves around partly covered edges
+    Premultiplied,
+}
+
+/// Comparison of the stencil value of a pixel with the reference value of a stencil test
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StencilCompare {
+    /// Every pixel passes
+    Always,
+    /// No pixel passes
+    Never,
+    /// Pixels whose stencil value equals the reference pass
+    Equal,
+    /// Pixels whose stencil value differs from the reference pass
+    NotEqual,
+}
+
+/// Change of the stencil value of a pixel that passes the stencil and the depth test
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StencilOp {
+    /// Leave the value as is
+    Keep,
+    /// Set the value to 0
+    Zero,
+    /// Set the value to the reference value
+    Replace,
+    /// Add 1, values stay at 255
+    Increment,
+    /// Subtract 1, values stay at 0
+    Decrement,
+}
+
+/// Stencil test of the pixel writes, e.g. to restrict drawing to a mask drawn before
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StencilTest {
+    /// Pixels that fail the comparison with the reference value are discarded
+    pub compare: StencilCompare,
+    /// Reference value of the comparison and of `StencilOp::Replace`
+    pub reference: u8,
+    /// Change of the stencil value of the pixels that pass the stencil and the depth test
+    pub pass: StencilOp,
+    /// Write only the stencil values, the colors and the depth of the pixels stay unchanged.
+    /// Used for drawing a mask
+    pub mask_only: bool,
+}
+
+impl StencilTest {
+    /// Whether a pixel with a stencil value passes the test
+    fn passes(&self, value: u8) -> bool {
+        return match self.compare {
+            StencilCompare::Always => true,
+            StencilCompare::Never => false,
+            StencilCompare::Equal => value == self.reference,
+            StencilCompare::NotEqual => value != self.reference,
+        };
+    }
+
+    /// Stencil value of a pixel after it has passed the stencil and the depth test
+    fn apply(&self, value: u8) -> u8 {
+        return match self.pass {
+            StencilOp::Keep => value,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => self.reference,
+            StencilOp::Increment => value.saturating_add(1),
+            StencilOp::Decrement => value.saturating_sub(1),
+        };
+    }
+}
+
+impl Color {
+    /// Color with the red, green and blue channels multiplied by the alpha channel
+    pub fn premultiplied(self) -> Color {
+        let channel = |c: u8| ((c as u32 * self.a as u32 + 127) / 255) as u8;
+        return Color {
+            r: channel(self.r),
+            g: channel(self.g),
+            b: channel(self.b),
+            a: self.a,
+        };
+    }
+
+    /// Combine a color written over another color with a blend equation
+    ///
+    /// The equation is evaluated for all channels including alpha with the channels in
+    /// [0, 1], the result is clamped to [0, 1].
+    ///
+    /// # Arguments
+    ///
+    /// * `src` - Color being written
+    /// * `dst` - Color already in the buffer
+    /// * `src_factor` - Weight of the source color
+    /// * `dst_factor` - Weight of the destination color
+    /// * `op` - Combination of the weighted colors
+    pub fn blend(
+        src: Color,
+        dst: Color,
+        src_factor: BlendFactor,
+        dst_factor: BlendFactor,
+        op: BlendOp,
+    ) -> Color {
+        let (s, d) = (FloatColor::from(src), FloatColor::from(dst));
+        let ws = src_factor.weights(s, d);
+        let wd = dst_factor.weights(s, d);
+        let channel = |s: f32, ws: f32, d: f32, wd: f32| match op {
+            BlendOp::Add => s * ws + d * wd,
+            BlendOp::Subtract => s * ws - d * wd,
+            BlendOp::ReverseSubtract => d * wd - s * ws,
+        };
+        return FloatColor {
+            r: channel(s.r, ws.r, d.r, wd.r),
+            g: channel(s.g, ws.g, d.g, wd.g),
+            b: channel(s.b, ws.b, d.b, wd.b),
+            a: channel(s.a, ws.a, d.a, wd.a),
         }
+        .into_color();
     }
+}
 
-    /// Translate (move) a mesh in space
+/// Material id of the faces that have no material
+pub const NO_MATERIAL: usize = usize::MAX;
+
+/// Surface material describing how a face reflects light
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Material {
+    /// Color of the light reflected regardless of the light direction
+    pub ambient: Color,
+    /// Color of the light scattered from the surface (Lambertian reflection)
+    pub diffuse: Color,
+    /// Color of the highlights (mirror-like reflection)
+    pub specular: Color,
+    /// Specular exponent, higher values produce smaller and sharper highlights
+    pub shininess: f32,
+    /// Index of the tangent space normal map in `Mesh::textures`, `None` lights the faces
+    /// with the interpolated normals only
+    pub normal_map: Option<usize>,
+}
+
+/// Curve that maps linear shaded intensities to the displayable range [0, 1]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ToneMapping {
+    /// Intensities above 1.0 are clamped to full brightness
+    Clamp,
+    /// Reinhard operator c / (1 + c), compresses highlights instead of clipping
+    Reinhard,
+}
+
+impl ToneMapping {
+    /// Map a linear intensity to the range [0, 1]
     ///
     /// # Arguments
     ///
-    /// * `translation` - Vector that specifies the displacement
-    pub fn translate(self: &mut Mesh, translation: Vector3<f32>) {
-        let xform = Matrix4::from_rows(&[
-            RowVector4::new(1.0, 0.0, 0.0, translation.x),
-            RowVector4::new(0.0, 1.0, 0.0, translation.y),
-            RowVector4::new(0.0, 0.0, 1.0, translation.z),
-            RowVector4::new(0.0, 0.0, 0.0, 1.0),
-        ]);
-        self.position = xform * self.position;
+    /// * `c` - Linear intensity, 1.0 corresponds to full brightness
+    pub fn apply(&self, c: f32) -> f32 {
+        let c = c.max(0.0);
+        match *self {
+            ToneMapping::Clamp => c.min(1.0),
+            ToneMapping::Reinhard => c / (1.0 + c),
+        }
     }
 
-    /// Rotate a mesh
+    /// Map the color channels of a linear color to the range [0, 1], alpha is unaffected
     ///
     /// # Arguments
     ///
-    /// * `angle` - Rotation angle around each cartesian axis in radians
-    pub fn rotate(self: &mut Mesh, angle: Vector3<f32>) {
-        self.angle.x = self.angle.x + angle.x;
-        self.angle.y = self.angle.y + angle.y;
+    /// * `color` - Linear color, 1.0 corresponds to full intensity
+    pub fn apply_color(&self, color: FloatColor) -> FloatColor {
+        return FloatColor {
+            r: self.apply(color.r),
+            g: self.apply(color.g),
+            b: self.apply(color.b),
+            a: color.a,
+        };
+    }
+}
+
+/// Encoding of the intensities of color channels
+///
+/// Lighting and blending are correct only on linear intensities, while images and displays
+/// usually store sRGB encoded values that give more precision to the dark shades.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorSpace {
+    /// Values are linear intensities
+    Linear,
+    /// Values are sRGB encoded
+    Srgb,
+}
+
+impl ColorSpace {
+    /// Convert a value in the color space into a linear intensity
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - Value in [0, 1]
+    pub fn to_linear(&self, c: f32) -> f32 {
+        return match *self {
+            ColorSpace::Linear => c,
+            ColorSpace::Srgb if c <= 0.04045 => c / 12.92,
+            ColorSpace::Srgb => ((c + 0.055) / 1.055).powf(2.4),
+        };
+    }
+
+    /// Convert a linear intensity into a value in the color space
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - Linear intensity in [0, 1]
+    pub fn from_linear(&self, c: f32) -> f32 {
+        return match *self {
+            ColorSpace::Linear => c,
+            ColorSpace::Srgb if c <= 0.003_130_8 => c * 12.92,
+            ColorSpace::Srgb => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+        };
+    }
+
+    /// Convert the color channels of a color into linear intensities, alpha is unaffected
+    pub fn color_to_linear(&self, color: FloatColor) -> FloatColor {
+        return FloatColor {
+            r: self.to_linear(color.r),
+            g: self.to_linear(color.g),
+            b: self.to_linear(color.b),
+            a: color.a,
+        };
+    }
+
+    /// Convert the linear color channels of a color into the color space, alpha is unaffected
+    pub fn color_from_linear(&self, color: FloatColor) -> FloatColor {
+        return FloatColor {
+            r: self.from_linear(color.r),
+            g: self.from_linear(color.g),
+            b: self.from_linear(color.b),
+            a: color.a,
+        };
+    }
+}
+
+/// Order of the color channels of a pixel in the pixel data of a display buffer
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ByteOrder {
+    /// Red, green, blue, alpha
+    Rgba,
+    /// Blue, green, red, alpha, e.g. for uploading into BGRA textures
+    Bgra,
+}
+
+/// Fill of the display buffer when it is cleared, behind all geometry
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Background {
+    /// Single color over the whole buffer
+    Solid(Color),
+    /// Colors interpolated row by row from the top color (first) to the bottom color (second)
+    VerticalGradient(Color, Color),
+}
+
+impl Background {
+    /// Color of a row of the buffer
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - Row index, 0 is the top row
+    /// * `height` - Number of rows in the buffer
+    pub fn row_color(&self, row: usize, height: usize) -> Color {
+        return match *self {
+            Background::Solid(color) => color,
+            Background::VerticalGradient(top, bottom) => {
+                let t = if height > 1 {
+                    row as f32 / (height - 1) as f32
+                } else {
+                    0.0
+                };
+                top * (1.0 - t) + bottom * t
+            }
+        };
+    }
+}
+
+/// Fog that blends the shaded colors toward a fog color with the distance from the camera
+///
+/// The distance is the depth in camera space, along the view direction. The fog is applied
+/// to the vertices after shading and interpolated over the faces, the alpha of the colors is
+/// left unchanged.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Fog {
+    /// Fog grows linearly from none at the start distance to full at the end distance
+    Linear {
+        /// Color of the fog, e.g. the background color
+        color: Color,
+        /// Distance where the fog begins
+        start: f32,
+        /// Distance where the fog hides the geometry completely
+        end: f32,
+    },
+    /// Fog grows exponentially, the fraction `1 - e^(-density * d)` of the color is fog where
+    /// `d` is the distance beyond the near plane
+    Exponential {
+        /// Color of the fog, e.g. the background color
+        color: Color,
+        /// Fog per unit of distance
+        density: f32,
+    },
+}
+
+impl Fog {
+    /// Fraction of the fog color in the color of a point, 0 for no fog and 1 for only fog
+    ///
+    /// # Arguments
+    ///
+    /// * `depth` - Distance of the point from the camera along the view direction
+    /// * `near` - Distance of the near plane, points on it have no fog
+    pub fn amount(&self, depth: f32, near: f32) -> f32 {
+        let amount = match *self {
+            Fog::Linear { start, end, .. } => {
+                let start = start.max(near);
+                if end > start {
+                    (depth - start) / (end - start)
+                } else if depth >= end {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Fog::Exponential { density, .. } => 1.0 - (-density * (depth - near)).exp(),
+        };
+        return amount.clamp(0.0, 1.0);
+    }
+
+    /// Blend a shaded color toward the fog color
+    ///
+    /// # Arguments
+    ///
+    /// * `color` - Shaded color of a point
+    /// * `depth` - Distance of the point from the camera along the view direction
+    /// * `near` - Distance of the near plane, points on it have no fog
+    pub fn apply(&self, color: FloatColor, depth: f32, near: f32) -> FloatColor {
+        let fog = match *self {
+            Fog::Linear { color, .. } | Fog::Exponential { color, .. } => FloatColor::from(color),
+        };
+        let amount = self.amount(depth, near);
+        return FloatColor {
+            a: color.a,
+            ..color * (1.0 - amount) + fog * amount
+        };
+    }
+}
+
+/// Selects what is written to the display buffer for each fragment
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RenderMode {
+    /// Lit vertex colors
+    Shaded,
+    /// Interpolated normal vector mapped to RGB, lighting is ignored
+    Normals,
+    /// Antialiased lines along the edges of the faces in the lit vertex colors
+    Wireframe {
+        /// Width of the lines in pixels
+        width: f32,
+    },
+    /// Lit vertex colors with the edges of the faces drawn over them (hidden-line removal)
+    ///
+    /// The edges are pulled toward the camera by a depth offset so that they win the depth
+    /// test against the face they belong to, but nearer faces still hide them.
+    FilledWireframe {
+        /// Width of the lines in pixels
+        width: f32,
+        /// Color of the lines
+        color: Color,
+        /// Offset added to the depth of the lines, negative values are nearer to the camera.
+        /// The offset is in the depth range of the buffer.
+        offset: f32,
+    },
+    /// Every face in a flat pseudo-random color derived from its index in the mesh, vertex
+    /// colors and lighting are ignored. Shows the boundaries and the order of the faces
+    FaceIds,
+    /// Discs at evenly spaced points on the surface of the faces in the lit vertex colors,
+    /// each with the interpolated color and depth of its center
+    Points {
+        /// Number of steps between the points along each edge of a face, a face has
+        /// (density + 1) * (density + 2) / 2 points
+        density: u32,
+        /// Radius of the discs in pixels
+        radius: f32,
+    },
+}
+
+/// How the diffuse brightness of the vertices is turned into shades
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ShadingModel {
+    /// Brightness is used as is for smooth shading
+    Smooth,
+    /// Brightness is quantized into a number of discrete bands for a flat cartoon look
+    Toon {
+        /// Number of brightness levels above zero
+        bands: u32,
+    },
+    /// No lighting, the vertex colors (or the diffuse colors of the materials) are drawn
+    /// exactly as they are, e.g. for sprites and UI geometry
+    Unlit,
+}
+
+impl ShadingModel {
+    /// Map a diffuse brightness (cosine of the light angle) to the brightness used for shading
+    ///
+    /// # Arguments
+    ///
+    /// * `brightness` - Diffuse brightness, 1.0 when the light hits the surface head-on
+    pub fn apply(&self, brightness: f32) -> f32 {
+        match *self {
+            ShadingModel::Smooth => brightness,
+            ShadingModel::Toon { bands } => {
+                let bands = bands.max(1) as f32;
+                (brightness.max(0.0) * bands).ceil().min(bands) / bands
+            }
+            ShadingModel::Unlit => 1.0,
+        }
+    }
+}
+
+/// Vertex order of the front side of a triangle, as seen when looking at the front side
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Winding {
+    /// Vertices are listed counter-clockwise (OBJ convention)
+    CounterClockwise,
+    /// Vertices are listed clockwise
+    Clockwise,
+}
+
+/// Axis aligned rectangle in pixel coordinates, y = 0 corresponds to the bottom edge
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Rect {
+    /// X coordinate of the left edge
+    pub x: usize,
+    /// Y coordinate of the bottom edge
+    pub y: usize,
+    /// Width in pixels
+    pub width: usize,
+    /// Height in pixels
+    pub height: usize,
+}
+
+impl Rect {
+    /// Check whether a pixel is inside the rectangle
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        return x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height;
+    }
+}
+
+/// Default depth of a pixel that has not been written since the last clear, smaller depth
+/// values are closer to the camera
+pub(crate) const CLEAR_DEPTH: f32 = f32::MAX;
+
+/// Pipeline settings of a display buffer
+///
+/// The buffer keeps its settings in `DisplayBuffer::state`. Copy the state to save it and
+/// assign it to restore it later, or pass one to `Mesh::render_with_state` and
+/// `Scene::render_with_state` for a single draw. The default is the state of a new display
+/// buffer.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RenderState {
+    /// Tone mapping applied when shaded colors are written to the buffer
+    pub tone_mapping: ToneMapping,
+    /// Color space of the pixel data, the shaded colors are linear and converted into it
+    /// after the tone mapping. Linear by default, `Srgb` encodes the colors for display
+    /// (gamma correction), e.g. when the meshes have textures decoded from sRGB.
+    pub color_space: ColorSpace,
+    /// Writes outside of the scissor rectangle are discarded
+    pub scissor: Option<Rect>,
+    /// Aspect ratio (width / height) in pixels of the region the projection fills, derived
+    /// from the size of the buffer when not set. The projection scales it by the pixel
+    /// aspect ratio in both cases.
+    pub aspect_ratio: Option<f32>,
+    /// Width of a pixel divided by its height on the target display, e.g. 0.5 for pixels
+    /// twice as tall as wide. Shapes keep their proportions on such a display, so a circle
+    /// covers 1 / ratio times as many pixels across as down. Defaults to 1 (square pixels).
+    pub pixel_aspect_ratio: f32,
+    /// Distances (near, far) from the eye to the clipping planes of the projection, the
+    /// defaults 0.1 and 100 are used when not set. A tight range around the visible geometry
+    /// gives more depth precision, see `Scene::fit_clip_planes`.
+    pub clip_planes: Option<(f32, f32)>,
+    /// User clip plane (a, b, c, d) in world space for cutaway views. Geometry at the points
+    /// where a x + b y + c z + d < 0 is discarded in addition to the geometry outside of the
+    /// view frustum, the faces are cut along the plane.
+    pub clip_plane: Option<Vector4<f32>>,
+    /// Fill of the pixel data on clear
+    pub background: Background,
+    /// Depth values (near, far) that the depth range [-1, 1] of the normalized device
+    /// coordinates is mapped to before storing, e.g. (0.0, 1.0). The normalized depth is
+    /// stored as is when not set. Near must be smaller than far for the depth test.
+    pub depth_range: Option<(f32, f32)>,
+    /// Blend equation for the pixels that pass the depth test, the color replaces the pixel
+    /// when not set. With a blend that ignores transparent colors, faces that are fully
+    /// transparent are skipped and do not write depth either.
+    pub blend: Option<Blend>,
+    /// Relation of the color channels to the alpha channel of the pixel data. The default
+    /// `AlphaMode::Straight` writes the colors as they are, `AlphaMode::Premultiplied`
+    /// multiplies them by their alpha before the blend, see `Blend::PREMULTIPLIED_ALPHA`.
+    pub alpha_mode: AlphaMode,
+    /// Faces whose area in raster space is at most this many square pixels are skipped
+    /// before rasterizing. Sub-pixel slivers cover few or no pixel centers but still cost a
+    /// walk over their bounding box. The default of 0 skips only the degenerate faces.
+    pub min_face_area: f32,
+    /// Normalize the interpolated normal of each fragment before it is shaded. The normals
+    /// between the vertices are shorter than unit length where the vertex normals differ, so
+    /// turning this off is slightly wrong for curved surfaces but saves a square root per
+    /// fragment. On by default.
+    pub normalize_normals: bool,
+    /// Fog blended into the shaded colors of the meshes drawn into the buffer
+    pub fog: Option<Fog>,
+    /// Depth written to every pixel on clear, in the depth range of the buffer. Only the
+    /// fragments closer than it pass the depth test, so a smaller value clips the geometry
+    /// behind it. Pixels at this depth count as not written, e.g. for the outlines and the
+    /// depth image. Defaults to the largest `f32`.
+    pub clear_depth: f32,
+    /// Stencil test of the pixel writes, only applied when the stencil buffer is enabled
+    pub stencil_test: Option<StencilTest>,
+    /// Convention of the world and camera space of the geometry drawn into the buffer,
+    /// `Handedness::LeftHanded` renders geometry authored for Direct3D with the camera
+    /// looking towards +z. The image is mirrored compared to the right-handed convention, so
+    /// the front faces of such geometry usually have the clockwise `Winding`.
+    pub handedness: Handedness,
+}
+
+impl Default for RenderState {
+    fn default() -> RenderState {
+        return RenderState {
+            tone_mapping: ToneMapping::Clamp,
+            color_space: ColorSpace::Linear,
+            scissor: None,
+            aspect_ratio: None,
+            pixel_aspect_ratio: 1.0,
+            clip_planes: None,
+            clip_plane: None,
+            background: Background::Solid(Color::TRANSPARENT),
+            depth_range: None,
+            blend: None,
+            alpha_mode: AlphaMode::Straight,
+            min_face_area: 0.0,
+            normalize_normals: true,
+            fog: None,
+            clear_depth: CLEAR_DEPTH,
+            stencil_test: None,
+            handedness: Handedness::RightHanded,
+        };
+    }
+}
+
+/// Display buffer defines a memory area that is used for rendering a raw image
+#[derive(Clone)]
+pub struct DisplayBuffer {
+    /// Width of the display area in pixels
+    pub width: usize,
+    /// Height of the display area in pixels
+    pub height: usize,
+    /// Bytes per pixel
+    pub bpp: usize,
+    /// Contents of the buffer (pixel data)
+    pub data: Box<[u8]>,
+    /// Z/depth buffer, empty when the depth is packed
+    pub z_buffer: Box<[f32]>,
+    /// Depth buffer of 16 bit values used in place of `z_buffer` when enabled with
+    /// `enable_packed_depth`, for half the memory. The depth range of the buffer (-1 to 1
+    /// when not set) is divided into 65535 steps, the largest value stands for the clear
+    /// depth.
+    pub packed_depth: Option<Box<[u16]>>,
+    /// Pipeline settings of the draws into the buffer
+    pub state: RenderState,
+    /// Number of writes attempted to each pixel regardless of the depth test result, only
+    /// counted when enabled with `enable_overdraw_counter`
+    pub overdraw: Option<Box<[u32]>>,
+    /// Secondary render target with the interpolated world space normal of the nearest face
+    /// at each pixel, rows stored like the pixel data. Written by the face rasterizer in the
+    /// same pass as the colors when enabled with `enable_normal_target`, pixels without a
+    /// face have a zero normal.
+    pub normals: Option<Box<[Vector3<f32>]>>,
+    /// Order of the color channels in `data`, `get_pixel` and the other accessors convert
+    /// back to `Color` and the image export writes RGBA regardless
+    pub byte_order: ByteOrder,
+    /// Stencil value of each pixel, rows stored like the pixel data. Cleared to 0, only
+    /// allocated when enabled with `enable_stencil`
+    pub stencil: Option<Box<[u8]>>,
+}
+
+impl DisplayBuffer {
+    pub fn new(width: usize, height: usize, bpp: usize) -> DisplayBuffer {
+        return DisplayBuffer {
+            height: height,
+            width: width,
+            bpp: bpp,
+            data: vec![0; width * height * bpp].into_boxed_slice(),
+            z_buffer: vec![CLEAR_DEPTH; width * height].into_boxed_slice(),
+            packed_depth: None,
+            state: RenderState::default(),
+            overdraw: None,
+            normals: None,
+            byte_order: ByteOrder::Rgba,
+            stencil: None,
+        };
+    }
+
+    /// return the size of the buffer in bytes
+    pub fn size(&self) -> usize {
+        return self.height * self.width * self.bpp;
+    }
+
+    /// return the number of pixels
+    pub fn num_pixels(&self) -> usize {
+        return self.height * self.width;
+    }
+
+    /// Aspect ratio (width / height) used for projecting into the buffer
+    pub fn aspect_ratio(&self) -> f32 {
+        let pixels = (self.width as f32) / (self.height as f32);
+        return self.state.aspect_ratio.unwrap_or(pixels) * self.state.pixel_aspect_ratio;
+    }
+
+    /// Pixel color of a shaded linear color, tone mapped and converted into the color space
+    /// of the buffer
+    pub(crate) fn encode_color(&self, color: FloatColor) -> Color {
+        let mapped = self.state.tone_mapping.apply_color(color);
+        return self
+            .state
+            .color_space
+            .color_from_linear(mapped)
+            .into_color();
+    }
+
+    /// Distances (near, far) from the eye to the clipping planes used for projecting into
+    /// the buffer
+    pub fn clip_planes(&self) -> (f32, f32) {
+        return self.state.clip_planes.unwrap_or((NEAR_PLANE, FAR_PLANE));
+    }
+
+    /// Matrix that transforms camera space to the clip space of the buffer
+    ///
+    /// The camera space is in the handedness of the buffer. The clip space is always the
+    /// right-handed one the renderer clips and depth tests in, so the left-handed clip space
+    /// z in [0, w] is mapped to [-w, w].
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        let (near, far) = self.clip_planes();
+        let projection = build_perspective_matrix(
+            near,
+            far,
+            ANGLE_OF_VIEW,
+            self.aspect_ratio(),
+            self.state.handedness,
+        );
+        return match self.state.handedness {
+            Handedness::RightHanded => projection,
+            Handedness::LeftHanded => {
+                let mut depth = Matrix4::identity();
+                depth[(2, 2)] = 2.0;
+                depth[(2, 3)] = -1.0;
+                depth * projection
+            }
+        };
+    }
+
+    /// Matrix that transforms world space to the camera space of the buffer, see
+    /// `handedness`
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    pub fn view_matrix(&self, eye: Vector3<f32>, lookat: Vector3<f32>) -> Matrix4<f32> {
+        let view = build_view_matrix(eye, lookat, Vector3::new(0.0, 1.0, 0.0));
+        // The camera looks along +z, the x axis turns around with it
+        let mirror = Vector3::new(-1.0, 1.0, -1.0);
+        return match self.state.handedness {
+            Handedness::RightHanded => view,
+            Handedness::LeftHanded => Matrix4::new_nonuniform_scaling(&mirror) * view,
+        };
+    }
+
+    /// Map a depth in normalized device coordinates to the depth range of the buffer
+    ///
+    /// # Arguments
+    ///
+    /// * `z` - Depth in normalized device coordinates, -1 at the near plane and 1 at the far
+    ///   plane
+    pub fn map_depth(&self, z: f32) -> f32 {
+        return match self.state.depth_range {
+            Some((near, far)) => near + (z + 1.0) * 0.5 * (far - near),
+            None => z,
+        };
+    }
+
+    /// Map a depth in the depth range of the buffer back to normalized device coordinates
+    pub(crate) fn unmap_depth(&self, z: f32) -> f32 {
+        return match self.state.depth_range {
+            Some((near, far)) => (z - near) / (far - near) * 2.0 - 1.0,
+            None => z,
+        };
+    }
+
+    /// Reset the contents of the buffer to the background and the depth to the farthest value
+    pub fn clear(&mut self) {
+        self.data = vec![0; self.width * self.height * self.bpp].into_boxed_slice();
+        if self.state.background != Background::Solid(Color::TRANSPARENT) {
+            for y in 0..self.height {
+                let color = self.state.background.row_color(y, self.height);
+                for x in 0..self.width {
+                    self.write_pixel(y * self.width + x, color);
+                }
+            }
+        }
+        if self.packed_depth.is_some() {
+            self.enable_packed_depth();
+        } else {
+            // this takes a lot of time when the initialization value is not 0.0
+            self.z_buffer =
+                vec![self.state.clear_depth; self.width * self.height].into_boxed_slice();
+        }
+        if self.overdraw.is_some() {
+            self.overdraw = Some(vec![0; self.width * self.height].into_boxed_slice());
+        }
+        if self.normals.is_some() {
+            self.enable_normal_target();
+        }
+        if self.stencil.is_some() {
+            self.enable_stencil();
+        }
+    }
+
+    /// Store the depth as 16 bit values in `packed_depth` from now on, see there
+    ///
+    /// The depth of all pixels is reset to the clear depth. Depths closer together than a
+    /// step of the packed range may be rejected by the depth test where the full precision
+    /// would pass them.
+    pub fn enable_packed_depth(&mut self) {
+        self.z_buffer = Box::new([]);
+        self.packed_depth = Some(vec![u16::MAX; self.width * self.height].into_boxed_slice());
+    }
+
+    /// Depth of the pixel at an index of the depth buffer
+    pub(crate) fn stored_depth(&self, index: usize) -> f32 {
+        let packed = match self.packed_depth {
+            Some(ref packed) => packed[index],
+            None => return self.z_buffer[index],
+        };
+        if packed == u16::MAX {
+            return self.state.clear_depth;
+        }
+        let (near, far) = self.state.depth_range.unwrap_or((-1.0, 1.0));
+        return near + packed as f32 / (u16::MAX - 1) as f32 * (far - near);
+    }
+
+    /// Write the depth of the pixel at an index of the depth buffer
+    fn store_depth(&mut self, index: usize, z: f32) {
+        let (near, far) = self.state.depth_range.unwrap_or((-1.0, 1.0));
+        match self.packed_depth {
+            Some(ref mut packed) => {
+                let steps = (u16::MAX - 1) as f32;
+                let t = ((z - near) / (far - near)).clamp(0.0, 1.0);
+                packed[index] = (t * steps).round() as u16;
+            }
+            None => self.z_buffer[index] = z,
+        }
+    }
+
+    /// Start counting the writes to each pixel for overdraw profiling
+    pub fn enable_overdraw_counter(&mut self) {
+        self.overdraw = Some(vec![0; self.width * self.height].into_boxed_slice());
+    }
+
+    /// Start writing the normals of the faces into the normal target, see `normals`
+    pub fn enable_normal_target(&mut self) {
+        self.normals = Some(vec![Vector3::zeros(); self.width * self.height].into_boxed_slice());
+    }
+
+    /// Start keeping a stencil value for each pixel, see `stencil_test`
+    pub fn enable_stencil(&mut self) {
+        self.stencil = Some(vec![0; self.width * self.height].into_boxed_slice());
+    }
+
+    /// Produce a heatmap image of the overdraw counter
+    ///
+    /// Pixels that were never written are black. The others range from blue (written once) to
+    /// red (written the most times). Returns `None` if the overdraw counter is not enabled.
+    pub fn overdraw_heatmap(&self) -> Option<DisplayBuffer> {
+        let counts = self.overdraw.as_ref()?;
+        let max = counts.iter().cloned().max().unwrap_or(0);
+        let mut image = DisplayBuffer::new(self.width, self.height, self.bpp);
+
+        for (index, &count) in counts.iter().enumerate() {
+            let color = if count == 0 {
+                Color {
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                    a: 255,
+                }
+            } else {
+                let t = if max > 1 {
+                    (count - 1) as f32 / (max - 1) as f32
+                } else {
+                    0.0
+                };
+                Color {
+                    r: (t * 255.0).round() as u8,
+                    g: 0,
+                    b: ((1.0 - t) * 255.0).round() as u8,
+                    a: 255,
+                }
+            };
+            image.write_pixel(index, color);
+        }
+
+        return Some(image);
+    }
+
+    /// Produce a grayscale image of the depth buffer
+    ///
+    /// Depth values are normalized linearly so that `near` maps to white and `far` maps to
+    /// black. Values beyond the range are clamped.
+    ///
+    /// # Arguments
+    ///
+    /// * `near` - Depth value that maps to white
+    /// * `far` - Depth value that maps to black
+    /// * `background` - Color of the pixels that have not been written since the last clear
+    pub fn depth_to_grayscale(&self, near: f32, far: f32, background: Color) -> DisplayBuffer {
+        let mut image = DisplayBuffer::new(self.width, self.height, self.bpp);
+
+        for index in 0..self.num_pixels() {
+            let z = self.stored_depth(index);
+            let color = if z == self.state.clear_depth {
+                background
+            } else {
+                let t = ((z - far) / (near - far)).clamp(0.0, 1.0);
+                let intensity = (t * 255.0).round() as u8;
+                Color {
+                    r: intensity,
+                    g: intensity,
+                    b: intensity,
+                    a: 255,
+                }
+            };
+            image.write_pixel(index, color);
+        }
+
+        return image;
+    }
+
+    /// Index of the pixel nearest to coordinates that may be outside of the buffer, the
+    /// coordinates are clamped to the edges. `None` for a buffer without pixels
+    fn clamped_index(&self, x: isize, y: isize) -> Option<usize> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        return Some((self.height - y - 1) * self.width + x);
+    }
+
+    /// Depth of a pixel with the coordinates clamped to the edges of the buffer
+    ///
+    /// Post-processing kernels can read the neighbours of the border pixels without bounds
+    /// checks, the pixels outside of the buffer repeat the nearest edge pixel. A buffer
+    /// without pixels reads as cleared, at `clear_depth`.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - X coordinate in pixels, value 0 corresponds to left edge
+    /// * `y` - Y coordinate in pixels, value 0 corresponds to bottom edge
+    pub fn sample_depth(&self, x: isize, y: isize) -> f32 {
+        return self
+            .clamped_index(x, y)
+            .map_or(self.state.clear_depth, |index| self.stored_depth(index));
+    }
+
+    /// Color of a pixel with the coordinates clamped to the edges of the buffer, see
+    /// `sample_depth`. A buffer without pixels reads as transparent black.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - X coordinate in pixels, value 0 corresponds to left edge
+    /// * `y` - Y coordinate in pixels, value 0 corresponds to bottom edge
+    pub fn sample_color(&self, x: isize, y: isize) -> Color {
+        return self
+            .clamped_index(x, y)
+            .map_or(Color::TRANSPARENT, |index| self.read_pixel(index));
+    }
+
+    /// Index of a pixel in the depth buffer and the other per pixel buffers, `None` for
+    /// coordinates outside of the buffer
+    ///
+    /// The rows are stored from the top edge down. The row is found with checked arithmetic,
+    /// so any coordinates are safe to pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - X coordinate in pixels, value 0 corresponds to left edge
+    /// * `y` - Y coordinate in pixels, value 0 correspoonds to bottom edge
+    pub fn pixel_index(&self, x: usize, y: usize) -> Option<usize> {
+        if x >= self.width {
+            return None;
+        }
+        let row = self.height.checked_sub(y)?.checked_sub(1)?;
+        return Some(row * self.width + x);
+    }
+
+    /// Color of a single pixel, `None` for coordinates outside of the buffer
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - X coordinate in pixels, value 0 corresponds to left edge
+    /// * `y` - Y coordinate in pixels, value 0 correspoonds to bottom edge
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<Color> {
+        return self.pixel_index(x, y).map(|index| self.read_pixel(index));
+    }
+
+    /// Set a single pixel to a desired color
+    ///
+    /// Coordinates outside of the buffer are ignored. Returns true if the pixel was written,
+    /// false if it is outside of the buffer or the scissor rectangle, failed the stencil or
+    /// the depth test or only its stencil value was written.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - X coordinate in pixels, value 0 corresponds to left edge
+    /// * `y` - Y coordinate in pixels, value 0 correspoonds to bottom edge
+    /// * `z` - Depth of the pixel, written only if closer (smaller) than the stored depth
+    /// * 'color' - Color of the pixel
+    pub fn set_pixel(&mut self, x: usize, y: usize, z: f32, color: Color) -> bool {
+        return match self.pixel_index(x, y) {
+            Some(index) => self.write_fragment(index, x, y, z, color),
+            None => false,
+        };
+    }
+
+    /// Set a single pixel to a desired color without checking the buffer bounds
+    ///
+    /// Same as `set_pixel` for callers that already keep the coordinates inside the buffer,
+    /// such as the rasterizer that clamps its bounding box. The bounds are only asserted in
+    /// debug builds.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - X coordinate in pixels, must be less than the width of the buffer
+    /// * `y` - Y coordinate in pixels, must be less than the height of the buffer
+    /// * `z` - Depth of the pixel, written only if closer (smaller) than the stored depth
+    /// * 'color' - Color of the pixel
+    pub(crate) fn set_pixel_unchecked(&mut self, x: usize, y: usize, z: f32, color: Color) -> bool {
+        debug_assert!(x < self.width && y < self.height);
+        let index: usize = (self.height - y - 1) * self.width + x;
+        return self.write_fragment(index, x, y, z, color);
+    }
+
+    /// Run the scissor, stencil and depth tests of a pixel and write it if they pass, the
+    /// index must belong to the coordinates
+    fn write_fragment(&mut self, index: usize, x: usize, y: usize, z: f32, color: Color) -> bool {
+        if let Some(scissor) = self.state.scissor {
+            if !scissor.contains(x, y) {
+                return false;
+            }
+        }
+
+        if let Some(ref mut counts) = self.overdraw {
+            counts[index] += 1;
+        }
+
+        let nearer = z < self.stored_depth(index);
+        if let (Some(test), Some(ref mut stencil)) = (self.state.stencil_test, &mut self.stencil) {
+            if !test.passes(stencil[index]) || !nearer {
+                return false;
+            }
+            stencil[index] = test.apply(stencil[index]);
+            if test.mask_only {
+                return false;
+            }
+        }
+
+        if nearer {
+            self.store_depth(index, z);
+            let color = match self.state.alpha_mode {
+                AlphaMode::Straight => color,
+                AlphaMode::Premultiplied => color.premultiplied(),
+            };
+            let color = match self.state.blend {
+                Some(b) => {
+                    let dst = self.read_pixel(index);
+                    Color::blend(color, dst, b.src_factor, b.dst_factor, b.op)
+                }
+                None => color,
+            };
+            self.write_pixel(index, color);
+            return true;
+        }
+        return false;
+    }
+
+    /// Check a fragment against the depth buffer before it is shaded (early depth test)
+    ///
+    /// Returns `true` when the pixel already has nearer geometry or fails the stencil test,
+    /// so the fragment can be skipped without computing its color. The rejected write is
+    /// counted by the overdraw counter like a write rejected in `set_pixel`. The coordinates
+    /// must be inside the buffer.
+    pub(crate) fn reject_depth(&mut self, x: usize, y: usize, z: f32) -> bool {
+        debug_assert!(x < self.width && y < self.height);
+        let index = (self.height - y - 1) * self.width + x;
+        let stencil_passes = match (self.state.stencil_test, &self.stencil) {
+            (Some(test), Some(stencil)) => test.passes(stencil[index]),
+            _ => true,
+        };
+        if stencil_passes && z < self.stored_depth(index) {
+            return false;
+        }
+        if let Some(ref mut counts) = self.overdraw {
+            counts[index] += 1;
+        }
+        return true;
+    }
+
+    /// Write the normal of a pixel to the normal target if it is enabled
+    pub(crate) fn write_normal(&mut self, x: usize, y: usize, normal: Vector3<f32>) {
+        let index = self.pixel_index(x, y);
+        if let (Some(index), Some(ref mut normals)) = (index, &mut self.normals) {
+            normals[index] = normal;
+        }
+    }
+
+    /// Write the color of a pixel at an index of the pixel data in the byte order of the
+    /// buffer
+    pub(crate) fn write_pixel(&mut self, index: usize, color: Color) {
+        let (first, third) = match self.byte_order {
+            ByteOrder::Rgba => (color.r, color.b),
+            ByteOrder::Bgra => (color.b, color.r),
+        };
+        self.data[index * self.bpp] = first;
+        self.data[index * self.bpp + 1] = color.g;
+        self.data[index * self.bpp + 2] = third;
+        self.data[index * self.bpp + 3] = color.a;
+    }
+
+    /// Read the color of a pixel at an index of the pixel data in the byte order of the buffer
+    pub(crate) fn read_pixel(&self, index: usize) -> Color {
+        let i = index * self.bpp;
+        let (first, third) = (self.data[i], self.data[i + 2]);
+        let (r, b) = match self.byte_order {
+            ByteOrder::Rgba => (first, third),
+            ByteOrder::Bgra => (third, first),
+        };
+        return Color {
+            r,
+            g: self.data[i + 1],
+            b,
+            a: self.data[i + 3],
+        };
+    }
+}
+
+/// Vertex shader hook, transforms a vertex in homogeneous clip space. Shared by the copies
+/// of a mesh, see `Mesh::with_faces`
+pub type VertexShader = Rc<dyn Fn(Vertex<Vector4<f32>>) -> Vertex<Vector4<f32>>>;
+
+/// Fragment shader function, computes the color of a fragment from the interpolated attributes
+/// and the uniforms of the frame
+///
+/// The lifetime bounds the data the function borrows, shaders passed to a single draw may
+/// borrow from the caller.
+pub type FragmentShaderFn<'a> = dyn Fn(&FragmentInput, &Uniforms) -> Color + 'a;
+
+/// Fragment shader hook of a mesh, shared like `VertexShader`
+pub type FragmentShader = Rc<FragmentShaderFn<'static>>;
+
+/// Camera and shader state shared by all faces of a draw
+pub(crate) struct DrawContext<'a> {
+    /// Position of the camera eye, also the position of the light
+    pub eye: Vector3<f32>,
+    /// Transforms world space to camera space
+    pub view: Matrix4<f32>,
+    /// Transforms camera space to clip space
+    pub projection: Matrix4<f32>,
+    /// Transforms clip space back to world space
+    pub inverse_view_projection: Matrix4<f32>,
+    /// Colors the fragments instead of the render mode
+    pub shader: Option<&'a FragmentShaderFn<'a>>,
+    /// Values passed to the shader
+    pub uniforms: Uniforms,
+    /// ID of the first face of the mesh when the faces are drawn into an ID buffer, see
+    /// `id_color`. The faces are then filled with their IDs instead of being shaded
+    pub first_id: Option<u32>,
+}
+
+impl<'a> DrawContext<'a> {
+    pub fn new(
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        buffer: &DisplayBuffer,
+        shader: Option<&'a FragmentShaderFn<'a>>,
+        time: f32,
+    ) -> DrawContext<'a> {
+        let view = buffer.view_matrix(eye, lookat);
+        let projection = buffer.projection_matrix();
+        return DrawContext {
+            eye,
+            view,
+            projection,
+            inverse_view_projection: (projection * view)
+                .try_inverse()
+                .unwrap_or_else(Matrix4::identity),
+            shader,
+            uniforms: Uniforms {
+                time,
+                resolution: Vector2::new(buffer.width as f32, buffer.height as f32),
+                camera_position: eye,
+            },
+            first_id: None,
+        };
+    }
+}
+
+/// Smallest and largest coordinates (min, max) of a bounding box
+type Bounds = (Vector3<f32>, Vector3<f32>);
+
+/// Faces of a mesh transformed to world space
+struct WorldCache {
+    /// Model matrix the faces were transformed with
+    model: Matrix4<f32>,
+    /// Faces in the order of `Mesh::faces`
+    faces: Vec<Face<Vector4<f32>>>,
+}
+
+/// A mesh is a collection of triangles that form a 3D surface
+pub struct Mesh {
+    /// World position of the center of the mesh
+    pub position: Vector4<f32>,
+    /// Rotation of the mesh around the x, y and z axes in radians, applied in this order
+    pub angle: Vector3<f32>,
+    /// Triangle faces that make up the mesh surface
+    pub faces: Vec<Face<Vector4<f32>>>,
+    /// Surface materials of the faces, indexed by `Face::material_id`
+    pub materials: Vec<Material>,
+    /// Textures of the materials, e.g. the normal maps indexed by `Material::normal_map`
+    pub textures: Vec<Texture>,
+    /// What the mesh fragments are colored by
+    pub render_mode: RenderMode,
+    /// How the brightness of the vertices is turned into shades
+    pub shading_model: ShadingModel,
+    /// Vertex order of the front faces, back faces are culled
+    pub winding: Winding,
+    /// Whether the mesh is drawn as a part of a scene
+    pub visible: bool,
+    /// Draw the back faces too, lit with their normals flipped (e.g. for thin geometry)
+    pub double_sided: bool,
+    /// World position of the point light that shades the mesh, `None` places the light at the
+    /// camera eye
+    pub light_position: Option<Vector3<f32>>,
+    /// Offset added to the depth of the fragments before the depth test
+    ///
+    /// Depth grows away from the camera, so a negative offset pulls the mesh toward the camera
+    /// and lets it win the depth test against coplanar geometry (e.g. decals or overlays).
+    /// Depth is in normalized device coordinates, [-1, 1] from the near to the far plane.
+    pub polygon_offset: f32,
+    /// Light the faces with the normal maps of their materials
+    ///
+    /// Normal mapped faces are lit per fragment instead of per vertex, turning this off
+    /// draws them like faces without a normal map.
+    pub normal_mapping: bool,
+    /// Hook applied to each shaded vertex in clip space before clipping
+    pub vertex_shader: Option<VertexShader>,
+    /// Hook that colors the fragments instead of the render mode
+    pub fragment_shader: Option<FragmentShader>,
+    /// Keep the faces transformed to world space between draws
+    ///
+    /// A mesh that does not move then skips the model transform of every face. The cache is a
+    /// copy of all faces, so it doubles the memory of the geometry. It is rebuilt when the
+    /// position or the rotation changes or faces are added or removed, other changes made
+    /// directly to `faces` need a call to `invalidate_cache`.
+    pub cache_transforms: bool,
+    /// Budget of faces drawn per frame, `None` draws all faces
+    ///
+    /// A crude level of detail for dense meshes on slow machines: when the mesh has more
+    /// faces than the budget, only a subset spread evenly over the faces is drawn, which
+    /// leaves holes in the surface.
+    pub max_faces: Option<usize>,
+    /// Faces transformed to world space when `cache_transforms` is set
+    world_cache: RefCell<Option<WorldCache>>,
+    /// Bounding box of the faces in local space and the number of faces it was computed
+    /// for, `None` until `bounds` computes it
+    bounds_cache: Cell<Option<(usize, Option<Bounds>)>>,
+}
+
+impl Mesh {
+    pub fn new() -> Mesh {
+        return Mesh {
+            position: Vector4::new(0.0, 0.0, 0.0, 1.0),
+            angle: Vector3::new(0.0, 0.0, 0.0),
+            faces: Vec::new(),
+            materials: Vec::new(),
+            textures: Vec::new(),
+            render_mode: RenderMode::Shaded,
+            shading_model: ShadingModel::Smooth,
+            winding: Winding::CounterClockwise,
+            visible: true,
+            double_sided: false,
+            light_position: None,
+            polygon_offset: 0.0,
+            normal_mapping: true,
+            vertex_shader: None,
+            fragment_shader: None,
+            cache_transforms: false,
+            max_faces: None,
+            world_cache: RefCell::new(None),
+            bounds_cache: Cell::new(None),
+        };
+    }
+
+    /// Mesh of other faces with the placement, materials, textures and render state of this
+    /// mesh, e.g. a simplified or otherwise rebuilt copy of its geometry
+    ///
+    /// The shaders are shared with this mesh. The caches start empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `faces` - Faces of the new mesh in the local space of this mesh
+    pub fn with_faces(&self, faces: Vec<Face<Vector4<f32>>>) -> Mesh {
+        return Mesh {
+            position: self.position,
+            angle: self.angle,
+            faces,
+            materials: self.materials.clone(),
+            textures: self.textures.clone(),
+            render_mode: self.render_mode,
+            shading_model: self.shading_model,
+            winding: self.winding,
+            visible: self.visible,
+            double_sided: self.double_sided,
+            light_position: self.light_position,
+            polygon_offset: self.polygon_offset,
+            normal_mapping: self.normal_mapping,
+            vertex_shader: self.vertex_shader.clone(),
+            fragment_shader: self.fragment_shader.clone(),
+            cache_transforms: self.cache_transforms,
+            max_faces: self.max_faces,
+            world_cache: RefCell::new(None),
+            bounds_cache: Cell::new(None),
+        };
+    }
+
+    /// Closed white unit sphere around the origin, e.g. as test and benchmark geometry
+    ///
+    /// The sphere is made of `rings` rings from the top to the bottom and `segments`
+    /// segments around the y axis. The normals point outwards and the texture coordinates
+    /// wrap around it once, with u growing around the y axis and v from the bottom to the
+    /// top. The rings at the poles are single triangles per segment.
+    ///
+    /// # Arguments
+    ///
+    /// * `rings` - Number of rings from the top to the bottom
+    /// * `segments` - Number of segments around the y axis
+    pub fn uv_sphere(rings: usize, segments: usize) -> Mesh {
+        let pi = ::std::f32::consts::PI;
+        let point = |ring: usize, segment: usize| {
+            let theta = pi * ring as f32 / rings as f32;
+            // The last segment closes the sphere at exactly the position of the first one
+            let phi = 2.0 * pi * (segment % segments) as f32 / segments as f32;
+            let n = Vector3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                -theta.sin() * phi.sin(),
+            );
+            return Vertex {
+                position: Vector4::new(n.x, n.y, n.z, 1.0),
+                color: FloatColor::WHITE,
+                normal: n,
+                uv: Vector2::new(
+                    segment as f32 / segments as f32,
+                    1.0 - ring as f32 / rings as f32,
+                ),
+                tangent: Vector3::zeros(),
+            };
+        };
+
+        let mut mesh = Mesh::new();
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let (a, b) = (point(ring, segment), point(ring + 1, segment));
+                let (c, d) = (point(ring + 1, segment + 1), point(ring, segment + 1));
+                // The other triangles at the poles would be degenerate
+                if ring > 0 {
+                    mesh.faces.push(Face {
+                        v0: a,
+                        v1: b,
+                        v2: d,
+                        material_id: NO_MATERIAL,
+                    });
+                }
+                if ring + 1 < rings {
+                    mesh.faces.push(Face {
+                        v0: d,
+                        v1: b,
+                        v2: c,
+                        material_id: NO_MATERIAL,
+                    });
+                }
+            }
+        }
+        return mesh;
+    }
+
+    /// Matrix that transforms the mesh from its local space to world space
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        let m_trans = Matrix4::from_rows(&[
+            RowVector4::new(1.0, 0.0, 0.0, self.position.x),
+            RowVector4::new(0.0, 1.0, 0.0, self.position.y),
+            RowVector4::new(0.0, 0.0, 1.0, self.position.z),
+            RowVector4::new(0.0, 0.0, 0.0, 1.0),
+        ]);
+
+        return m_trans * rotation_matrix(self.angle);
+    }
+
+    /// Render a mesh into a display buffer
+    ///
+    /// The fragment shader of the mesh is run at time 0, animated shaders are rendered with
+    /// `render_with_shader`.
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    /// * `buffer` - Display buffer (render target)
+    pub fn render(
+        self: &Mesh,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        buffer: &mut DisplayBuffer,
+    ) {
+        self.render_with_shader(eye, lookat, buffer, self.fragment_shader.as_deref(), 0.0);
+    }
+
+    /// Render a mesh into a display buffer with other pipeline settings than its own
+    ///
+    /// The settings of the buffer are replaced by the state for the draw and restored after
+    /// it, so the buffer keeps its configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    /// * `buffer` - Display buffer (render target)
+    /// * `state` - Pipeline settings of the draw
+    pub fn render_with_state(
+        &self,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        buffer: &mut DisplayBuffer,
+        state: &RenderState,
+    ) {
+        let saved = buffer.state;
+        buffer.state = *state;
+        self.render(eye, lookat, buffer);
+        buffer.state = saved;
+    }
+
+    /// Render a mesh into a display buffer with a fragment shader
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    /// * `buffer` - Display buffer (render target)
+    /// * `shader` - Colors the fragments instead of the render mode, replaces the fragment
+    ///   shader of the mesh
+    /// * `time` - Animation time in seconds passed to the shader
+    pub fn render_with_shader(
+        &self,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        buffer: &mut DisplayBuffer,
+        shader: Option<&FragmentShaderFn>,
+        time: f32,
+    ) {
+        let context = DrawContext::new(eye, lookat, buffer, shader, time);
+        self.draw(self.model_matrix(), &context, buffer);
+    }
+
+    /// Render the faces of the mesh with the camera and the shader of a draw
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - Transform from the local space to the world space, the model matrix of the
+    ///   mesh or of an instance of it
+    /// * `context` - Camera and shader of the draw
+    /// * `buffer` - Display buffer (render target)
+    pub(crate) fn draw(
+        &self,
+        model: Matrix4<f32>,
+        context: &DrawContext,
+        buffer: &mut DisplayBuffer,
+    ) {
+        // Skip a mesh that is completely outside of the view without transforming its faces.
+        // A vertex shader may move the vertices anywhere, so its mesh is always drawn
+        if self.vertex_shader.is_none() {
+            let mvp = context.projection * context.view * model;
+            if outside_frustum(&self.clip_space_bounds(mvp)) {
+                return;
+            }
+        }
+
+        // Instances are transformed on every draw and leave the cache of the mesh as it is
+        let instance = model != self.model_matrix();
+        let mut cache = self.world_cache.borrow_mut();
+        let current = |c: &&WorldCache| c.model == model && c.faces.len() == self.faces.len();
+        if !self.cache_transforms {
+            *cache = None;
+        } else if !instance && cache.as_ref().filter(current).is_none() {
+            *cache = Some(WorldCache {
+                model,
+                faces: self.faces.iter().map(|f| f.transform(model)).collect(),
+            });
+        }
+        let cached = cache.as_ref().filter(current);
+
+        // Face i is drawn when the budget scaled to it crosses an integer, which picks
+        // exactly `budget` faces evenly spread over the mesh
+        let count = self.faces.len() as u64;
+        let budget = self.max_faces.map_or(count, |m| count.min(m as u64));
+        let sampled = |i: u64| (i + 1) * budget / count != i * budget / count;
+
+        let mut index = 0;
+        for (material_id, faces) in self.material_batches() {
+            let material = self.materials.get(material_id);
+            for t in faces.iter() {
+                let i = index;
+                index += 1;
+                if budget < count && !sampled(i as u64) {
+                    continue;
+                }
+                let face_world = match cached {
+                    Some(world) => world.faces[i],
+                    None => t.transform(model),
+                };
+                self.draw_world_face(&face_world, i, material, context, buffer);
+            }
+        }
+    }
+
+    /// Smallest and largest coordinates of the vertices in the local space of the mesh
+    ///
+    /// The box is cached, so culling the mesh on every draw does not go through all vertices.
+    /// Methods that change the geometry, like `apply_transform`, drop the cached box, changes
+    /// made directly to `faces` need a call to `invalidate_cache` unless they add or remove
+    /// faces. `None` for a mesh without faces.
+    pub fn bounds(&self) -> Option<Bounds> {
+        if let Some((count, bounds)) = self.bounds_cache.get() {
+            if count == self.faces.len() {
+                return bounds;
+            }
+        }
+        let bounds = self.compute_bounds();
+        self.bounds_cache.set(Some((self.faces.len(), bounds)));
+        return bounds;
+    }
+
+    /// Compute the bounding box of the faces again and cache it, see `bounds`
+    pub fn recompute_bounds(&mut self) -> Option<Bounds> {
+        self.bounds_cache.set(None);
+        return self.bounds();
+    }
+
+    /// Bounding box of the vertices in the local space of the mesh without the cache
+    fn compute_bounds(&self) -> Option<Bounds> {
+        if self.faces.is_empty() {
+            return None;
+        }
+
+        let mut min = Vector3::repeat(f32::MAX);
+        let mut max = Vector3::repeat(f32::MIN);
+        for v in self.vertices() {
+            let p = v.position.xyz() / v.position.w;
+            min = min.zip_map(&p, f32::min);
+            max = max.zip_map(&p, f32::max);
+        }
+        return Some((min, max));
+    }
+
+    /// Corners of the bounding box of the mesh in its local space transformed by a matrix
+    ///
+    /// With the model-view-projection matrix the corners are in clip space, where
+    /// `clipping::outside_frustum` rejects a mesh outside of the view with only 8 vertices
+    /// transformed. Corner `i` has the largest x, y and z when bit 0, 1 and 2 of `i` are set.
+    /// All corners of a mesh without faces are at the origin of the mesh.
+    ///
+    /// # Arguments
+    ///
+    /// * `mvp` - Matrix applied to the corners, e.g. the model-view-projection matrix
+    pub fn clip_space_bounds(&self, mvp: Matrix4<f32>) -> [Vector4<f32>; 8] {
+        let (min, max) = self
+            .bounds()
+            .unwrap_or((Vector3::zeros(), Vector3::zeros()));
+        let mut corners = [Vector4::zeros(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let pick = |axis: usize| [min[axis], max[axis]][(i >> axis) & 1];
+            *corner = mvp * Vector4::new(pick(0), pick(1), pick(2), 1.0);
+        }
+        return corners;
+    }
+
+    /// Drop the faces cached in world space and the cached bounding box, they are computed
+    /// again when needed
+    ///
+    /// Needed after changing `faces` directly when `cache_transforms` is set, and for the
+    /// bounding box after moving vertices directly, see `bounds`.
+    pub fn invalidate_cache(&mut self) {
+        *self.world_cache.get_mut() = None;
+        self.bounds_cache.set(None);
+    }
+
+    /// Shade, project, clip and rasterize a face in world space
+    ///
+    /// # Arguments
+    ///
+    /// * `face_world` - Face transformed to world space
+    /// * `face_index` - Index of the face in the mesh, colors the faces in `RenderMode::FaceIds`
+    /// * `material` - Material of the face
+    /// * `context` - Camera and shader of the draw
+    /// * `buffer` - Display buffer (render target)
+    pub(crate) fn draw_world_face(
+        &self,
+        face_world: &Face<Vector4<f32>>,
+        face_index: usize,
+        material: Option<&Material>,
+        context: &DrawContext,
+        buffer: &mut DisplayBuffer,
+    ) {
+        // A face that can only produce transparent fragments does not change a blended
+        // buffer. The shaders could change the alpha, so faces with shaders are drawn
+        let blend_ignores_face = buffer.state.blend.is_some_and(|b| b.ignores_transparent())
+            && self.vertex_shader.is_none()
+            && context.shader.is_none()
+            && match material {
+                Some(m) => m.diffuse.a == 0,
+                None => {
+                    face_world.v0.color.a <= 0.0
+                        && face_world.v1.color.a <= 0.0
+                        && face_world.v2.color.a <= 0.0
+                }
+            };
+        if blend_ignores_face {
+            return;
+        }
+
+        let eye = context.eye;
+        let mut triangle_world_3d = face_world.to_3d();
+
+        // The normals are not unit vectors after a transform that scales the mesh,
+        // which would scale the brightness too
+        let unit = |n: Vector3<f32>| n.try_normalize(f32::EPSILON).unwrap_or(n);
+        triangle_world_3d.v0.normal = unit(triangle_world_3d.v0.normal);
+        triangle_world_3d.v1.normal = unit(triangle_world_3d.v1.normal);
+        triangle_world_3d.v2.normal = unit(triangle_world_3d.v2.normal);
+
+        // Backface culling: skip faces whose front side points away from the eye. The
+        // back side of a double sided face is lit like a front side facing the eye
+        let face_normal = triangle_normal(
+            triangle_world_3d.v0.position,
+            triangle_world_3d.v1.position,
+            triangle_world_3d.v2.position,
+            self.winding,
+        );
+        if face_normal.dot(&(eye - triangle_world_3d.v0.position)) <= 0.0 {
+            if !self.double_sided {
+                return;
+            }
+            triangle_world_3d.v0.normal = -triangle_world_3d.v0.normal;
+            triangle_world_3d.v1.normal = -triangle_world_3d.v1.normal;
+            triangle_world_3d.v2.normal = -triangle_world_3d.v2.normal;
+        }
+
+        // Light vector is a unit vector from the vertex to the light source, by default the
+        // light sits at the eye like a headlamp
+        let light = self.light_position.unwrap_or(eye);
+        let brightness = |v: &Vertex<Vector3<f32>>| (light - v.position).normalize().dot(&v.normal);
+        let brightness_v0 = brightness(&triangle_world_3d.v0);
+        let brightness_v1 = brightness(&triangle_world_3d.v1);
+        let brightness_v2 = brightness(&triangle_world_3d.v2);
+
+        // With the light at the eye, a negative dot product means that the light is hitting
+        // the inner surface of the mesh and we can simply ignore the triangle (not render it).
+        // A light elsewhere leaves the faces turned away from it unlit, but they are still
+        // seen from the eye
+        //
+        // Debug visualizations ignore lighting, so nothing is culled
+        let lit = match self.render_mode {
+            RenderMode::Shaded | RenderMode::FilledWireframe { .. } | RenderMode::Points { .. } => {
+                true
+            }
+            RenderMode::Normals | RenderMode::Wireframe { .. } | RenderMode::FaceIds => false,
+        };
+        let lit = lit && context.first_id.is_none();
+        let visible = self.light_position.is_some()
+            || self.shading_model == ShadingModel::Unlit
+            || brightness_v0 > 0.0
+            || brightness_v1 > 0.0
+            || brightness_v2 > 0.0;
+        if visible || !lit {
+            // Step 2: World to camera space
+            let triangle_view = face_world.transform(context.view);
+
+            // Step 3: Camera to clip space
+            // The vertices are shaded before clipping so that the new vertices on the
+            // clipping planes get interpolated colors. The debug visualization shows the
+            // world space normals
+            let mut triangle_camera = triangle_view.transform(context.projection);
+            let shade_vertex = |color: FloatColor, brightness: f32| match self.shading_model {
+                ShadingModel::Unlit => material.map_or(color, |m| FloatColor::from(m.diffuse)),
+                model => shade(color, material, model.apply(brightness)),
+            };
+            // Normal mapped faces are shaded per fragment, a shader replaces the lighting
+            let per_fragment = lit
+                && self.normal_mapping
+                && self.shading_model != ShadingModel::Unlit
+                && context.shader.is_none();
+            let normal_map = material
+                .and_then(|m| m.normal_map)
+                .and_then(|i| self.textures.get(i))
+                .filter(|_| per_fragment);
+            if normal_map.is_none() {
+                triangle_camera.v0.color = shade_vertex(triangle_camera.v0.color, brightness_v0);
+                triangle_camera.v1.color = shade_vertex(triangle_camera.v1.color, brightness_v1);
+                triangle_camera.v2.color = shade_vertex(triangle_camera.v2.color, brightness_v2);
+            }
+            if let (Some(fog), None) = (buffer.state.fog, normal_map) {
+                let (near, depth) = (buffer.clip_planes().0, |z| {
+                    buffer.state.handedness.view_depth(z)
+                });
+                let v = &mut triangle_camera;
+                v.v0.color = fog.apply(v.v0.color, depth(triangle_view.v0.position.z), near);
+                v.v1.color = fog.apply(v.v1.color, depth(triangle_view.v1.position.z), near);
+                v.v2.color = fog.apply(v.v2.color, depth(triangle_view.v2.position.z), near);
+            }
+            if self.render_mode == RenderMode::FaceIds {
+                let color = face_id_color(face_index);
+                triangle_camera.v0.color = color;
+                triangle_camera.v1.color = color;
+                triangle_camera.v2.color = color;
+            }
+            triangle_camera.v0.normal = triangle_world_3d.v0.normal;
+            triangle_camera.v1.normal = triangle_world_3d.v1.normal;
+            triangle_camera.v2.normal = triangle_world_3d.v2.normal;
+            triangle_camera.v0.tangent = face_world.v0.tangent;
+            triangle_camera.v1.tangent = face_world.v1.tangent;
+            triangle_camera.v2.tangent = face_world.v2.tangent;
+            if let Some(ref shader) = self.vertex_shader {
+                triangle_camera.v0 = shader(triangle_camera.v0);
+                triangle_camera.v1 = shader(triangle_camera.v1);
+                triangle_camera.v2 = shader(triangle_camera.v2);
+            }
+            if let Some(first_id) = context.first_id {
+                let color = id_color(first_id + face_index as u32);
+                triangle_camera.v0.color = color;
+                triangle_camera.v1.color = color;
+                triangle_camera.v2.color = color;
+            }
+
+            // Step 4.1: Clipping against the view frustum and the user clip plane. The
+            // plane is moved to clip space with the inverse transpose of the transform
+            let user_plane = buffer
+                .state
+                .clip_plane
+                .map(|plane| context.inverse_view_projection.transpose() * plane);
+            let polygon = clip_triangle(&triangle_camera, user_plane);
+
+            // Step 4.2: PERSPECTIVE DIVIDE (normalization)
+            // Perspective division, far away points moved closer to origin
+            // To screen space. All visible points between [-1, 1].
+            // Depth is divided too: z/w is linear in screen space, so interpolating it
+            // with the barycentric coordinates of a pixel gives the correct depth. It
+            // grows from -1 at the near plane to 1 at the far plane.
+            //
+            // Step 5: Viewport transform
+            let (width, height) = (buffer.width as f32, buffer.height as f32);
+            let vertices: Vec<Vertex<Vector3<f32>>> = polygon
+                .iter()
+                .map(|v| {
+                    let z = v.position.z / v.position.w + self.polygon_offset;
+                    Vertex {
+                        position: Vector3::new(
+                            (1.0 + v.position.x / v.position.w) * 0.5 * width,
+                            (1.0 + v.position.y / v.position.w) * 0.5 * height,
+                            buffer.map_depth(z),
+                        ),
+                        color: v.color,
+                        normal: v.normal,
+                        uv: v.uv,
+                        tangent: v.tangent,
+                    }
+                })
+                .collect();
+
+            let lighting = match (normal_map, material) {
+                (Some(texture), Some(&material)) => {
+                    Some(self.normal_map_lighting(texture, material, context, buffer))
+                }
+                _ => None,
+            };
+
+            // The clipped polygon is convex, draw it as a fan of triangles
+            for i in 1..vertices.len().saturating_sub(1) {
+                let t_viewport = Face {
+                    v0: vertices[0],
+                    v1: vertices[i],
+                    v2: vertices[i + 1],
+                    material_id: face_world.material_id,
+                };
+                // The IDs fill the faces whatever the render mode
+                if context.first_id.is_some() {
+                    t_viewport.render_with_mode(buffer, RenderMode::Shaded);
+                    continue;
+                }
+                t_viewport.render_with_shader(
+                    buffer,
+                    self.render_mode,
+                    lighting.as_ref().map_or(context.shader, |l| Some(l)),
+                    &context.uniforms,
+                );
+            }
+        }
+    }
+
+    /// Fragment shader that lights the fragments of a face with a normal map
+    ///
+    /// The world position of each fragment is recovered from its raster position and depth,
+    /// the normal map perturbs the interpolated normal and the fragment is shaded and fogged
+    /// like a vertex. The colors of the face vertices must be left unshaded.
+    fn normal_map_lighting<'a>(
+        &self,
+        texture: &'a Texture,
+        material: Material,
+        context: &DrawContext,
+        buffer: &DisplayBuffer,
+    ) -> impl Fn(&FragmentInput, &Uniforms) -> Color + 'a {
+        // Raster x and y in pixels and the depth of the buffer back to normalized device
+        // coordinates, the inverse of the viewport transform of `draw_world_face`
+        let depth_offset = buffer.unmap_depth(0.0);
+        let depth_scale = buffer.unmap_depth(1.0) - depth_offset;
+        let raster_to_ndc = Matrix4::from_rows(&[
+            RowVector4::new(2.0 / buffer.width as f32, 0.0, 0.0, -1.0),
+            RowVector4::new(0.0, 2.0 / buffer.height as f32, 0.0, -1.0),
+            RowVector4::new(0.0, 0.0, depth_scale, depth_offset - self.polygon_offset),
+            RowVector4::new(0.0, 0.0, 0.0, 1.0),
+        ]);
+        let raster_to_world = context.inverse_view_projection * raster_to_ndc;
+
+        let view = context.view;
+        let light = self.light_position.unwrap_or(context.eye);
+        let model = self.shading_model;
+        let (fog, near, handedness) = (
+            buffer.state.fog,
+            buffer.clip_planes().0,
+            buffer.state.handedness,
+        );
+        let (tone_mapping, color_space) = (buffer.state.tone_mapping, buffer.state.color_space);
+
+        return move |f: &FragmentInput, _: &Uniforms| {
+            let p = raster_to_world * Vector4::new(f.position.x, f.position.y, f.position.z, 1.0);
+            let p = p / p.w;
+            let normal = texture.sample_normal(f.normal, f.tangent, f.uv, f.uv_dx, f.uv_dy);
+            let brightness = (light - p.xyz())
+                .try_normalize(f32::EPSILON)
+                .map_or(0.0, |l| l.dot(&normal));
+            let mut color = shade(f.color, Some(&material), model.apply(brightness));
+            if let Some(fog) = fog {
+                color = fog.apply(color, handedness.view_depth((view * p).z), near);
+            }
+            return color_space
+                .color_from_linear(tone_mapping.apply_color(color))
+                .into_color();
+        };
+    }
+
+    /// Sort the faces by material so that the faces of each material are drawn together
+    ///
+    /// The order of the faces within a material is kept.
+    pub fn sort_faces_by_material(&mut self) {
+        self.faces.sort_by_key(|f| f.material_id);
+        self.invalidate_cache();
+    }
+
+    /// Group the faces into runs of consecutive faces with the same material
+    ///
+    /// Returns the material id and the faces of each run in drawing order. Each material has a
+    /// single run after `sort_faces_by_material`.
+    pub fn material_batches(&self) -> Vec<(usize, &[Face<Vector4<f32>>])> {
+        return self
+            .faces
+            .chunk_by(|a, b| a.material_id == b.material_id)
+            .map(|run| (run[0].material_id, run))
+            .collect();
+    }
+
+    /// Number of triangle faces in the mesh
+    pub fn face_count(&self) -> usize {
+        return self.faces.len();
+    }
+
+    /// Number of vertices in the mesh, three per face
+    pub fn vertex_count(&self) -> usize {
+        return self.faces.len() * 3;
+    }
+
+    /// Iterate over the faces of the mesh in drawing order
+    pub fn iter_faces(&self) -> impl Iterator<Item = &Face<Vector4<f32>>> {
+        return self.faces.iter();
+    }
+
+    /// Iterate over the vertices of all faces, three per face in the order v0, v1, v2
+    pub fn vertices(&self) -> impl Iterator<Item = &Vertex<Vector4<f32>>> {
+        return self
+            .faces
+            .iter()
+            .flat_map(|f| IntoIterator::into_iter([&f.v0, &f.v1, &f.v2]));
+    }
+
+    /// Append the faces of another mesh to this mesh
+    ///
+    /// The faces are transformed by the model matrix of `other` and then into the local space
+    /// of this mesh, so the merged mesh renders the same as drawing both meshes separately
+    /// with a single draw. Faces are reordered if the meshes have a different winding. The
+    /// textures of `other` are appended to the textures of this mesh, so the normal maps of
+    /// the merged materials keep referring to their own texture.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Mesh whose faces to append
+    pub fn merge(&mut self, other: &Mesh) {
+        let to_local = self
+            .model_matrix()
+            .try_inverse()
+            .expect("Could not invert model matrix")
+            * other.model_matrix();
+        let texture_offset = self.textures.len();
+        self.textures.extend(other.textures.iter().cloned());
+
+        for face in other.faces.iter() {
+            let mut face = face.transform(to_local);
+            face.material_id = match other.materials.get(face.material_id) {
+                Some(&m) => self.add_material(Material {
+                    normal_map: m.normal_map.map(|i| i + texture_offset),
+                    ..m
+                }),
+                None => NO_MATERIAL,
+            };
+            if other.winding != self.winding {
+                std::mem::swap(&mut face.v1, &mut face.v2);
+            }
+            self.faces.push(face);
+        }
+        self.invalidate_cache();
+    }
+
+    /// Add a material to the materials of the mesh
+    ///
+    /// Returns the material id for the faces. An equal material is shared instead of adding
+    /// a duplicate.
+    ///
+    /// # Arguments
+    ///
+    /// * `material` - Material to add
+    pub fn add_material(&mut self, material: Material) -> usize {
+        if let Some(id) = self.materials.iter().position(|m| *m == material) {
+            return id;
+        }
+        self.materials.push(material);
+        return self.materials.len() - 1;
+    }
+
+    /// Translate (move) a mesh in space
+    ///
+    /// # Arguments
+    ///
+    /// * `translation` - Vector that specifies the displacement
+    pub fn translate(self: &mut Mesh, translation: Vector3<f32>) {
+        let xform = Matrix4::from_rows(&[
+            RowVector4::new(1.0, 0.0, 0.0, translation.x),
+            RowVector4::new(0.0, 1.0, 0.0, translation.y),
+            RowVector4::new(0.0, 0.0, 1.0, translation.z),
+            RowVector4::new(0.0, 0.0, 0.0, 1.0),
+        ]);
+        self.position = xform * self.position;
+    }
+
+    /// Move and scale the faces so that the mesh is centered at its origin and fits a unit cube
+    ///
+    /// The bounding box of the vertices is centered at the origin of the mesh and its longest
+    /// side is scaled to 1. The scale is uniform, so the normals keep their directions.
+    pub fn center_and_normalize(&mut self) {
+        let (min, max) = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+
+        let center = (min + max) * 0.5;
+        let size = (max - min).max();
+        let scale = if size > 0.0 { 1.0 / size } else { 1.0 };
+        self.apply_transform(Matrix4::new_scaling(scale) * Matrix4::new_translation(&-center));
+    }
+
+    /// Transform the faces of the mesh permanently
+    ///
+    /// Unlike `translate` and `rotate`, which change the transform applied when the mesh is
+    /// drawn, the vertices are changed in place. The normals are transformed with the inverse
+    /// transpose of the matrix and normalized. A matrix that mirrors the geometry would turn
+    /// the front faces around, so the winding order of the faces is reversed with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `m` - Transform from the current local space of the vertices to the new one
+    pub fn apply_transform(&mut self, m: Matrix4<f32>) {
+        let mirrors = m.fixed_slice::<na::U3, na::U3>(0, 0).determinant() < 0.0;
+        let unit = |n: Vector3<f32>| n.try_normalize(f32::EPSILON).unwrap_or(n);
+        for face in self.faces.iter_mut() {
+            *face = face.transform(m);
+            face.v0.normal = unit(face.v0.normal);
+            face.v1.normal = unit(face.v1.normal);
+            face.v2.normal = unit(face.v2.normal);
+            if mirrors {
+                std::mem::swap(&mut face.v1, &mut face.v2);
+            }
+        }
+        self.invalidate_cache();
+    }
+
+    /// Check the winding order of the faces against their vertex normals
+    ///
+    /// Returns the indices of the faces whose geometric normal (derived from the vertex
+    /// positions and the winding order of the mesh) points away from any of its vertex
+    /// normals. Such faces are culled or lit from the wrong side, which makes the mesh look
+    /// inside-out.
+    pub fn validate_winding(&self) -> Vec<usize> {
+        let xyz = |v: &Vertex<Vector4<f32>>| Vector3::new(v.position.x, v.position.y, v.position.z);
+
+        return self
+            .faces
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| {
+                let n = triangle_normal(xyz(&f.v0), xyz(&f.v1), xyz(&f.v2), self.winding);
+                n.dot(&f.v0.normal) < 0.0 || n.dot(&f.v1.normal) < 0.0 || n.dot(&f.v2.normal) < 0.0
+            })
+            .map(|(i, _)| i)
+            .collect();
+    }
+
+    /// Negate the normals of all vertices, e.g. for a model exported with inward normals
+    ///
+    /// Inverted normals show up as dark faces or as faces listed by `validate_winding`. The
+    /// winding order is left as it is, so faces with both the wrong normals and the wrong
+    /// winding also need a change of `winding`.
+    pub fn invert_normals(&mut self) {
+        for face in self.faces.iter_mut() {
+            face.v0.normal = -face.v0.normal;
+            face.v1.normal = -face.v1.normal;
+            face.v2.normal = -face.v2.normal;
+        }
+        self.invalidate_cache();
+    }
+
+    /// Replace the vertex normals with the normal of their face, for flat shading
+    ///
+    /// The face normals follow the winding order of the mesh, degenerate faces get zero
+    /// normals. The tangents are made perpendicular to the new normals.
+    pub fn compute_flat_normals(&mut self) {
+        let winding = self.winding;
+        for face in self.faces.iter_mut() {
+            let (p0, p1, p2) = (face.v0.position, face.v1.position, face.v2.position);
+            let n = triangle_normal(p0.xyz(), p1.xyz(), p2.xyz(), winding)
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(Vector3::zeros);
+            for v in [&mut face.v0, &mut face.v1, &mut face.v2].iter_mut() {
+                v.normal = n;
+                v.tangent = orthonormal_tangent(v.tangent, n);
+            }
+        }
+        self.invalidate_cache();
+    }
+
+    /// Replace the vertex normals with the average of the normals of the faces around each
+    /// vertex position, for smooth shading
+    ///
+    /// The face normals are weighted by the area of the faces, so that small faces do not
+    /// bend the normals of large ones. Vertices at the same position share the normal, also
+    /// where the model has uv seams or hard edges. The tangents are made perpendicular to the
+    /// new normals.
+    pub fn compute_smooth_normals(&mut self) {
+        let key = |p: &Vector4<f32>| [p.x, p.y, p.z].map(f32::to_bits);
+        let winding = self.winding;
+        let area_normal = |face: &Face<Vector4<f32>>| {
+            let (p0, p1, p2) = (face.v0.position, face.v1.position, face.v2.position);
+            return triangle_normal(p0.xyz(), p1.xyz(), p2.xyz(), winding);
+        };
+
+        let mut normals = HashMap::new();
+        for face in self.faces.iter() {
+            let n = area_normal(face);
+            for v in [&face.v0, &face.v1, &face.v2].iter() {
+                *normals
+                    .entry(key(&v.position))
+                    .or_insert_with(Vector3::zeros) += n;
+            }
+        }
+        for face in self.faces.iter_mut() {
+            let flat = area_normal(face)
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(Vector3::zeros);
+            for v in [&mut face.v0, &mut face.v1, &mut face.v2].iter_mut() {
+                let n = normals[&key(&v.position)].try_normalize(f32::EPSILON);
+                v.normal = n.unwrap_or(flat);
+                v.tangent = orthonormal_tangent(v.tangent, v.normal);
+            }
+        }
+        self.invalidate_cache();
+    }
+
+    /// Compute the tangents of the vertices from their positions and texture coordinates
+    ///
+    /// The tangent of each face points along the u texture coordinate. Vertices with the same
+    /// position, normal and texture coordinates get the average of the tangents of their faces,
+    /// made perpendicular to the normal. Faces with degenerate texture coordinates add no
+    /// tangent, so a mesh without texture coordinates gets zero tangents.
+    pub fn compute_tangents(&mut self) {
+        let key = |v: &Vertex<Vector4<f32>>| {
+            let (p, n) = (v.position, v.normal);
+            return [p.x, p.y, p.z, p.w, n.x, n.y, n.z, v.uv.x, v.uv.y].map(f32::to_bits);
+        };
+
+        let mut tangents = HashMap::new();
+        for face in self.faces.iter() {
+            let (v0, v1, v2) = (face.v0.to_3d(), face.v1.to_3d(), face.v2.to_3d());
+            let (e1, e2) = (v1.position - v0.position, v2.position - v0.position);
+            let (d1, d2) = (v1.uv - v0.uv, v2.uv - v0.uv);
+            let det = d1.x * d2.y - d2.x * d1.y;
+            let tangent = if det.abs() > f32::EPSILON {
+                (e1 * d2.y - e2 * d1.y) / det
+            } else {
+                Vector3::zeros()
+            };
+            for v in [&face.v0, &face.v1, &face.v2].iter() {
+                *tangents.entry(key(v)).or_insert_with(Vector3::zeros) += tangent;
+            }
+        }
+
+        for face in self.faces.iter_mut() {
+            for v in [&mut face.v0, &mut face.v1, &mut face.v2].iter_mut() {
+                v.tangent = orthonormal_tangent(tangents[&key(v)], v.normal);
+            }
+        }
+        self.invalidate_cache();
+    }
+
+    /// Rotate a mesh
+    ///
+    /// # Arguments
+    ///
+    /// * `angle` - Rotation angle around each cartesian axis in radians
+    pub fn rotate(self: &mut Mesh, angle: Vector3<f32>) {
+        self.angle.x = self.angle.x + angle.x;
+        self.angle.y = self.angle.y + angle.y;
         self.angle.z = self.angle.z + angle.z;
     }
+
+    /// Rotate a mesh around a point in world space
+    ///
+    /// The position of the mesh orbits the point and the orientation of the mesh turns with
+    /// it, so every vertex keeps its distance to the point. The rotation is applied after the
+    /// current rotation of the mesh.
+    ///
+    /// # Arguments
+    ///
+    /// * `point` - Center of the rotation in world space
+    /// * `angle` - Rotation angle around the x, y and z axes through the point in radians,
+    ///   applied in this order
+    pub fn rotate_about(&mut self, point: Vector3<f32>, angle: Vector3<f32>) {
+        let rotation = rotation_matrix(angle);
+        let offset = self.position.xyz() / self.position.w - point;
+        let offset = rotation * Vector4::new(offset.x, offset.y, offset.z, 0.0);
+        self.position = Vector4::new(point.x, point.y, point.z, 1.0) + offset;
+        self.angle = rotation_angles(&(rotation * rotation_matrix(self.angle)));
+    }
+}
+
+/// Rotation around the x, y and z axes, applied in this order
+pub(crate) fn rotation_matrix(angle: Vector3<f32>) -> Matrix4<f32> {
+    let m_rot_x = Matrix4::from_rows(&[
+        RowVector4::new(1.0, 0.0, 0.0, 0.0),
+        RowVector4::new(0.0, angle.x.cos(), -angle.x.sin(), 0.0),
+        RowVector4::new(0.0, angle.x.sin(), angle.x.cos(), 0.0),
+        RowVector4::new(0.0, 0.0, 0.0, 1.0),
+    ]);
+    let m_rot_y = Matrix4::from_rows(&[
+        RowVector4::new(angle.y.cos(), 0.0, angle.y.sin(), 0.0),
+        RowVector4::new(0.0, 1.0, 0.0, 0.0),
+        RowVector4::new(-angle.y.sin(), 0.0, angle.y.cos(), 0.0),
+        RowVector4::new(0.0, 0.0, 0.0, 1.0),
+    ]);
+    let m_rot_z = Matrix4::from_rows(&[
+        RowVector4::new(angle.z.cos(), -angle.z.sin(), 0.0, 0.0),
+        RowVector4::new(angle.z.sin(), angle.z.cos(), 0.0, 0.0),
+        RowVector4::new(0.0, 0.0, 1.0, 0.0),
+        RowVector4::new(0.0, 0.0, 0.0, 1.0),
+    ]);
+
+    return m_rot_z * m_rot_y * m_rot_x;
+}
+
+/// Rotation angles of a rotation matrix, the inverse of `rotation_matrix`
+fn rotation_angles(m: &Matrix4<f32>) -> Vector3<f32> {
+    let sin_y = (-m[(2, 0)]).clamp(-1.0, 1.0);
+    let y = sin_y.asin();
+    // At +-90 degrees around y the x and z rotations turn around the same axis (gimbal lock),
+    // the whole rotation is then put on the z axis
+    if sin_y.abs() > 1.0 - 1e-6 {
+        return Vector3::new(0.0, y, (-m[(0, 1)]).atan2(m[(1, 1)]));
+    }
+    return Vector3::new(m[(2, 1)].atan2(m[(2, 2)]), y, m[(1, 0)].atan2(m[(0, 0)]));
+}
+
+/// Mesh of a single upright triangle with a red, a green and a blue corner
+///
+/// The triangle has sides of length 1, the bottom edge on the x axis centered at the origin
+/// and the front face toward +z. This is the model of the test app when no file is given and
+/// a known good geometry for tests.
+pub fn default_triangle() -> Mesh {
+    let vertex = |x: f32, y: f32, color: Color| Vertex {
+        position: Vector4::new(x, y, 0.0, 1.0),
+        color: color.into(),
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        uv: Vector2::zeros(),
+        tangent: Vector3::zeros(),
+    };
+    let red = Color {
+        r: 255,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+    let green = Color {
+        r: 0,
+        g: 255,
+        b: 0,
+        a: 255,
+    };
+    let blue = Color {
+        r: 0,
+        g: 0,
+        b: 255,
+        a: 255,
+    };
+    let side_len = 1.0;
+
+    let mut model = Mesh::new();
+    model.faces.push(Face {
+        v0: vertex(0.0, side_len, red),
+        v1: vertex(-side_len / 2.0, 0.0, green),
+        v2: vertex(side_len / 2.0, 0.0, blue),
+        material_id: NO_MATERIAL,
+    });
+
+    return model;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn test_triangle(normal: Vector3<f32>) -> Face<Vector4<f32>> {
+        let color = FloatColor::WHITE;
+        return Face {
+            v0: Vertex {
+                position: Vector4::new(0.0, 1.0, 0.0, 1.0),
+                color,
+                normal,
+                uv: Vector2::zeros(),
+                tangent: Vector3::zeros(),
+            },
+            v1: Vertex {
+                position: Vector4::new(-0.5, 0.0, 0.0, 1.0),
+                color,
+                normal,
+                uv: Vector2::zeros(),
+                tangent: Vector3::zeros(),
+            },
+            v2: Vertex {
+                position: Vector4::new(0.5, 0.0, 0.0, 1.0),
+                color,
+                normal,
+                uv: Vector2::zeros(),
+                tangent: Vector3::zeros(),
+            },
+            material_id: NO_MATERIAL,
+        };
+    }
+
+    fn count_written_pixels(buffer: &DisplayBuffer) -> usize {
+        return buffer
+            .z_buffer
+            .iter()
+            .filter(|&&z| z != CLEAR_DEPTH)
+            .count();
+    }
+
+    fn render_test_mesh(mesh: &mut Mesh) -> DisplayBuffer {
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        mesh.position = Vector4::new(0.0, 0.0, -3.0, 1.0);
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+        return buffer;
+    }
+
+    fn pixel(buffer: &DisplayBuffer, x: usize, y: usize) -> &[u8] {
+        let index = ((buffer.height - y - 1) * buffer.width + x) * buffer.bpp;
+        return &buffer.data[index..index + buffer.bpp];
+    }
+
+    /// Face in the plane z = slope * x - 3 with a solid color. The normals point to +z
+    fn sloped_face(slope: f32, corners: [(f32, f32); 3], color: FloatColor) -> Face<Vector4<f32>> {
+        let vertex = |(x, y): (f32, f32)| Vertex {
+            position: Vector4::new(x, y, slope * x - 3.0, 1.0),
+            color,
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            uv: Vector2::zeros(),
+            tangent: Vector3::zeros(),
+        };
+        return Face {
+            v0: vertex(corners[0]),
+            v1: vertex(corners[1]),
+            v2: vertex(corners[2]),
+            material_id: NO_MATERIAL,
+        };
+    }
+
+    #[test]
+    fn test_depth_test_at_triangle_intersection() {
+        let red = FloatColor {
+            r: 1.0,
+            g: 0.0,
+            b: 0.0,
+            a: 1.0,
+        };
+        let green = FloatColor {
+            r: 0.0,
+            g: 1.0,
+            b: 0.0,
+            a: 1.0,
+        };
+        // The faces intersect along the line x = 0, which projects to the center column. Left
+        // of it the green face is closer to the camera, right of it the red one
+        let mut mesh = Mesh::new();
+        mesh.faces.push(sloped_face(
+            2.0,
+            [(-1.0, -1.0), (1.0, -1.0), (0.0, 1.0)],
+            red,
+        ));
+        mesh.faces.push(sloped_face(
+            -2.0,
+            [(-1.0, -0.9), (0.8, -1.0), (0.3, 1.0)],
+            green,
+        ));
+        let mut buffer = DisplayBuffer::new(101, 101, 4);
+
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+
+        for y in 40..60 {
+            assert!(pixel(&buffer, 49, y)[1] > 0, "row {}", y);
+            assert_eq!(pixel(&buffer, 49, y)[0], 0, "row {}", y);
+            assert!(pixel(&buffer, 51, y)[0] > 0, "row {}", y);
+            assert_eq!(pixel(&buffer, 51, y)[1], 0, "row {}", y);
+        }
+    }
+
+    /// Raster positions (x, y from the bottom) of the pixels written by a triangle at a
+    /// position, seen from the origin looking down -z
+    fn written_positions(position: Vector4<f32>) -> (DisplayBuffer, Vec<(usize, usize)>) {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        mesh.position = position;
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+        let written = (0..buffer.height)
+            .flat_map(|y| (0..buffer.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| buffer.get_pixel(x, y).unwrap().a != 0)
+            .collect();
+        return (buffer, written);
+    }
+
+    #[test]
+    fn test_fog_amount() {
+        let linear = Fog::Linear {
+            color: Color::GRAY,
+            start: 2.0,
+            end: 6.0,
+        };
+        let exponential = Fog::Exponential {
+            color: Color::GRAY,
+            density: 0.5,
+        };
+
+        assert_eq!(linear.amount(1.0, 0.1), 0.0);
+        assert_eq!(linear.amount(4.0, 0.1), 0.5);
+        assert_eq!(linear.amount(10.0, 0.1), 1.0);
+        assert_eq!(exponential.amount(0.1, 0.1), 0.0);
+        assert!((exponential.amount(2.1, 0.1) - (1.0 - (-1.0f32).exp())).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_fog_fades_distant_meshes_into_fog_color() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        mesh.shading_model = ShadingModel::Unlit;
+        mesh.position = Vector4::new(0.0, 0.0, -3.0, 1.0);
+        let drawn_colors = |fog: Fog| {
+            let mut buffer = DisplayBuffer::new(40, 30, 4);
+            buffer.state.fog = Some(fog);
+            mesh.render(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, -1.0),
+                &mut buffer,
+            );
+            let mut colors: Vec<Color> = (0..buffer.height)
+                .flat_map(|y| (0..buffer.width).map(move |x| (x, y)))
+                .map(|(x, y)| buffer.get_pixel(x, y).unwrap())
+                .filter(|c| c.a != 0)
+                .collect();
+            colors.dedup();
+            return colors;
+        };
+
+        let hidden = drawn_colors(Fog::Linear {
+            color: Color::BLUE,
+            start: 1.0,
+            end: 2.0,
+        });
+        let clear = drawn_colors(Fog::Linear {
+            color: Color::BLUE,
+            start: 5.0,
+            end: 10.0,
+        });
+        let half = drawn_colors(Fog::Linear {
+            color: Color::BLUE,
+            start: 1.0,
+            end: 5.0,
+        });
+
+        assert_eq!(hidden, vec![Color::BLUE]);
+        assert_eq!(clear, vec![Color::WHITE]);
+        // Halfway to the fog color, up to rounding
+        assert!(half
+            .iter()
+            .all(|c| c.b == 255 && c.r == c.g && (c.r as i32 - 128).abs() <= 1));
+    }
+
+    #[test]
+    fn test_points_right_of_and_above_the_camera_land_right_and_up() {
+        let (_, right) = written_positions(Vector4::new(1.0, 0.0, -3.0, 1.0));
+        let (buffer, above) = written_positions(Vector4::new(0.0, 0.5, -3.0, 1.0));
+
+        assert!(!right.is_empty() && right.iter().all(|&(x, _)| x > 20));
+        assert!(!above.is_empty() && above.iter().all(|&(_, y)| y > 15));
+        // The rows of the pixel data are stored from the top, so the geometry above the
+        // camera is in the first half of the data
+        let first = buffer.z_buffer.iter().position(|&z| z != CLEAR_DEPTH);
+        assert!(first.unwrap() / buffer.width < 15);
+    }
+
+    #[test]
+    fn test_view_is_right_handed() {
+        // Looking along +x with y up, +z is on the right (x cross y = z)
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        mesh.angle.y = -::std::f32::consts::FRAC_PI_2;
+        mesh.position = Vector4::new(3.0, 0.0, 1.0, 1.0);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            &mut buffer,
+        );
+
+        let written: Vec<usize> = (0..40)
+            .filter(|&x| (0..30).any(|y| buffer.get_pixel(x, y).unwrap().a != 0))
+            .collect();
+        assert!(!written.is_empty() && written.iter().all(|&x| x > 20));
+    }
+
+    #[test]
+    fn test_positive_angles_rotate_counter_clockwise() {
+        let rotate = |angle: Vector3<f32>, p: Vector4<f32>| {
+            let mut mesh = Mesh::new();
+            mesh.angle = angle;
+            return mesh.model_matrix() * p;
+        };
+        let quarter = ::std::f32::consts::FRAC_PI_2;
+        let x = Vector4::new(1.0, 0.0, 0.0, 1.0);
+        let y = Vector4::new(0.0, 1.0, 0.0, 1.0);
+        let z = Vector4::new(0.0, 0.0, 1.0, 1.0);
+
+        assert!((rotate(Vector3::new(quarter, 0.0, 0.0), y) - z).norm() < 1e-6);
+        assert!((rotate(Vector3::new(0.0, quarter, 0.0), z) - x).norm() < 1e-6);
+        assert!((rotate(Vector3::new(0.0, 0.0, quarter), x) - y).norm() < 1e-6);
+    }
+
+    #[test]
+    fn test_backface_culling_respects_winding() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+
+        let buffer = render_test_mesh(&mut mesh);
+        assert!(count_written_pixels(&buffer) > 0);
+
+        mesh.winding = Winding::Clockwise;
+        let buffer = render_test_mesh(&mut mesh);
+        assert_eq!(count_written_pixels(&buffer), 0);
+    }
+
+    #[test]
+    fn test_polygon_offset_wins_depth_test_on_coplanar_faces() {
+        let green = FloatColor {
+            r: 0.0,
+            g: 1.0,
+            b: 0.0,
+            a: 1.0,
+        };
+        let mut solid = Mesh::new();
+        solid.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        face.v0.color = green;
+        face.v1.color = green;
+        face.v2.color = green;
+        let mut overlay = Mesh::new();
+        overlay.faces.push(face);
+        overlay.position = Vector4::new(0.0, 0.0, -3.0, 1.0);
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+
+        let mut buffer = render_test_mesh(&mut solid);
+        overlay.render(eye, lookat, &mut buffer);
+        assert!(pixel(&buffer, 20, 18)[2] > 0);
+
+        overlay.polygon_offset = -0.001;
+        overlay.render(eye, lookat, &mut buffer);
+        assert!(pixel(&buffer, 20, 18)[1] > 0);
+        assert_eq!(pixel(&buffer, 20, 18)[2], 0);
+    }
+
+    #[test]
+    fn test_uv_sphere_is_closed_unit_sphere() {
+        let sphere = Mesh::uv_sphere(4, 8);
+
+        assert_eq!(sphere.face_count(), 2 * 4 * 8 - 2 * 8);
+        assert!(sphere.validate_winding().is_empty());
+        for v in sphere.vertices() {
+            assert!((v.position.xyz().norm() - 1.0).abs() < 1e-5);
+            assert!((v.normal - v.position.xyz()).norm() < 1e-5);
+        }
+        let (min, max) = sphere.bounds().unwrap();
+        assert!((min + Vector3::repeat(1.0)).norm() < 1e-5);
+        assert!((max - Vector3::repeat(1.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn test_merge_renders_same_as_separate_meshes() {
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+        let mut a = Mesh::new();
+        a.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        a.position = Vector4::new(-0.6, 0.0, -3.0, 1.0);
+        a.angle = Vector3::new(0.0, 0.0, 0.3);
+        let mut b = Mesh::new();
+        let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        std::mem::swap(&mut face.v1, &mut face.v2);
+        b.faces.push(face);
+        b.winding = Winding::Clockwise;
+        b.position = Vector4::new(0.5, -0.4, -3.5, 1.0);
+        b.angle = Vector3::new(0.2, 0.4, 0.0);
+
+        let mut separate = DisplayBuffer::new(40, 30, 4);
+        a.render(eye, lookat, &mut separate);
+        let written_a = count_written_pixels(&separate);
+        b.render(eye, lookat, &mut separate);
+        assert!(count_written_pixels(&separate) > written_a);
+        a.merge(&b);
+        let mut merged = DisplayBuffer::new(40, 30, 4);
+        a.render(eye, lookat, &mut merged);
+
+        assert_eq!(a.faces.len(), 2);
+        assert_eq!(merged.data, separate.data);
+    }
+
+    #[test]
+    fn test_merge_keeps_normal_maps_of_the_other_mesh() {
+        let flat = Color {
+            r: 128,
+            g: 128,
+            b: 255,
+            a: 255,
+        };
+        let mut a = Mesh::new();
+        a.textures.push(Texture::new(1, 1, &[Color::WHITE]));
+        let mut b = Mesh::new();
+        b.textures.push(Texture::new(1, 1, &[flat]));
+        let material = b.add_material(Material {
+            ambient: Color::BLACK,
+            diffuse: Color::WHITE,
+            specular: Color::BLACK,
+            shininess: 1.0,
+            normal_map: Some(0),
+        });
+        let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        face.material_id = material;
+        b.faces.push(face);
+
+        a.merge(&b);
+
+        assert_eq!(a.textures.len(), 2);
+        let normal_map = a.materials[a.faces[0].material_id].normal_map;
+        assert_eq!(normal_map, Some(1));
+        let uv = Vector2::new(0.5, 0.5);
+        let texel = a.textures[1].sample_level(uv, 0);
+        assert_eq!(texel, b.textures[0].sample_level(uv, 0));
+    }
+
+    #[test]
+    fn test_left_handed_buffer_matches_mirrored_right_handed_scene() {
+        let mut mesh = Mesh::new();
+        let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        face.v0.position.x = 0.4;
+        mesh.faces.push(face);
+        let right = render_test_mesh(&mut mesh);
+
+        // The same scene authored left-handed: z is flipped, so the triangle lies along +z,
+        // faces the camera with a -z normal and winds the other way
+        for v in [&mut face.v0, &mut face.v1, &mut face.v2].iter_mut() {
+            v.normal.z = -1.0;
+        }
+        mesh.faces[0] = face;
+        mesh.winding = Winding::Clockwise;
+        mesh.position = Vector4::new(0.0, 0.0, 3.0, 1.0);
+        let mut left = DisplayBuffer::new(40, 30, 4);
+        left.state.handedness = Handedness::LeftHanded;
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            &mut left,
+        );
+
+        assert!(count_written_pixels(&left) > 0);
+        for y in 0..30 {
+            for x in 0..40 {
+                assert_eq!(pixel(&left, x, y), pixel(&right, x, y));
+                let (x, y) = (x as isize, y as isize);
+                assert!((left.sample_depth(x, y) - right.sample_depth(x, y)).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_perspective_clip_z_follows_handedness() {
+        let ndc_z = |handedness: Handedness, z: f32| {
+            let projection = build_perspective_matrix(1.0, 10.0, 90.0, 1.0, handedness);
+            let p = projection * Vector4::new(0.0, 0.0, z, 1.0);
+            return p.z / p.w;
+        };
+
+        assert!((ndc_z(Handedness::RightHanded, -1.0) + 1.0).abs() < 1e-5);
+        assert!((ndc_z(Handedness::RightHanded, -10.0) - 1.0).abs() < 1e-5);
+        assert!(ndc_z(Handedness::LeftHanded, 1.0).abs() < 1e-5);
+        assert!((ndc_z(Handedness::LeftHanded, 10.0) - 1.0).abs() < 1e-5);
+        // Halfway in depth the z is the same fraction of the range under both conventions
+        let right = (ndc_z(Handedness::RightHanded, -2.0) + 1.0) / 2.0;
+        assert!((right - ndc_z(Handedness::LeftHanded, 2.0)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_face_ids_color_each_face_flat_and_distinct() {
+        let mut mesh = Mesh::new();
+        let mut left = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        let mut right = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        for v in [&mut left.v0, &mut left.v1, &mut left.v2].iter_mut() {
+            v.position.x -= 0.6;
+        }
+        for v in [&mut right.v0, &mut right.v1, &mut right.v2].iter_mut() {
+            v.position.x += 0.6;
+            v.color.r = 0.0;
+        }
+        mesh.faces.push(left);
+        mesh.faces.push(right);
+        mesh.light_position = Some(Vector3::new(-5.0, 0.0, 0.0));
+        mesh.render_mode = RenderMode::FaceIds;
+
+        let buffer = render_test_mesh(&mut mesh);
+
+        let color = |index: usize| face_id_color(index).into_color();
+        let row = 30 - 14 - 1;
+        let (l, r) = (buffer.get_pixel(12, row), buffer.get_pixel(28, row));
+        assert_eq!(l, Some(color(0)));
+        assert_eq!(r, Some(color(1)));
+        assert_ne!(color(0), color(1));
+    }
+
+    #[test]
+    fn test_aspect_ratio_override_scales_projection() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let buffer = render_test_mesh(&mut mesh);
+        let default_pixels = count_written_pixels(&buffer);
+
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        buffer.state.aspect_ratio = Some(0.5 * buffer.aspect_ratio());
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+        let squashed_pixels = count_written_pixels(&buffer);
+
+        assert!(squashed_pixels > 0);
+        assert!(squashed_pixels * 3 < default_pixels * 2);
+    }
+
+    #[test]
+    fn test_pixel_aspect_ratio_stretches_circle_into_ellipse() {
+        // Fan of triangles around the center of a circle of radius 0.5
+        let mut mesh = Mesh::new();
+        let point = |i: usize| {
+            let a = i as f32 * std::f32::consts::PI / 16.0;
+            return Vector4::new(0.5 * a.cos(), 0.5 * a.sin(), 0.0, 1.0);
+        };
+        for i in 0..32 {
+            let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+            face.v0.position = Vector4::new(0.0, 0.0, 0.0, 1.0);
+            face.v1.position = point(i);
+            face.v2.position = point(i + 1);
+            mesh.faces.push(face);
+        }
+        mesh.shading_model = ShadingModel::Unlit;
+        mesh.position = Vector4::new(0.0, 0.0, -1.5, 1.0);
+        let extent = |aspect_ratio: Option<f32>, pixel_aspect_ratio: f32| {
+            let mut buffer = DisplayBuffer::new(120, 120, 4);
+            buffer.state.aspect_ratio = aspect_ratio;
+            buffer.state.pixel_aspect_ratio = pixel_aspect_ratio;
+            mesh.render(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, -1.0),
+                &mut buffer,
+            );
+            let written: Vec<(usize, usize)> = (0..buffer.height)
+                .flat_map(|y| (0..buffer.width).map(move |x| (x, y)))
+                .filter(|&(x, y)| buffer.get_pixel(x, y).unwrap().a != 0)
+                .collect();
+            let span = |axis: fn(&(usize, usize)) -> usize| {
+                let values = written.iter().map(axis);
+                return (values.clone().max().unwrap() - values.min().unwrap() + 1) as f32;
+            };
+            return (span(|p| p.0), span(|p| p.1));
+        };
+
+        let (width, height) = extent(None, 1.0);
+        assert!((width - height).abs() <= 1.0);
+        let (width, height) = extent(None, 0.5);
+        assert!((width / height - 2.0).abs() < 0.1);
+        // An overridden aspect ratio is also scaled by the pixel aspect ratio
+        let (width, height) = extent(Some(1.0), 0.5);
+        assert!((width / height - 2.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_render_clips_face_crossing_near_plane() {
+        let color = FloatColor::WHITE;
+        let vertex = |x: f32, z: f32| Vertex {
+            position: Vector4::new(x, -0.5, z, 1.0),
+            color,
+            normal: Vector3::new(0.0, 1.0, 0.0),
+            uv: Vector2::zeros(),
+            tangent: Vector3::zeros(),
+        };
+        let mut mesh = Mesh::new();
+        // Floor that extends from behind the eye to the front of it
+        mesh.faces.push(Face {
+            v0: vertex(-1.0, 1.0),
+            v1: vertex(1.0, 1.0),
+            v2: vertex(0.0, -3.0),
+            material_id: NO_MATERIAL,
+        });
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+
+        assert!(count_written_pixels(&buffer) > 0);
+        for y in 15..buffer.height {
+            for x in 0..buffer.width {
+                assert_eq!(pixel(&buffer, x, y)[3], 0, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_looks_up_material_by_id() {
+        let black = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let green = Material {
+            ambient: Color { g: 255, ..black },
+            diffuse: black,
+            specular: black,
+            shininess: 1.0,
+            normal_map: None,
+        };
+        let mut mesh = Mesh::new();
+        let id = mesh.add_material(green);
+        assert_eq!(mesh.add_material(green), id);
+        assert_eq!(mesh.materials.len(), 1);
+
+        let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        face.material_id = id;
+        mesh.faces.push(face);
+        let buffer = render_test_mesh(&mut mesh);
+        assert_eq!(pixel(&buffer, 20, 18), [0, 255, 0, 255]);
+
+        mesh.faces[0].material_id = NO_MATERIAL;
+        let buffer = render_test_mesh(&mut mesh);
+        assert_eq!(pixel(&buffer, 20, 18)[0], pixel(&buffer, 20, 18)[1]);
+    }
+
+    #[test]
+    fn test_sorted_batches_render_same_as_unsorted() {
+        let black = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let mut mesh = Mesh::new();
+        let red = mesh.add_material(Material {
+            ambient: Color { r: 255, ..black },
+            diffuse: black,
+            specular: black,
+            shininess: 1.0,
+            normal_map: None,
+        });
+        let blue = mesh.add_material(Material {
+            ambient: Color { b: 255, ..black },
+            diffuse: black,
+            specular: black,
+            shininess: 1.0,
+            normal_map: None,
+        });
+        for (i, &id) in [red, blue, NO_MATERIAL, red, blue].iter().enumerate() {
+            let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+            face = face.transform(Matrix4::new_translation(&Vector3::new(
+                i as f32 * 0.4 - 0.8,
+                i as f32 * -0.2,
+                i as f32 * -0.1,
+            )));
+            face.material_id = id;
+            mesh.faces.push(face);
+        }
+        let unsorted = render_test_mesh(&mut mesh);
+        assert_eq!(mesh.material_batches().len(), 5);
+
+        mesh.sort_faces_by_material();
+        let sorted = render_test_mesh(&mut mesh);
+
+        let batches: Vec<(usize, usize)> = mesh
+            .material_batches()
+            .iter()
+            .map(|&(id, faces)| (id, faces.len()))
+            .collect();
+        assert_eq!(batches, vec![(red, 2), (blue, 2), (NO_MATERIAL, 1)]);
+        assert_eq!(sorted.data, unsorted.data);
+    }
+
+    #[test]
+    fn test_center_and_normalize_fits_unit_cube() {
+        let mut mesh = Mesh::new();
+        let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        face.v0.position = Vector4::new(2.0, 0.0, 0.0, 1.0);
+        face.v1.position = Vector4::new(6.0, 1.0, 0.0, 1.0);
+        face.v2.position = Vector4::new(4.0, 0.0, 2.0, 1.0);
+        mesh.faces.push(face);
+
+        mesh.center_and_normalize();
+
+        let f = &mesh.faces[0];
+        assert_eq!(f.v0.position, Vector4::new(-0.5, -0.125, -0.25, 1.0));
+        assert_eq!(f.v1.position, Vector4::new(0.5, 0.125, -0.25, 1.0));
+        assert_eq!(f.v2.position, Vector4::new(0.0, -0.125, 0.25, 1.0));
+        assert_eq!(f.v0.normal, Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_apply_transform_bakes_positions_and_normals() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let rotate = Matrix4::from_euler_angles(0.0, ::std::f32::consts::FRAC_PI_2, 0.0);
+        let translate = Matrix4::new_translation(&Vector3::new(1.0, 2.0, 3.0));
+        let m = translate * rotate * Matrix4::new_scaling(2.0);
+
+        mesh.apply_transform(m);
+
+        let v0 = mesh.faces[0].v0;
+        assert!((v0.position - Vector4::new(1.0, 4.0, 3.0, 1.0)).norm() < 1e-5);
+        assert!((v0.normal - Vector3::new(1.0, 0.0, 0.0)).norm() < 1e-5);
+        assert!(mesh.validate_winding().is_empty());
+    }
+
+    #[test]
+    fn test_apply_transform_keeps_front_faces_when_mirroring() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+
+        let mirror_x = Matrix4::new_nonuniform_scaling(&Vector3::new(-1.0, 1.0, 1.0));
+        mesh.apply_transform(mirror_x);
+
+        assert!(mesh.validate_winding().is_empty());
+        assert!(count_written_pixels(&render_test_mesh(&mut mesh)) > 0);
+    }
+
+    #[test]
+    fn test_rotate_about_orbits_the_point() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        mesh.position = Vector4::new(3.0, 1.0, -2.0, 1.0);
+        mesh.angle = Vector3::new(0.2, -0.4, 0.3);
+        let point = Vector3::new(1.0, 1.0, -2.0);
+        let local = mesh.faces[0].v0.position;
+        let before = mesh.model_matrix() * local;
+
+        // A quarter turn around y moves the mesh from +x of the point to -z of it
+        mesh.rotate_about(point, Vector3::new(0.0, ::std::f32::consts::FRAC_PI_2, 0.0));
+        assert!((mesh.position - Vector4::new(1.0, 1.0, -4.0, 1.0)).norm() < 1e-5);
+
+        let angle = Vector3::new(0.7, 0.1, -0.5);
+        mesh.rotate_about(point, angle);
+        let after = mesh.model_matrix() * local;
+        let radius = |p: Vector4<f32>| (p.xyz() - point).norm();
+        assert!((radius(after) - radius(before)).abs() < 1e-5);
+
+        let orbit = Matrix4::new_translation(&point)
+            * rotation_matrix(angle)
+            * rotation_matrix(Vector3::new(0.0, ::std::f32::consts::FRAC_PI_2, 0.0))
+            * Matrix4::new_translation(&-point);
+        assert!((after - orbit * before).norm() < 1e-5);
+    }
+
+    #[test]
+    fn test_vertices_iterates_all_faces_in_order() {
+        let mut mesh = Mesh::new();
+        let mut second = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        second.v2.position = Vector4::new(7.0, 8.0, 9.0, 1.0);
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        mesh.faces.push(second);
+
+        assert_eq!(mesh.face_count(), 2);
+        assert_eq!(mesh.vertex_count(), 6);
+        assert_eq!(mesh.iter_faces().count(), 2);
+        assert_eq!(mesh.vertices().count(), 6);
+        assert_eq!(
+            mesh.vertices().last().unwrap().position,
+            Vector4::new(7.0, 8.0, 9.0, 1.0)
+        );
+        assert_eq!(
+            mesh.vertices().next().unwrap().position,
+            mesh.faces[0].v0.position
+        );
+    }
+
+    #[test]
+    fn test_face_to_3d_keeps_attributes() {
+        let mut face = test_triangle(Vector3::new(0.0, 1.0, 0.0));
+        face.v1.uv = Vector2::new(0.25, 0.75);
+        face.v2.color.a = 0.5;
+        face.material_id = 3;
+
+        let f = face.to_3d();
+
+        assert_eq!(f.v0.position, Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(f.v1.position, Vector3::new(-0.5, 0.0, 0.0));
+        assert_eq!(f.v1.uv, Vector2::new(0.25, 0.75));
+        assert_eq!(f.v2.color, face.v2.color);
+        assert_eq!(f.v0.normal, Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(f.material_id, 3);
+    }
+
+    #[test]
+    fn test_cached_transforms_follow_rotation_and_invalidation() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let uncached = render_test_mesh(&mut mesh);
+        mesh.cache_transforms = true;
+
+        assert_eq!(render_test_mesh(&mut mesh).data, uncached.data);
+        assert_eq!(render_test_mesh(&mut mesh).data, uncached.data);
+
+        mesh.rotate(Vector3::new(0.0, 0.0, 0.5));
+        let mut rotated = render_test_mesh(&mut mesh);
+        mesh.cache_transforms = false;
+        assert_eq!(rotated.data, render_test_mesh(&mut mesh).data);
+        assert!(rotated.data != uncached.data);
+
+        mesh.cache_transforms = true;
+        render_test_mesh(&mut mesh);
+        mesh.faces[0].v0.position.y = 0.5;
+        assert_eq!(render_test_mesh(&mut mesh).data, rotated.data);
+        mesh.invalidate_cache();
+        rotated = render_test_mesh(&mut mesh);
+        assert!(count_written_pixels(&rotated) < count_written_pixels(&uncached));
+    }
+
+    #[test]
+    fn test_cached_transforms_follow_added_faces() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        mesh.cache_transforms = true;
+        let one = render_test_mesh(&mut mesh);
+
+        let mut second = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        for v in [&mut second.v0, &mut second.v1, &mut second.v2].iter_mut() {
+            v.position.y -= 1.0;
+        }
+        mesh.faces.push(second);
+        let two = render_test_mesh(&mut mesh);
+        mesh.cache_transforms = false;
+
+        assert!(count_written_pixels(&two) > count_written_pixels(&one));
+        assert_eq!(two.data, render_test_mesh(&mut mesh).data);
+    }
+
+    #[test]
+    fn test_blend_over_operator() {
+        let src = Color {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 128,
+        };
+        let dst = Color {
+            r: 0,
+            g: 0,
+            b: 255,
+            a: 255,
+        };
+
+        let c = Color::blend(
+            src,
+            dst,
+            BlendFactor::SrcAlpha,
+            BlendFactor::OneMinusSrcAlpha,
+            BlendOp::Add,
+        );
+
+        assert_eq!(
+            c,
+            Color {
+                r: 128,
+                g: 0,
+                b: 127,
+                a: 191,
+            }
+        );
+    }
+
+    #[test]
+    fn test_blend_additive_and_multiply() {
+        let src = Color {
+            r: 100,
+            g: 50,
+            b: 200,
+            a: 255,
+        };
+        let dst = Color {
+            r: 100,
+            g: 250,
+            b: 102,
+            a: 255,
+        };
+        let with = |b: Blend| Color::blend(src, dst, b.src_factor, b.dst_factor, b.op);
+
+        assert_eq!(
+            with(Blend::ADDITIVE),
+            Color {
+                r: 200,
+                g: 255,
+                b: 255,
+                a: 255,
+            }
+        );
+        assert_eq!(
+            with(Blend::MULTIPLY),
+            Color {
+                r: 39,
+                g: 49,
+                b: 80,
+                a: 255,
+            }
+        );
+    }
+
+    #[test]
+    fn test_stencil_restricts_drawing_to_mask() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        mesh.position = Vector4::new(0.0, 0.0, -3.0, 1.0);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        buffer.enable_stencil();
+        buffer.state.stencil_test = Some(StencilTest {
+            compare: StencilCompare::Always,
+            reference: 1,
+            pass: StencilOp::Replace,
+            mask_only: true,
+        });
+
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+        assert!(buffer.data.iter().all(|&b| b == 0));
+        assert_eq!(count_written_pixels(&buffer), 0);
+
+        buffer.state.stencil_test = Some(StencilTest {
+            compare: StencilCompare::Equal,
+            reference: 1,
+            pass: StencilOp::Keep,
+            mask_only: false,
+        });
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                buffer.set_pixel(x, y, 0.0, Color::RED);
+            }
+        }
+
+        let masked = buffer.stencil.as_ref().unwrap().iter().filter(|&&s| s == 1);
+        assert!(masked.count() > 0);
+        assert_eq!(buffer.get_pixel(20, 18), Some(Color::RED));
+        assert_eq!(buffer.get_pixel(2, 2), Some(Color::TRANSPARENT));
+        for (i, &s) in buffer.stencil.as_ref().unwrap().iter().enumerate() {
+            assert_eq!(buffer.z_buffer[i] == 0.0, s == 1);
+        }
+    }
+
+    #[test]
+    fn test_render_with_state_applies_and_restores_settings() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let full = render_test_mesh(&mut mesh);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        buffer.state.fog = Some(Fog::Linear {
+            color: Color::BLUE,
+            start: 1.0,
+            end: 2.0,
+        });
+        let saved = buffer.state;
+        let state = RenderState {
+            scissor: Some(Rect {
+                x: 0,
+                y: 0,
+                width: 20,
+                height: 30,
+            }),
+            ..RenderState::default()
+        };
+
+        mesh.render_with_state(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+            &state,
+        );
+
+        assert_eq!(buffer.state, saved);
+        let default = DisplayBuffer::new(4, 4, 4).state;
+        assert_eq!(RenderState::default(), default);
+        let written = count_written_pixels(&buffer);
+        assert!(written > 0 && written < count_written_pixels(&full));
+        assert_eq!(buffer.get_pixel(19, 15), full.get_pixel(19, 15));
+        assert_eq!(buffer.get_pixel(21, 15), Some(Color::TRANSPARENT));
+    }
+
+    #[test]
+    fn test_set_pixel_blends_with_buffer() {
+        let mut buffer = DisplayBuffer::new(2, 2, 4);
+        let gray = Color {
+            r: 100,
+            g: 100,
+            b: 100,
+            a: 255,
+        };
+        buffer.set_pixel(0, 0, 0.5, gray);
+        buffer.state.blend = Some(Blend::ADDITIVE);
+
+        assert!(buffer.set_pixel(0, 0, 0.4, gray));
+        assert!(!buffer.set_pixel(0, 0, 0.6, gray));
+
+        assert_eq!(buffer.get_pixel(0, 0).unwrap().r, 200);
+    }
+
+    #[test]
+    fn test_premultiplied_alpha_composites_half_coverage() {
+        // White edge pixel half covered by a face
+        let edge = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 128,
+        };
+        let over = |background: Color, mode: AlphaMode, blend: Blend| {
+            let mut buffer = DisplayBuffer::new(1, 1, 4);
+            buffer.state.background = Background::Solid(background);
+            buffer.clear();
+            buffer.state.alpha_mode = mode;
+            buffer.state.blend = Some(blend);
+            buffer.set_pixel(0, 0, 0.0, edge);
+            return buffer.get_pixel(0, 0).unwrap();
+        };
+        let straight = |bg| over(bg, AlphaMode::Straight, Blend::ALPHA);
+        let premul = |bg| over(bg, AlphaMode::Premultiplied, Blend::PREMULTIPLIED_ALPHA);
+
+        // Over an opaque background the result stays opaque
+        assert_eq!(premul(Color::BLUE), Color::from_hex(0x8080_FFFF));
+        assert_eq!(straight(Color::BLUE).a, 191);
+        // Over nothing premultiplied white stays white at half opacity, straight alpha stores
+        // a gray at quarter opacity, the dark fringe
+        assert_eq!(premul(Color::TRANSPARENT), Color::from_hex(0x8080_8080));
+        assert_eq!(straight(Color::TRANSPARENT), Color::from_hex(0x8080_8040));
+    }
+
+    #[test]
+    fn test_transparent_faces_are_skipped_when_blending() {
+        let mut mesh = Mesh::new();
+        let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        face.v0.color.a = 0.0;
+        face.v1.color.a = 0.0;
+        face.v2.color.a = 0.0;
+        mesh.faces.push(face);
+        mesh.position = Vector4::new(0.0, 0.0, -3.0, 1.0);
+        let render = |blend: Option<Blend>| {
+            let mut buffer = DisplayBuffer::new(40, 30, 4);
+            buffer.state.blend = blend;
+            buffer.enable_overdraw_counter();
+            mesh.render(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, -1.0),
+                &mut buffer,
+            );
+            return buffer.overdraw.unwrap().iter().sum::<u32>();
+        };
+
+        assert!(Blend::ALPHA.ignores_transparent());
+        assert!(!Blend::ADDITIVE.ignores_transparent());
+        assert_eq!(render(Some(Blend::ALPHA)), 0);
+        assert!(render(Some(Blend::ADDITIVE)) > 0);
+        assert!(render(None) > 0);
+    }
+
+    #[test]
+    fn test_default_triangle_faces_the_camera() {
+        let mut mesh = default_triangle();
+
+        assert_eq!(mesh.face_count(), 1);
+        assert!(mesh.validate_winding().is_empty());
+        assert!(count_written_pixels(&render_test_mesh(&mut mesh)) > 0);
+    }
+
+    #[test]
+    fn test_double_sided_renders_back_faces_lit() {
+        let mut front = Mesh::new();
+        front.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let mut back = Mesh::new();
+        back.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        back.angle.y = std::f32::consts::PI;
+
+        let front = render_test_mesh(&mut front);
+        assert_eq!(count_written_pixels(&render_test_mesh(&mut back)), 0);
+        back.double_sided = true;
+        let back = render_test_mesh(&mut back);
+
+        assert!(count_written_pixels(&back) > 0);
+        assert_eq!(count_written_pixels(&back), count_written_pixels(&front));
+        let center = pixel(&back, 20, 18);
+        assert!(center[0] > 200, "back face is not lit: {:?}", center);
+        assert_eq!(center, pixel(&front, 20, 18));
+    }
+
+    #[test]
+    fn test_light_to_the_side_shades_asymmetrically() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let headlamp = render_test_mesh(&mut mesh);
+        assert_eq!(pixel(&headlamp, 18, 16), pixel(&headlamp, 22, 16));
+
+        mesh.light_position = Some(Vector3::new(2.0, 0.5, -2.5));
+        let buffer = render_test_mesh(&mut mesh);
+
+        let (left, right) = (pixel(&buffer, 18, 16), pixel(&buffer, 22, 16));
+        assert!(left[3] == 255 && right[3] == 255);
+        assert!(right[0] > left[0], "left {:?}, right {:?}", left, right);
+    }
+
+    #[test]
+    fn test_brightness_of_scaled_mesh_stays_in_unit_range() {
+        let mut mesh = Mesh::new();
+        let scale = Matrix4::new_nonuniform_scaling(&Vector3::new(2.0, 2.0, 0.2));
+        let face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        mesh.faces.push(face.transform(scale));
+        assert!(mesh.faces[0].v0.normal.norm() > 1.0);
+        let brightest = Rc::new(Cell::new(0.0f32));
+        let seen = brightest.clone();
+        mesh.vertex_shader = Some(Rc::new(move |v: Vertex<Vector4<f32>>| {
+            seen.set(seen.get().max(v.color.r));
+            v
+        }));
+
+        render_test_mesh(&mut mesh);
+
+        assert!(brightest.get() > 0.9);
+        assert!(brightest.get() <= 1.0);
+    }
+
+    #[test]
+    fn test_render_calls_shader_hooks() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        // Move the vertices to the left half of the view in clip space
+        mesh.vertex_shader = Some(Rc::new(|mut v: Vertex<Vector4<f32>>| {
+            v.position.x -= v.position.w;
+            v
+        }));
+        mesh.fragment_shader = Some(Rc::new(|f: &FragmentInput, _: &Uniforms| Color {
+            r: 0,
+            g: 0,
+            b: (f.color.b * 100.0) as u8,
+            a: 255,
+        }));
+
+        let buffer = render_test_mesh(&mut mesh);
+
+        assert!(count_written_pixels(&buffer) > 0);
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                let p = pixel(&buffer, x, y);
+                assert!(p[3] == 0 || (x < 20 && p[0] == 0 && p[2] <= 100));
+            }
+        }
+    }
+
+    #[test]
+    fn test_clear_depth_rejects_fragments_behind_it() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let buffer = render_test_mesh(&mut mesh);
+        let depth = buffer.z_buffer[(30 - 18 - 1) * 40 + 20];
+
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        buffer.state.clear_depth = depth - 0.01;
+        buffer.clear();
+        assert!(buffer.z_buffer.iter().all(|&z| z == depth - 0.01));
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+
+        assert!(buffer.data.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_user_clip_plane_cuts_sphere_in_half() {
+        use std::f32::consts::PI;
+        let (rings, segments) = (12, 24);
+        let point = |ring: usize, segment: usize| {
+            let theta = PI * ring as f32 / rings as f32;
+            let phi = 2.0 * PI * segment as f32 / segments as f32;
+            let n = Vector3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                -theta.sin() * phi.sin(),
+            );
+            let mut v = test_triangle(n).v0;
+            v.position = Vector4::new(n.x, n.y, n.z, 1.0);
+            return v;
+        };
+        let mut mesh = Mesh::new();
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let (a, b) = (point(ring, segment), point(ring + 1, segment));
+                let (c, d) = (point(ring + 1, segment + 1), point(ring, segment + 1));
+                for &(v0, v1, v2) in [(a, b, d), (d, b, c)].iter() {
+                    mesh.faces.push(Face {
+                        v0,
+                        v1,
+                        v2,
+                        material_id: NO_MATERIAL,
+                    });
+                }
+            }
+        }
+        let whole = render_test_mesh(&mut mesh);
+
+        // Keep the part of the sphere centered at (0, 0, -3) to the right of a plane through
+        // the eye. The plane is seen edge on, as a vertical line 0.75 pixels to the right of
+        // the middle of the buffer, clear of the pixel centers
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        let slope = 0.75 / 20.0 / buffer.projection_matrix()[(0, 0)];
+        buffer.state.clip_plane = Some(Vector4::new(1.0, 0.0, slope, 0.0));
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+
+        let mut cut_rows = 0;
+        for y in 0..30 {
+            let covered = |b: &DisplayBuffer, x: usize| pixel(b, x, y)[3] != 0;
+            for x in 0..40 {
+                let expected = x > 20 && covered(&whole, x);
+                assert_eq!(covered(&buffer, x), expected, "pixel ({}, {})", x, y);
+            }
+            if covered(&whole, 19) {
+                cut_rows += 1;
+            }
+        }
+        assert!(cut_rows > 5);
+    }
+
+    #[test]
+    fn test_packed_depth_matches_full_precision() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let full = render_test_mesh(&mut mesh);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        buffer.enable_packed_depth();
+
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+
+        assert!(buffer.z_buffer.is_empty());
+        assert_eq!(buffer.packed_depth.as_ref().unwrap().len(), 40 * 30);
+        assert_eq!(buffer.data, full.data);
+        assert_eq!(buffer.sample_depth(0, 0), CLEAR_DEPTH);
+        let (z, expected) = (buffer.sample_depth(20, 18), full.sample_depth(20, 18));
+        assert!((z - expected).abs() <= 2.0 / 65534.0);
+        buffer.clear();
+        assert!(buffer.packed_depth.unwrap().iter().all(|&z| z == u16::MAX));
+    }
+
+    #[test]
+    fn test_depth_range_maps_stored_depth() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let buffer = render_test_mesh(&mut mesh);
+        let ndc = buffer.z_buffer[(30 - 18 - 1) * 40 + 20];
+
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        buffer.state.depth_range = Some((0.0, 1.0));
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+        let mapped = buffer.z_buffer[(30 - 18 - 1) * 40 + 20];
+
+        assert!(ndc > -1.0 && ndc < 1.0);
+        assert!((mapped - (ndc + 1.0) * 0.5).abs() < 1e-6);
+        assert_eq!(buffer.map_depth(-1.0), 0.0);
+        assert_eq!(buffer.map_depth(1.0), 1.0);
+    }
+
+    #[test]
+    fn test_validate_winding_reports_mismatching_normals() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, -1.0)));
+
+        assert_eq!(mesh.validate_winding(), vec![1]);
+
+        mesh.winding = Winding::Clockwise;
+        assert_eq!(mesh.validate_winding(), vec![0]);
+    }
+
+    #[test]
+    fn test_compute_tangents_are_perpendicular_to_normals() {
+        let mut face = test_triangle(Vector3::new(0.3, 0.0, 1.0).normalize());
+        face.v0.uv = Vector2::new(0.5, 1.0);
+        face.v1.uv = Vector2::new(0.0, 0.0);
+        face.v2.uv = Vector2::new(1.0, 0.0);
+        face.v2.normal = Vector3::new(-0.2, 0.1, 1.0).normalize();
+        let mut mesh = Mesh::new();
+        mesh.faces.push(face);
+
+        mesh.compute_tangents();
+
+        let scale = Matrix4::new_nonuniform_scaling(&Vector3::new(3.0, 1.0, 0.5));
+        let rotation = Matrix4::from_euler_angles(0.3, 0.7, 0.0);
+        let transformed = mesh.faces[0].transform(rotation * scale);
+        for face in [mesh.faces[0], transformed].iter() {
+            for v in [face.v0, face.v1, face.v2].iter() {
+                assert!(v.tangent.dot(&v.normal.normalize()).abs() < 1e-5);
+                assert!((v.tangent.norm() - 1.0).abs() < 1e-5);
+            }
+        }
+        assert!(mesh.faces[0].v1.tangent.x > 0.9);
+    }
+
+    #[test]
+    fn test_normal_map_shades_bumps_that_follow_the_light() {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: Vector4::new(x, y, 0.0, 1.0),
+            color: FloatColor::WHITE,
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            uv: Vector2::new((x + 1.0) * 0.5, (y + 1.0) * 0.5),
+            tangent: Vector3::zeros(),
+        };
+        let (a, b, c, d) = (
+            vertex(-1.0, -1.0),
+            vertex(1.0, -1.0),
+            vertex(1.0, 1.0),
+            vertex(-1.0, 1.0),
+        );
+        let mut mesh = Mesh::new();
+        for &(v0, v1, v2) in [(a, b, c), (a, c, d)].iter() {
+            mesh.faces.push(Face {
+                v0,
+                v1,
+                v2,
+                material_id: 0,
+            });
+        }
+        mesh.compute_tangents();
+        // The left half of the quad faces left and the right half faces right
+        let tilt = |r: u8| Color {
+            r,
+            g: 128,
+            b: 218,
+            a: 255,
+        };
+        let normal_map = Texture::new(2, 1, &[tilt(38), tilt(218)]);
+        mesh.textures.push(normal_map);
+        let black = Color::BLACK;
+        mesh.add_material(Material {
+            ambient: black,
+            diffuse: Color::WHITE,
+            specular: black,
+            shininess: 1.0,
+            normal_map: Some(0),
+        });
+        let brightness = |mesh: &mut Mesh, light_x: f32| {
+            mesh.light_position = Some(Vector3::new(light_x, 0.0, -1.0));
+            let buffer = render_test_mesh(mesh);
+            return (pixel(&buffer, 16, 15)[0], pixel(&buffer, 24, 15)[0]);
+        };
+
+        let (left, right) = brightness(&mut mesh, -5.0);
+        assert!(left > right + 50, "{} {}", left, right);
+        let (left, right) = brightness(&mut mesh, 5.0);
+        assert!(right > left + 50, "{} {}", left, right);
+
+        mesh.normal_mapping = false;
+        let (left, right) = brightness(&mut mesh, 5.0);
+        assert!(left.abs_diff(right) < 20, "{} {}", left, right);
+    }
+
+    #[test]
+    fn test_computed_normals_are_flat_or_averaged_over_positions() {
+        // Two faces of a roof meeting at the ridge along the x axis
+        let mut mesh = Mesh::new();
+        let mut front = test_triangle(Vector3::zeros());
+        front.v0.position = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        front.v1.position = Vector4::new(0.0, -1.0, 1.0, 1.0);
+        front.v2.position = Vector4::new(1.0, -1.0, 1.0, 1.0);
+        let mut back = front;
+        back.v1.position = Vector4::new(1.0, -1.0, -1.0, 1.0);
+        back.v2.position = Vector4::new(0.0, -1.0, -1.0, 1.0);
+        mesh.faces = vec![front, back];
+
+        mesh.compute_flat_normals();
+
+        let slope = 0.5f32.sqrt();
+        assert!((mesh.faces[0].v2.normal - Vector3::new(0.0, slope, slope)).norm() < 1e-5);
+        assert!((mesh.faces[1].v0.normal - Vector3::new(0.0, slope, -slope)).norm() < 1e-5);
+        assert!(mesh.validate_winding().is_empty());
+
+        mesh.compute_smooth_normals();
+
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        assert!((mesh.faces[0].v0.normal - up).norm() < 1e-5);
+        assert!((mesh.faces[1].v0.normal - up).norm() < 1e-5);
+        assert!((mesh.faces[0].v1.normal - Vector3::new(0.0, slope, slope)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn test_invert_normals_fixes_inward_normals() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, -1.0)));
+
+        mesh.invert_normals();
+
+        assert_eq!(mesh.faces[1].v2.normal, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(mesh.validate_winding(), vec![0]);
+    }
+
+    #[test]
+    fn test_bounds_cache_is_dropped_by_geometry_changes() {
+        let mut mesh = Mesh::new();
+        assert_eq!(mesh.bounds(), None);
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let bounds = (Vector3::new(-0.5, 0.0, 0.0), Vector3::new(0.5, 1.0, 0.0));
+        assert_eq!(mesh.bounds(), Some(bounds));
+
+        mesh.apply_transform(Matrix4::new_scaling(2.0));
+        assert_eq!(mesh.bounds(), Some((bounds.0 * 2.0, bounds.1 * 2.0)));
+
+        // Moving a vertex in place keeps the stale box until it is recomputed
+        mesh.faces[0].v1.position.y = 4.0;
+        assert_eq!(mesh.bounds().unwrap().1.y, 2.0);
+        assert_eq!(mesh.recompute_bounds().unwrap().1.y, 4.0);
+        assert_eq!(mesh.bounds().unwrap().1.y, 4.0);
+    }
+
+    #[test]
+    fn test_clip_space_bounds_corners() {
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let translation = Matrix4::new_translation(&Vector3::new(0.0, 0.0, -2.0));
+
+        let corners = mesh.clip_space_bounds(translation);
+
+        assert_eq!(corners[0], Vector4::new(-0.5, 0.0, -2.0, 1.0));
+        assert_eq!(corners[3], Vector4::new(0.5, 1.0, -2.0, 1.0));
+        assert_eq!(corners[7], corners[3]);
+        let origin = translation.column(3).into_owned();
+        assert_eq!(Mesh::new().clip_space_bounds(translation), [origin; 8]);
+    }
+
+    #[test]
+    fn test_mesh_outside_frustum_is_rejected() {
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+        let mut mesh = Mesh::new();
+        mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        let view = build_view_matrix(eye, lookat, Vector3::y());
+        let projection = buffer.projection_matrix() * view;
+        let outside = |mesh: &Mesh| {
+            let mvp = projection * mesh.model_matrix();
+            return outside_frustum(&mesh.clip_space_bounds(mvp));
+        };
+
+        mesh.position = Vector4::new(0.0, 0.0, -2.0, 1.0);
+        assert!(!outside(&mesh));
+        mesh.position = Vector4::new(0.0, 0.0, 2.0, 1.0);
+        assert!(outside(&mesh));
+        mesh.position = Vector4::new(10.0, 0.0, -2.0, 1.0);
+        assert!(outside(&mesh));
+
+        mesh.render(eye, lookat, &mut buffer);
+        assert!(buffer.data.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_max_faces_draws_an_even_subset() {
+        let mut mesh = Mesh::new();
+        for _ in 0..10 {
+            mesh.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        }
+        let vertices = Rc::new(Cell::new(0));
+        let seen = vertices.clone();
+        mesh.vertex_shader = Some(Rc::new(move |v: Vertex<Vector4<f32>>| {
+            seen.set(seen.get() + 1);
+            v
+        }));
+        let mut drawn_faces = |max_faces: Option<usize>| {
+            vertices.set(0);
+            mesh.max_faces = max_faces;
+            render_test_mesh(&mut mesh);
+            return vertices.get() / 3;
+        };
+
+        assert_eq!(drawn_faces(None), 10);
+        assert_eq!(drawn_faces(Some(3)), 3);
+        assert_eq!(drawn_faces(Some(0)), 0);
+        assert_eq!(drawn_faces(Some(20)), 10);
+    }
+
+    #[test]
+    fn test_color_add_saturates() {
+        let a = Color {
+            r: 200,
+            g: 10,
+            b: 0,
+            a: 255,
+        };
+        let b = Color {
+            r: 100,
+            g: 20,
+            b: 0,
+            a: 0,
+        };
+
+        let c = a + b;
+
+        assert_eq!((c.r, c.g, c.b, c.a), (255, 30, 0, 255));
+    }
+
+    #[test]
+    fn test_color_sub_saturates() {
+        let a = Color {
+            r: 200,
+            g: 10,
+            b: 0,
+            a: 255,
+        };
+        let b = Color {
+            r: 100,
+            g: 20,
+            b: 0,
+            a: 0,
+        };
+
+        let c = a - b;
+
+        assert_eq!((c.r, c.g, c.b, c.a), (100, 0, 0, 255));
+    }
+
+    #[test]
+    fn test_color_from_hex() {
+        let orange = Color {
+            r: 255,
+            g: 136,
+            b: 0,
+            a: 255,
+        };
+
+        assert_eq!(Color::from_hex(0xFF88_00FF), orange);
+        assert_eq!(Color::from_hex_str("#ff8800"), Ok(orange));
+        assert_eq!(
+            Color::from_hex_str("FF880080"),
+            Ok(Color { a: 128, ..orange })
+        );
+        assert_eq!(
+            Color::from_hex_str("#f00f"),
+            Err(ParseColorError::InvalidLength(4))
+        );
+        assert_eq!(
+            Color::from_hex_str("#ff88zz"),
+            Err(ParseColorError::InvalidDigit('z'))
+        );
+        assert_eq!(
+            Color::from_hex_str("+ff8800"),
+            Err(ParseColorError::InvalidDigit('+'))
+        );
+    }
+
+    #[test]
+    fn test_color_barycentric_blend() {
+        let red = Color {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let green = Color {
+            r: 0,
+            g: 255,
+            b: 0,
+            a: 255,
+        };
+        let blue = Color {
+            r: 0,
+            g: 0,
+            b: 255,
+            a: 0,
+        };
+
+        let c = 0.5 * red + 0.25 * green + blue * 0.25;
+
+        assert_eq!((c.r, c.g, c.b, c.a), (128, 64, 64, 192));
+    }
+
+    #[test]
+    fn test_clear_fills_vertical_gradient() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let black = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(2, 3, 4);
+        buffer.state.background = Background::VerticalGradient(white, black);
+
+        buffer.clear();
+
+        assert_eq!(pixel(&buffer, 1, 2), [255, 255, 255, 255]);
+        assert_eq!(pixel(&buffer, 0, 1), [128, 128, 128, 255]);
+        assert_eq!(pixel(&buffer, 1, 0), [0, 0, 0, 255]);
+        assert_eq!(count_written_pixels(&buffer), 0);
+    }
+
+    #[test]
+    fn test_unlit_draws_vertex_colors_unchanged() {
+        let mut mesh = Mesh::new();
+        let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        let color = FloatColor {
+            r: 0.2,
+            g: 0.4,
+            b: 0.6,
+            a: 1.0,
+        };
+        face.v0.color = color;
+        face.v1.color = color;
+        face.v2.color = color;
+        mesh.faces.push(face);
+        // The light grazes the face, lit shading would be nearly black
+        mesh.light_position = Some(Vector3::new(10.0, 0.5, -3.0));
+        mesh.shading_model = ShadingModel::Unlit;
+
+        let buffer = render_test_mesh(&mut mesh);
+
+        assert_eq!(pixel(&buffer, 20, 16), [51, 102, 153, 255]);
+    }
+
+    #[test]
+    fn test_toon_shading_quantizes_brightness() {
+        let toon = ShadingModel::Toon { bands: 3 };
+
+        assert_eq!(toon.apply(-0.5), 0.0);
+        assert_eq!(toon.apply(0.0), 0.0);
+        assert_eq!(toon.apply(0.2), 1.0 / 3.0);
+        assert_eq!(toon.apply(0.5), 2.0 / 3.0);
+        assert_eq!(toon.apply(0.9), 1.0);
+        assert_eq!(toon.apply(1.0), 1.0);
+        assert_eq!(ShadingModel::Smooth.apply(0.5), 0.5);
+    }
+
+    #[test]
+    fn test_depth_to_grayscale() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let background = Color {
+            r: 0,
+            g: 0,
+            b: 128,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(3, 1, 4);
+        buffer.set_pixel(0, 0, 1.0, white);
+        buffer.set_pixel(1, 0, 3.0, white);
+
+        let image = buffer.depth_to_grayscale(3.0, 1.0, background);
+
+        assert_eq!(image.data[0..4], [0, 0, 0, 255]);
+        assert_eq!(image.data[4..8], [255, 255, 255, 255]);
+        assert_eq!(image.data[8..12], [0, 0, 128, 255]);
+    }
+
+    #[test]
+    fn test_set_pixel_ignores_out_of_range_coordinates() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(3, 2, 4);
+
+        assert!(!buffer.set_pixel(3, 0, 1.0, white));
+        assert!(!buffer.set_pixel(0, 2, 1.0, white));
+        assert!(buffer.set_pixel(2, 1, 1.0, white));
+        assert!(!buffer.set_pixel(2, 1, 2.0, white));
+        assert_eq!(count_written_pixels(&buffer), 1);
+    }
+
+    #[test]
+    fn test_pixel_index_at_boundary_coordinates() {
+        let mut buffer = DisplayBuffer::new(3, 2, 4);
+
+        assert_eq!(buffer.pixel_index(0, 1), Some(0));
+        assert_eq!(buffer.pixel_index(2, 0), Some(5));
+        for &(x, y) in [(3, 0), (0, 2), (3, 2), (usize::MAX, 0), (0, usize::MAX)].iter() {
+            assert_eq!(buffer.pixel_index(x, y), None);
+            assert!(!buffer.set_pixel(x, y, 0.0, Color::WHITE));
+            assert!(buffer.get_pixel(x, y).is_none());
+        }
+        assert_eq!(count_written_pixels(&buffer), 0);
+
+        let mut empty = DisplayBuffer::new(0, 0, 4);
+        assert_eq!(empty.pixel_index(0, 0), None);
+        assert!(!empty.set_pixel(0, 0, 0.0, Color::WHITE));
+    }
+
+    #[test]
+    fn test_shade_uses_material_over_vertex_color() {
+        let black = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let material = Material {
+            ambient: Color {
+                r: 20,
+                g: 20,
+                b: 20,
+                a: 255,
+            },
+            diffuse: Color {
+                r: 0,
+                g: 200,
+                b: 0,
+                a: 255,
+            },
+            specular: black,
+            shininess: 1.0,
+            normal_map: None,
+        };
+
+        let unlit = shade(white.into(), Some(&material), 0.0).into_color();
+        assert_eq!((unlit.r, unlit.g, unlit.b), (20, 20, 20));
+
+        let lit = shade(white.into(), Some(&material), 1.0).into_color();
+        assert_eq!((lit.r, lit.g, lit.b), (20, 220, 20));
+
+        let vertex_colored = shade(white.into(), None, 1.0).into_color();
+        assert_eq!(
+            (vertex_colored.r, vertex_colored.g, vertex_colored.b),
+            (255, 255, 255)
+        );
+    }
+
+    #[test]
+    fn test_float_color_into_color_clamps_and_rounds() {
+        let c = FloatColor {
+            r: 2.0,
+            g: -1.0,
+            b: 0.5,
+            a: 1.0,
+        };
+
+        let c = c.into_color();
+
+        assert_eq!((c.r, c.g, c.b, c.a), (255, 0, 128, 255));
+    }
+
+    #[test]
+    fn test_float_color_is_not_limited_to_full_intensity() {
+        let c = FloatColor::from(Color {
+            r: 255,
+            g: 51,
+            b: 0,
+            a: 255,
+        });
+
+        let c = c * 2.0 + c;
+
+        assert_eq!((c.r, c.g, c.b), (3.0, 0.6, 0.0));
+    }
+
+    #[test]
+    fn test_overdraw_counts_depth_rejected_writes() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(2, 1, 4);
+        buffer.enable_overdraw_counter();
+
+        buffer.set_pixel(0, 0, 2.0, white);
+        buffer.set_pixel(0, 0, 1.0, white);
+        buffer.set_pixel(0, 0, 3.0, white);
+        buffer.set_pixel(1, 0, 1.0, white);
+
+        assert_eq!(buffer.overdraw.as_ref().unwrap()[..], [3, 1]);
+
+        let heatmap = buffer.overdraw_heatmap().unwrap();
+        assert_eq!(heatmap.data[0..4], [255, 0, 0, 255]);
+        assert_eq!(heatmap.data[4..8], [0, 0, 255, 255]);
+
+        buffer.clear();
+        assert_eq!(buffer.overdraw.as_ref().unwrap()[..], [0, 0]);
+    }
+
+    #[test]
+    fn test_normal_target_keeps_normals_of_nearest_faces() {
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+        let mut near = Mesh::new();
+        near.faces.push(test_triangle(Vector3::new(0.0, 0.0, 1.0)));
+        near.position = Vector4::new(0.0, 0.0, -3.0, 1.0);
+        let mut far = Mesh::new();
+        far.faces.push(test_triangle(Vector3::new(0.6, 0.0, 0.8)));
+        far.position = Vector4::new(0.0, 0.0, -4.0, 1.0);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        buffer.enable_normal_target();
+
+        near.render(eye, lookat, &mut buffer);
+        far.render(eye, lookat, &mut buffer);
+
+        let normal_at = |buffer: &DisplayBuffer, x: usize, y: usize| {
+            buffer.normals.as_ref().unwrap()[(buffer.height - y - 1) * buffer.width + x]
+        };
+        assert!((normal_at(&buffer, 20, 18) - Vector3::new(0.0, 0.0, 1.0)).norm() < 1e-5);
+        assert_eq!(normal_at(&buffer, 0, 0), Vector3::zeros());
+
+        buffer.clear();
+        far.render(eye, lookat, &mut buffer);
+        assert!((normal_at(&buffer, 20, 17) - Vector3::new(0.6, 0.0, 0.8)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn test_sampling_clamps_to_the_edges() {
+        let red = Color {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(3, 2, 4);
+        buffer.set_pixel(0, 1, 0.5, red);
+
+        assert_eq!(buffer.sample_depth(0, 1), 0.5);
+        assert_eq!(buffer.sample_depth(-4, 7), 0.5);
+        assert_eq!(buffer.sample_depth(5, -1), CLEAR_DEPTH);
+        assert_eq!(buffer.sample_color(-1, 2), red);
+        assert_eq!(buffer.sample_color(1, 1).a, 0);
+        let empty = DisplayBuffer::new(0, 0, 4);
+        assert_eq!(empty.sample_depth(0, 0), CLEAR_DEPTH);
+        assert_eq!(empty.sample_color(-1, 1), Color::TRANSPARENT);
+    }
+
+    #[test]
+    fn test_bgra_byte_order_swaps_red_and_blue() {
+        let color = Color {
+            r: 10,
+            g: 20,
+            b: 30,
+            a: 40,
+        };
+        let mut buffer = DisplayBuffer::new(2, 1, 4);
+        buffer.byte_order = ByteOrder::Bgra;
+
+        buffer.set_pixel(1, 0, 0.0, color);
+
+        assert_eq!(buffer.data[4..8], [30, 20, 10, 40]);
+        assert_eq!(buffer.get_pixel(1, 0), Some(color));
+        assert_eq!(buffer.sample_color(1, 0), color);
+    }
+
+    #[test]
+    fn test_overdraw_heatmap_requires_counter() {
+        let buffer = DisplayBuffer::new(2, 1, 4);
+
+        assert!(buffer.overdraw_heatmap().is_none());
+    }
+
+    #[test]
+    fn test_srgb_conversion_round_trips() {
+        for &c in [0.0, 0.002, 0.2, 0.5, 1.0].iter() {
+            let encoded = ColorSpace::Srgb.from_linear(c);
+            assert!((ColorSpace::Srgb.to_linear(encoded) - c).abs() < 1e-5);
+        }
+        assert!((ColorSpace::Srgb.from_linear(0.5) - 0.7354).abs() < 1e-3);
+        assert_eq!(ColorSpace::Linear.from_linear(0.5), 0.5);
+    }
+
+    #[test]
+    fn test_srgb_buffer_encodes_shaded_colors() {
+        let mut face = test_triangle(Vector3::new(0.0, 0.0, 1.0));
+        let color = FloatColor {
+            r: 0.5,
+            g: 0.0,
+            b: 1.0,
+            a: 1.0,
+        };
+        face.v0.color = color;
+        face.v1.color = color;
+        face.v2.color = color;
+        let mut mesh = Mesh::new();
+        mesh.faces.push(face);
+        mesh.shading_model = ShadingModel::Unlit;
+        mesh.position = Vector4::new(0.0, 0.0, -3.0, 1.0);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        buffer.state.color_space = ColorSpace::Srgb;
+
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+
+        assert_eq!(buffer.get_pixel(20, 16), Some(Color::from_hex(0xBC00_FFFF)));
+    }
+
+    #[test]
+    fn test_tone_mapping_clamp() {
+        assert_eq!(ToneMapping::Clamp.apply(0.5), 0.5);
+        assert_eq!(ToneMapping::Clamp.apply(2.0), 1.0);
+        assert_eq!(ToneMapping::Clamp.apply(4.0), 1.0);
+        assert_eq!(ToneMapping::Clamp.apply(-1.0), 0.0);
+    }
+
+    #[test]
+    fn test_tone_mapping_reinhard_preserves_highlights() {
+        let highlight = ToneMapping::Reinhard.apply(2.0);
+        let brighter_highlight = ToneMapping::Reinhard.apply(4.0);
+
+        assert!(highlight < brighter_highlight);
+        assert!(brighter_highlight < 1.0);
+        assert_eq!(ToneMapping::Reinhard.apply(0.0), 0.0);
+    }
 }
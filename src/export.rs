@@ -0,0 +1,575 @@
+// Saving display buffers to image files
+//
+// The PNG encoder is intentionally minimal: the pixel data is stored in uncompressed deflate
+// blocks, which every PNG decoder accepts, so no compression library is needed. The binary
+// PPM format is simpler still and is used for the golden images of the rendering tests.
+//
+// Animations are written as GIF. The frames are mapped to a fixed palette of 256 colors with
+// 3 bits of red and green and 2 bits of blue, which needs no palette search between frames,
+// and compressed with the variable length LZW code of the format.
+//
+// `DisplayBuffer::save_png` is not available on wasm32, where there is no file system. Use
+// `write_png` instead.
+
+use core::{ByteOrder, DisplayBuffer};
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::BufWriter;
+use std::io::{self, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+/// Maximum amount of data in a single stored (uncompressed) deflate block
+const MAX_STORED_BLOCK: usize = 65535;
+
+/// Smallest LZW code size of the GIF image data, one code per palette index
+const GIF_MIN_CODE_SIZE: u8 = 8;
+
+/// Largest LZW code size of the GIF image data
+const GIF_MAX_CODE_SIZE: u8 = 12;
+
+/// Maximum amount of data in a GIF data sub-block
+const GIF_MAX_SUB_BLOCK: usize = 255;
+
+/// CRC-32 (ISO 3309) checksum used by PNG chunks
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    return !crc;
+}
+
+/// Adler-32 checksum used by zlib streams
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    return (b << 16) | a;
+}
+
+/// Wrap data into a zlib stream made of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len() / MAX_STORED_BLOCK + 1;
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // CMF: deflate with 32K window, FLG: no dictionary, fastest compression level
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(if last { 0x01 } else { 0x00 });
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    return out;
+}
+
+/// Write a single PNG chunk (length, type, data and CRC)
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let mut crc_data = Vec::with_capacity(4 + data.len());
+    crc_data.extend_from_slice(kind);
+    crc_data.extend_from_slice(data);
+    out.write_all(&crc32(&crc_data).to_be_bytes())?;
+    return Ok(());
+}
+
+/// Index of the color in the fixed 3-3-2 palette of the GIF frames
+fn gif_palette_index(r: u8, g: u8, b: u8) -> u8 {
+    let level = |c: u8, max: u32| (c as u32 * max + 127) / 255;
+    return (level(r, 7) << 5 | level(g, 7) << 2 | level(b, 3)) as u8;
+}
+
+/// RGB triples of the fixed 3-3-2 palette, see `gif_palette_index`
+fn gif_palette() -> Vec<u8> {
+    let mut palette = Vec::with_capacity(256 * 3);
+    for index in 0..256u32 {
+        palette.push(((index >> 5) * 255 / 7) as u8);
+        palette.push(((index >> 2 & 7) * 255 / 7) as u8);
+        palette.push(((index & 3) * 255 / 3) as u8);
+    }
+    return palette;
+}
+
+/// Compress palette indices with the LZW variant of GIF
+///
+/// The codes are packed starting from the least significant bit. The code size grows from
+/// 9 to 12 bits as the table fills up, a full table is reset with a clear code.
+fn gif_lzw(indices: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << GIF_MIN_CODE_SIZE;
+    let end = clear + 1;
+    let mut out = Vec::new();
+    let (mut bits, mut bit_count) = (0u32, 0u8);
+    let mut emit = |code: u16, size: u8, out: &mut Vec<u8>| {
+        bits |= (code as u32) << bit_count;
+        bit_count += size;
+        while bit_count >= 8 {
+            out.push(bits as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = GIF_MIN_CODE_SIZE + 1;
+    let mut next = end + 1;
+    emit(clear, size, &mut out);
+    let mut indices = indices.iter();
+    let mut prefix = match indices.next() {
+        Some(&index) => index as u16,
+        None => {
+            emit(end, size, &mut out);
+            emit(0, 7, &mut out);
+            return out;
+        }
+    };
+    for &index in indices {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        emit(prefix, size, &mut out);
+        if next < 1 << GIF_MAX_CODE_SIZE {
+            table.insert((prefix, index), next);
+            if next == 1 << size && size < GIF_MAX_CODE_SIZE {
+                size += 1;
+            }
+            next += 1;
+        } else {
+            emit(clear, size, &mut out);
+            table.clear();
+            size = GIF_MIN_CODE_SIZE + 1;
+            next = end + 1;
+        }
+        prefix = index as u16;
+    }
+    emit(prefix, size, &mut out);
+    emit(end, size, &mut out);
+    // Flush the last partial byte
+    emit(0, 7, &mut out);
+    return out;
+}
+
+/// Split data into GIF sub-blocks, terminated by an empty block
+fn write_sub_blocks<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    for block in data.chunks(GIF_MAX_SUB_BLOCK) {
+        out.write_all(&[block.len() as u8])?;
+        out.write_all(block)?;
+    }
+    return out.write_all(&[0]);
+}
+
+/// Encoder of an animated GIF, one display buffer per frame
+///
+/// The animation loops forever. Each frame covers the whole image and replaces the previous
+/// one, its colors are reduced to a fixed palette of 256 colors and alpha is ignored.
+pub struct GifEncoder<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> GifEncoder<W> {
+    /// Start an animation by writing the header, the palette and the loop extension
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - Destination of the GIF data
+    /// * `width` - Width of the frames in pixels, at most 65535
+    /// * `height` - Height of the frames in pixels, at most 65535
+    pub fn new(mut out: W, width: usize, height: usize) -> io::Result<GifEncoder<W>> {
+        if width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "GIF images are at most 65535 pixels wide and high",
+            ));
+        }
+
+        out.write_all(b"GIF89a")?;
+        out.write_all(&(width as u16).to_le_bytes())?;
+        out.write_all(&(height as u16).to_le_bytes())?;
+        // Global palette of 256 colors with 8 bits per channel, background color and aspect
+        out.write_all(&[0xF7, 0, 0])?;
+        out.write_all(&gif_palette())?;
+        // NETSCAPE2.0 application extension, a loop count of 0 repeats forever
+        out.write_all(&[0x21, 0xFF, 11])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[3, 1, 0, 0, 0])?;
+        return Ok(GifEncoder { out, width, height });
+    }
+
+    /// Append the contents of a display buffer as the next frame
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Frame to add, of the size given to `new` with at least 3 bytes per pixel
+    /// * `delay` - Time to show the frame in hundredths of a second
+    pub fn write_frame(&mut self, buffer: &DisplayBuffer, delay: u16) -> io::Result<()> {
+        if buffer.width != self.width || buffer.height != self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "GIF frames must have the size of the image",
+            ));
+        }
+        let indices: Vec<u8> = buffer
+            .to_rgba8()?
+            .chunks(4)
+            .map(|p| gif_palette_index(p[0], p[1], p[2]))
+            .collect();
+
+        // Graphic control extension: keep the frame when the next one is drawn
+        self.out.write_all(&[0x21, 0xF9, 4, 0x04])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+        // Image descriptor of the whole image without a local palette
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        self.out.write_all(&[0, GIF_MIN_CODE_SIZE])?;
+        return write_sub_blocks(&mut self.out, &gif_lzw(&indices));
+    }
+
+    /// End the animation and return the destination
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        return Ok(self.out);
+    }
+}
+
+impl DisplayBuffer {
+    /// Encode the contents of the buffer as a PNG image
+    ///
+    /// The pixel data is expected to be RGBA (4 bytes per pixel) or RGB (3 bytes per pixel).
+    /// The image is always stored as RGB(A), so a buffer in BGRA order has its red and blue
+    /// channels swapped back. The top row of the image is the top edge of the display.
+    pub fn write_png<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let color_type = match self.bpp {
+            3 => 2,
+            4 => 6,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "PNG export supports only RGB and RGBA buffers",
+                ));
+            }
+        };
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // Bit depth, color type, compression, filter and interlace methods
+        header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+        // The rows of the pixel data are already ordered from top to bottom, each row is
+        // prefixed with filter type 0 (none)
+        let stride = self.width * self.bpp;
+        let mut raw = Vec::with_capacity((stride + 1) * self.height);
+        for row in self.data.chunks(stride) {
+            raw.push(0);
+            let start = raw.len();
+            raw.extend_from_slice(row);
+            if self.byte_order == ByteOrder::Bgra {
+                for pixel in raw[start..].chunks_mut(self.bpp) {
+                    pixel.swap(0, 2);
+                }
+            }
+        }
+
+        out.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'])?;
+        write_chunk(out, b"IHDR", &header)?;
+        write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+        write_chunk(out, b"IEND", &[])?;
+        return Ok(());
+    }
+
+    /// Encode the contents of the buffer as a binary PPM (P6) image
+    ///
+    /// PPM has no alpha channel, so only the red, green and blue channels are written, in
+    /// this order for both byte orders. The top row of the image is the top edge of the
+    /// display. Buffers with fewer than 3 bytes per pixel are rejected.
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if self.bpp < 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "PPM export needs at least 3 bytes per pixel",
+            ));
+        }
+
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let mut raw = Vec::with_capacity(self.num_pixels() * 3);
+        for pixel in self.data.chunks(self.bpp) {
+            match self.byte_order {
+                ByteOrder::Rgba => raw.extend_from_slice(&pixel[0..3]),
+                ByteOrder::Bgra => raw.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]),
+            }
+        }
+        return out.write_all(&raw);
+    }
+
+    /// Copy the pixels of the buffer into a tightly packed RGBA image, 4 bytes per pixel
+    ///
+    /// The rows are ordered from the top edge of the display to the bottom one, so the
+    /// result is already in screen orientation and in the layout of `image::RgbaImage`:
+    /// `RgbaImage::from_raw(width, height, buffer.to_rgba8())` wraps it without copying.
+    /// Buffers in BGRA order have their red and blue channels swapped back and RGB buffers
+    /// get an opaque alpha. Buffers with fewer than 3 bytes per pixel are rejected.
+    pub fn to_rgba8(&self) -> io::Result<Vec<u8>> {
+        if self.bpp < 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "RGBA export needs at least 3 bytes per pixel",
+            ));
+        }
+
+        let mut raw = Vec::with_capacity(self.num_pixels() * 4);
+        for pixel in self.data.chunks(self.bpp) {
+            let alpha = if self.bpp > 3 { pixel[3] } else { 255 };
+            match self.byte_order {
+                ByteOrder::Rgba => raw.extend_from_slice(&[pixel[0], pixel[1], pixel[2], alpha]),
+                ByteOrder::Bgra => raw.extend_from_slice(&[pixel[2], pixel[1], pixel[0], alpha]),
+            }
+        }
+        return Ok(raw);
+    }
+
+    /// Save the contents of the buffer to a PNG file, see `write_png` for the byte order
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file to create
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_png(&mut out)?;
+        return out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{default_triangle, Color};
+    use na::{Vector3, Vector4};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_zlib_stored_splits_blocks() {
+        let data = vec![7u8; MAX_STORED_BLOCK + 10];
+
+        let stream = zlib_stored(&data);
+
+        assert_eq!(stream.len(), 2 + 5 + MAX_STORED_BLOCK + 5 + 10 + 4);
+        assert_eq!(stream[2], 0x00);
+        assert_eq!(stream[2 + 5 + MAX_STORED_BLOCK], 0x01);
+    }
+
+    #[test]
+    fn test_write_png_layout() {
+        let buffer = DisplayBuffer::new(2, 3, 4);
+        let mut png = Vec::new();
+
+        buffer.write_png(&mut png).unwrap();
+
+        assert_eq!(
+            png[0..8],
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']
+        );
+        assert_eq!(png[12..16], *b"IHDR");
+        assert_eq!(png[16..20], 2u32.to_be_bytes());
+        assert_eq!(png[20..24], 3u32.to_be_bytes());
+        assert_eq!(png[png.len() - 8..png.len() - 4], *b"IEND");
+    }
+
+    #[test]
+    fn test_write_png_stores_bgra_buffers_as_rgba() {
+        let color = Color {
+            r: 200,
+            g: 100,
+            b: 50,
+            a: 255,
+        };
+        let mut rgba = DisplayBuffer::new(3, 2, 4);
+        let mut bgra = DisplayBuffer::new(3, 2, 4);
+        bgra.byte_order = ByteOrder::Bgra;
+        rgba.set_pixel(1, 1, 0.0, color);
+        bgra.set_pixel(1, 1, 0.0, color);
+        let (mut rgba_png, mut bgra_png) = (Vec::new(), Vec::new());
+
+        rgba.write_png(&mut rgba_png).unwrap();
+        bgra.write_png(&mut bgra_png).unwrap();
+
+        assert_ne!(rgba.data, bgra.data);
+        assert_eq!(rgba_png, bgra_png);
+    }
+
+    #[test]
+    fn test_write_ppm_drops_alpha() {
+        let mut buffer = DisplayBuffer::new(2, 1, 4);
+        buffer.byte_order = ByteOrder::Bgra;
+        buffer.set_pixel(1, 0, 0.0, Color::from_hex(0x1020_3040));
+        let mut ppm = Vec::new();
+
+        buffer.write_ppm(&mut ppm).unwrap();
+
+        assert_eq!(ppm[..11], *b"P6\n2 1\n255\n");
+        assert_eq!(ppm[11..], [0, 0, 0, 0x10, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn test_to_rgba8_is_in_screen_orientation() {
+        let mut rgb = DisplayBuffer::new(2, 2, 3);
+        let mut bgra = DisplayBuffer::new(2, 2, 4);
+        bgra.byte_order = ByteOrder::Bgra;
+        // Bottom right pixel of the display, the last pixel of the image
+        rgb.data[9..].copy_from_slice(&[0x10, 0x20, 0x30]);
+        bgra.set_pixel(1, 0, 0.0, Color::from_hex(0x1020_3040));
+
+        let rgb_image = rgb.to_rgba8().unwrap();
+        let bgra_image = bgra.to_rgba8().unwrap();
+
+        assert_eq!(rgb_image.len(), 16);
+        assert_eq!(rgb_image[..4], [0, 0, 0, 255]);
+        assert_eq!(rgb_image[12..], [0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(bgra_image[12..], [0x10, 0x20, 0x30, 0x40]);
+        assert!(DisplayBuffer::new(2, 2, 1).to_rgba8().is_err());
+    }
+
+    /// Decode the LZW data of a GIF frame back into palette indices
+    fn decode_gif_lzw(data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << GIF_MIN_CODE_SIZE;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = GIF_MIN_CODE_SIZE + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        let (mut bits, mut bit_count) = (0u32, 0u8);
+        let mut bytes = data.iter();
+        loop {
+            while bit_count < size {
+                bits |= (*bytes.next().unwrap() as u32) << bit_count;
+                bit_count += 8;
+            }
+            let code = (bits & ((1 << size) - 1)) as usize;
+            bits >>= size;
+            bit_count -= size;
+
+            if code == clear {
+                table = (0..clear).map(|i| vec![i as u8]).collect();
+                table.extend_from_slice(&[Vec::new(), Vec::new()]);
+                size = GIF_MIN_CODE_SIZE + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(p)) => [p.clone(), vec![p[0]]].concat(),
+                (None, None) => panic!("Code {} is not in the table", code),
+            };
+            if let Some(p) = previous {
+                table.push([p, vec![entry[0]]].concat());
+                if table.len() == 1 << size && size < GIF_MAX_CODE_SIZE {
+                    size += 1;
+                }
+            }
+            out.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_gif_lzw_round_trip_through_table_resets() {
+        // Enough distinct runs to fill the code table several times
+        let indices: Vec<u8> = (0..40_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8 & (i as u8 | 3))
+            .collect();
+
+        assert_eq!(decode_gif_lzw(&gif_lzw(&indices)), indices);
+        assert_eq!(decode_gif_lzw(&gif_lzw(&[5; 1000])), vec![5; 1000]);
+        assert!(decode_gif_lzw(&gif_lzw(&[])).is_empty());
+    }
+
+    #[test]
+    fn test_gif_encoder_layout() {
+        let mut buffer = DisplayBuffer::new(3, 2, 4);
+        buffer.set_pixel(0, 1, 0.0, Color::from_hex(0xFF00_00FF));
+        let mut encoder = GifEncoder::new(Vec::new(), 3, 2).unwrap();
+
+        encoder.write_frame(&buffer, 4).unwrap();
+        encoder.write_frame(&buffer, 4).unwrap();
+        let gif = encoder.finish().unwrap();
+
+        assert_eq!(gif[0..6], *b"GIF89a");
+        assert_eq!(gif[6..10], [3, 0, 2, 0]);
+        let palette = &gif[13..13 + 768];
+        let red = gif_palette_index(255, 0, 0) as usize;
+        assert_eq!(palette[red * 3..red * 3 + 3], [255, 0, 0]);
+        assert_eq!(gif[13 + 768 + 3..13 + 768 + 14], *b"NETSCAPE2.0");
+        let frames = gif.windows(3).filter(|w| *w == [0x21, 0xF9, 4]).count();
+        assert_eq!(frames, 2);
+        assert_eq!(gif[gif.len() - 1], 0x3B);
+        assert!(GifEncoder::new(Vec::new(), 2, 2)
+            .unwrap()
+            .write_frame(&buffer, 4)
+            .is_err());
+    }
+
+    /// Render the default triangle and compare it to the golden image byte by byte
+    ///
+    /// Set `UPDATE_GOLDEN=1` to write the golden image again after an intended change.
+    #[test]
+    fn test_default_triangle_matches_golden_image() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/default_triangle.ppm");
+        let mut mesh = default_triangle();
+        mesh.position = Vector4::new(0.0, -0.5, -2.0, 1.0);
+        let mut buffer = DisplayBuffer::new(64, 48, 4);
+        mesh.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+        let mut ppm = Vec::new();
+        buffer.write_ppm(&mut ppm).unwrap();
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(path, &ppm).unwrap();
+        }
+        let golden = std::fs::read(path).unwrap();
+        assert!(ppm == golden, "render differs from {}", path);
+    }
+}
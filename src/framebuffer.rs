@@ -0,0 +1,70 @@
+// Double buffering of the rendered frames
+//
+// The renderer draws into the back buffer while the front buffer holds the last finished
+// frame for presenting, e.g. copying it to a window or saving a screenshot. Swapping the two
+// when a frame is done keeps the presenting side from ever seeing a half drawn frame, and is
+// the point where rendering and presenting can later run on separate threads.
+
+use core::DisplayBuffer;
+use std::mem;
+
+/// Front and back display buffers of the same size
+pub struct Framebuffer {
+    front: DisplayBuffer,
+    back: DisplayBuffer,
+}
+
+impl Framebuffer {
+    /// Create a framebuffer that renders into a display buffer
+    ///
+    /// The front buffer starts as a copy of the buffer, clear it first to present an empty
+    /// frame before the first swap.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Back buffer with the size, pixel format and render settings to use
+    pub fn new(buffer: DisplayBuffer) -> Framebuffer {
+        return Framebuffer {
+            front: buffer.clone(),
+            back: buffer,
+        };
+    }
+
+    /// Last finished frame, for presenting
+    pub fn front(&self) -> &DisplayBuffer {
+        return &self.front;
+    }
+
+    /// Buffer of the frame being rendered
+    pub fn back(&mut self) -> &mut DisplayBuffer {
+        return &mut self.back;
+    }
+
+    /// Make the rendered frame the front buffer and reuse the old front for the next frame
+    ///
+    /// Only the pixel data changes places. The depth buffer, the render targets and the
+    /// settings such as the fog or the background stay with the back buffer, so changes made
+    /// to them apply to all following frames. The back buffer holds the pixels of an old
+    /// frame after the swap, clear it before rendering.
+    pub fn swap(&mut self) {
+        mem::swap(&mut self.front.data, &mut self.back.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Color;
+
+    #[test]
+    fn test_swap_presents_the_rendered_frame() {
+        let mut framebuffer = Framebuffer::new(DisplayBuffer::new(4, 3, 4));
+        framebuffer.back().set_pixel(1, 1, 0.0, Color::RED);
+
+        assert!(framebuffer.front().data.iter().all(|&b| b == 0));
+        framebuffer.swap();
+
+        assert_eq!(framebuffer.front().get_pixel(1, 1).unwrap().r, 255);
+        assert!(framebuffer.back().data.iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,111 @@
+// Axis gizmo for checking the orientation of the camera and the meshes
+//
+// The gizmo draws the three world axes from an origin point: x in red, y in green and z in
+// blue. The axes are `Line3D`s, so they are depth tested against the rest of the scene.
+
+use core::{Color, DisplayBuffer, Line3D, Renderable};
+use na::{Vector3, Vector4};
+
+/// Lines along the positive world axes from a point
+pub struct AxisGizmo {
+    /// Start point of the axes in world space
+    pub origin: Vector3<f32>,
+    /// Length of each axis line
+    pub length: f32,
+}
+
+impl AxisGizmo {
+    /// Create a gizmo
+    ///
+    /// # Arguments
+    ///
+    /// * `origin` - Start point of the axes in world space
+    /// * `length` - Length of each axis line
+    pub fn new(origin: Vector3<f32>, length: f32) -> AxisGizmo {
+        return AxisGizmo { origin, length };
+    }
+
+    /// Axis lines in world space in the order x, y, z
+    pub fn lines(&self) -> [Line3D; 3] {
+        let o = self.origin;
+        let axis = |direction: Vector3<f32>, color: Color| {
+            let end = o + direction * self.length;
+            return Line3D {
+                a: Vector4::new(o.x, o.y, o.z, 1.0),
+                b: Vector4::new(end.x, end.y, end.z, 1.0),
+                color,
+            };
+        };
+        return [
+            axis(Vector3::x(), Color::RED),
+            axis(Vector3::y(), Color::GREEN),
+            axis(Vector3::z(), Color::BLUE),
+        ];
+    }
+
+    /// Render the axes into a display buffer
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    /// * `buffer` - Display buffer (render target)
+    pub fn render(&self, eye: Vector3<f32>, lookat: Vector3<f32>, buffer: &mut DisplayBuffer) {
+        let view = buffer.view_matrix(eye, lookat);
+        let view_projection = buffer.projection_matrix() * view;
+
+        for line in self.lines().iter() {
+            line.transform(view_projection).render(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_axes_point_along_world_axes() {
+        let gizmo = AxisGizmo::new(Vector3::new(1.0, 2.0, 3.0), 0.5);
+
+        let lines = gizmo.lines();
+
+        assert_eq!(lines[0].b, Vector4::new(1.5, 2.0, 3.0, 1.0));
+        assert_eq!(lines[1].b, Vector4::new(1.0, 2.5, 3.0, 1.0));
+        assert_eq!(lines[2].b, Vector4::new(1.0, 2.0, 3.5, 1.0));
+        assert!(lines
+            .iter()
+            .all(|l| l.a == Vector4::new(1.0, 2.0, 3.0, 1.0)));
+        let colors: Vec<_> = lines
+            .iter()
+            .map(|l| (l.color.r, l.color.g, l.color.b))
+            .collect();
+        assert_eq!(colors, vec![(255, 0, 0), (0, 255, 0), (0, 0, 255)]);
+    }
+
+    #[test]
+    fn test_render_draws_x_right_and_y_up() {
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        let gizmo = AxisGizmo::new(Vector3::new(0.0, 0.0, -3.0), 1.0);
+
+        gizmo.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+        );
+
+        let pixels = |r: u8, g: u8| {
+            (0..buffer.height)
+                .flat_map(|y| (0..buffer.width).map(move |x| (x, y)))
+                .filter(|&(x, y)| {
+                    let c = buffer.get_pixel(x, y).unwrap();
+                    c.a != 0 && c.r == r && c.g == g
+                })
+                .collect::<Vec<_>>()
+        };
+        let x_axis = pixels(255, 0);
+        let y_axis = pixels(0, 255);
+        assert!(!x_axis.is_empty() && x_axis.iter().all(|&(x, y)| x >= 20 && y == 15));
+        assert!(!y_axis.is_empty() && y_axis.iter().all(|&(x, y)| x == 20 && y >= 15));
+    }
+}
@@ -0,0 +1,122 @@
+// Ground plane grid for spatial reference
+//
+// The grid is a set of line segments on the y = 0 plane, centered at the world origin. The
+// segments are drawn as `Line3D`s and depth tested against the rest of the scene.
+
+use core::{Color, DisplayBuffer, Line3D, Renderable};
+use na::{Vector3, Vector4};
+
+/// Square grid of lines on the y = 0 plane
+pub struct Grid {
+    /// Distance from the origin to the edges of the grid along the x and z axes
+    pub extent: f32,
+    /// Distance between two adjacent lines
+    pub spacing: f32,
+    /// Color of the lines
+    pub color: Color,
+}
+
+impl Grid {
+    /// Create a gray grid
+    ///
+    /// # Arguments
+    ///
+    /// * `extent` - Distance from the origin to the edges of the grid
+    /// * `spacing` - Distance between two adjacent lines
+    pub fn new(extent: f32, spacing: f32) -> Grid {
+        return Grid {
+            extent,
+            spacing,
+            color: Color {
+                r: 96,
+                g: 96,
+                b: 96,
+                a: 255,
+            },
+        };
+    }
+
+    /// End points of the line segments in world space
+    ///
+    /// Lines run parallel to the x and z axes at multiples of the spacing within the extent.
+    pub fn segments(&self) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+        let mut segments = Vec::new();
+        if self.spacing <= 0.0 || self.extent < 0.0 {
+            return segments;
+        }
+
+        let n = (self.extent / self.spacing).floor() as i32;
+        for i in -n..=n {
+            let offset = i as f32 * self.spacing;
+            segments.push((
+                Vector3::new(offset, 0.0, -self.extent),
+                Vector3::new(offset, 0.0, self.extent),
+            ));
+            segments.push((
+                Vector3::new(-self.extent, 0.0, offset),
+                Vector3::new(self.extent, 0.0, offset),
+            ));
+        }
+        return segments;
+    }
+
+    /// Render the grid into a display buffer
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    /// * `buffer` - Display buffer (render target)
+    pub fn render(&self, eye: Vector3<f32>, lookat: Vector3<f32>, buffer: &mut DisplayBuffer) {
+        let view = buffer.view_matrix(eye, lookat);
+        let view_projection = buffer.projection_matrix() * view;
+
+        for (a, b) in self.segments() {
+            let line = Line3D {
+                a: Vector4::new(a.x, a.y, a.z, 1.0),
+                b: Vector4::new(b.x, b.y, b.z, 1.0),
+                color: self.color,
+            };
+            line.transform(view_projection).render(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments_cover_extent() {
+        let grid = Grid::new(2.5, 1.0);
+
+        let segments = grid.segments();
+
+        assert_eq!(segments.len(), 10);
+        assert!(segments.iter().all(|(a, b)| a.y == 0.0 && b.y == 0.0));
+        assert!(segments.iter().any(
+            |(a, b)| a == &Vector3::new(-2.0, 0.0, -2.5) && b == &Vector3::new(-2.0, 0.0, 2.5)
+        ));
+    }
+
+    #[test]
+    fn test_render_draws_lines_below_the_eye() {
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+
+        Grid::new(4.0, 1.0).render(
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, 0.0, -3.0),
+            &mut buffer,
+        );
+
+        let written = |rows: ::std::ops::Range<usize>| {
+            rows.flat_map(|y| (0..buffer.width).map(move |x| (x, y)))
+                .filter(|&(x, y)| {
+                    buffer.data[((buffer.height - y - 1) * buffer.width + x) * 4 + 3] != 0
+                })
+                .count()
+        };
+        assert!(written(0..15) > 0);
+        assert_eq!(written(20..30), 0);
+    }
+}
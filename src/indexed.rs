@@ -0,0 +1,330 @@
+// Indexed triangle geometry
+//
+// Adjacent triangles of a closed surface share most of their vertices, a face list stores
+// each shared vertex once per triangle (about six times on a typical closed mesh). Indexed
+// geometry stores every distinct vertex once and the triangles as indices into the vertices,
+// so each vertex is transformed to world space only once per draw.
+
+use core::{
+    normal_matrix, orthonormal_tangent, DisplayBuffer, DrawContext, Face, FragmentShaderFn, Mesh,
+    Vertex,
+};
+use na::{Vector3, Vector4};
+use std::collections::HashMap;
+
+/// Triangle geometry with the vertices shared by the triangles stored once
+pub struct IndexedMesh {
+    /// Distinct vertices of the triangles in local space
+    pub vertices: Vec<Vertex<Vector4<f32>>>,
+    /// Indices of the vertices of each triangle in the winding order of the triangle
+    pub triangles: Vec<[usize; 3]>,
+    /// Material id of each triangle, see `Face::material_id`
+    pub material_ids: Vec<usize>,
+}
+
+/// Bit patterns of all attributes of a vertex, equal for vertices that are the same
+fn vertex_key(v: &Vertex<Vector4<f32>>) -> [u32; 16] {
+    let p = &v.position;
+    let n = &v.normal;
+    let c = &v.color;
+    let t = &v.tangent;
+    return [
+        p.x, p.y, p.z, p.w, n.x, n.y, n.z, v.uv.x, v.uv.y, c.r, c.g, c.b, c.a, t.x, t.y, t.z,
+    ]
+    .map(f32::to_bits);
+}
+
+impl IndexedMesh {
+    /// Convert a face list into indexed geometry
+    ///
+    /// Vertices are shared when all of their attributes are equal, so vertices at the same
+    /// position with different normals (hard edges) or texture coordinates (seams) are kept
+    /// separate.
+    ///
+    /// # Arguments
+    ///
+    /// * `faces` - Faces to convert, e.g. the faces of a mesh
+    pub fn from_faces(faces: &[Face<Vector4<f32>>]) -> IndexedMesh {
+        let mut indexed = IndexedMesh {
+            vertices: Vec::new(),
+            triangles: Vec::with_capacity(faces.len()),
+            material_ids: Vec::with_capacity(faces.len()),
+        };
+        let mut indices = HashMap::new();
+
+        for face in faces.iter() {
+            let mut triangle = [0; 3];
+            for (index, v) in triangle
+                .iter_mut()
+                .zip([&face.v0, &face.v1, &face.v2].iter())
+            {
+                let vertices = &mut indexed.vertices;
+                *index = *indices.entry(vertex_key(v)).or_insert_with(|| {
+                    vertices.push(**v);
+                    vertices.len() - 1
+                });
+            }
+            indexed.triangles.push(triangle);
+            indexed.material_ids.push(face.material_id);
+        }
+
+        return indexed;
+    }
+
+    /// Expand the geometry into a face list with the vertices copied into each face
+    pub fn to_faces(&self) -> Vec<Face<Vector4<f32>>> {
+        return self
+            .triangles
+            .iter()
+            .zip(self.material_ids.iter())
+            .map(|(t, &material_id)| Face {
+                v0: self.vertices[t[0]],
+                v1: self.vertices[t[1]],
+                v2: self.vertices[t[2]],
+                material_id,
+            })
+            .collect();
+    }
+
+    /// Render the geometry with the placement, materials and render state of a mesh
+    ///
+    /// The geometry is placed, shaded and drawn with the position, rotation, materials and
+    /// render state of the mesh, the faces of the mesh are not drawn. Each vertex is
+    /// transformed to world space once, the result is the same as rendering the mesh with the
+    /// faces of `to_faces`.
+    ///
+    /// # Arguments
+    ///
+    /// * `mesh` - Mesh whose state the geometry is drawn with
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    /// * `buffer` - Display buffer (render target)
+    /// * `shader` - Colors the fragments instead of the render mode, replaces the fragment
+    ///   shader of the mesh
+    /// * `time` - Animation time in seconds passed to the shader
+    pub fn render(
+        &self,
+        mesh: &Mesh,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        buffer: &mut DisplayBuffer,
+        shader: Option<&FragmentShaderFn>,
+        time: f32,
+    ) {
+        let context = DrawContext::new(eye, lookat, buffer, shader, time);
+        let model = mesh.model_matrix();
+        let m_normal = normal_matrix(&model);
+        let m_tangent = model.fixed_slice::<na::U3, na::U3>(0, 0).into_owned();
+        let world: Vec<Vertex<Vector4<f32>>> = self
+            .vertices
+            .iter()
+            .map(|v| {
+                let normal = m_normal * v.normal;
+                return Vertex {
+                    position: model * v.position,
+                    normal,
+                    tangent: orthonormal_tangent(m_tangent * v.tangent, normal),
+                    ..*v
+                };
+            })
+            .collect();
+
+        let triangles = self.triangles.iter().zip(self.material_ids.iter());
+        for (i, (t, &material_id)) in triangles.enumerate() {
+            let face_world = Face {
+                v0: world[t[0]],
+                v1: world[t[1]],
+                v2: world[t[2]],
+                material_id,
+            };
+            let material = mesh.materials.get(material_id);
+            mesh.draw_world_face(&face_world, i, material, &context, buffer);
+        }
+    }
+}
+
+impl Mesh {
+    /// Merge the vertices of the faces that are at most `epsilon` apart
+    ///
+    /// The merged vertices get the position of the first vertex of the group in face order
+    /// and the average of the normals of the group, so a face list with a normal per face
+    /// becomes smooth shaded. The colors and texture coordinates are kept, vertices of a
+    /// group that differ in them (e.g. at uv seams) stay separate in the indexed geometry.
+    ///
+    /// Returns the faces as indexed geometry with the welded vertices shared.
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Largest distance between two positions that are merged
+    pub fn weld_vertices(&mut self, epsilon: f32) -> IndexedMesh {
+        // Positions are bucketed into cubes of the size of epsilon, the positions to merge
+        // with are then in the same or one of the neighbouring cubes
+        let size = epsilon.max(f32::MIN_POSITIVE);
+        let cell = |p: &Vector4<f32>| (p.xyz() / size).map(|c| c.floor() as i64);
+        let mut cells: HashMap<Vector3<i64>, Vec<usize>> = HashMap::new();
+        // Position and sum of the normals of each group
+        let mut groups: Vec<(Vector4<f32>, Vector3<f32>)> = Vec::new();
+        let mut group_of = Vec::with_capacity(self.faces.len() * 3);
+
+        for face in self.faces.iter() {
+            for v in [&face.v0, &face.v1, &face.v2].iter() {
+                let c = cell(&v.position);
+                let near = (0..27).find_map(|i| {
+                    let offset = Vector3::new(i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1);
+                    return cells
+                        .get(&(c + offset))?
+                        .iter()
+                        .copied()
+                        .find(|&g| (groups[g].0.xyz() - v.position.xyz()).norm() <= epsilon);
+                });
+                let group = near.unwrap_or_else(|| {
+                    groups.push((v.position, Vector3::zeros()));
+                    cells.entry(c).or_default().push(groups.len() - 1);
+                    groups.len() - 1
+                });
+                groups[group].1 += v.normal;
+                group_of.push(group);
+            }
+        }
+
+        for (face, face_groups) in self.faces.iter_mut().zip(group_of.chunks(3)) {
+            let mut vertices = [&mut face.v0, &mut face.v1, &mut face.v2];
+            for (v, &group) in vertices.iter_mut().zip(face_groups.iter()) {
+                let (position, normals) = groups[group];
+                v.position = position;
+                v.normal = normals.try_normalize(f32::EPSILON).unwrap_or(v.normal);
+                v.tangent = orthonormal_tangent(v.tangent, v.normal);
+            }
+        }
+        self.invalidate_cache();
+
+        return IndexedMesh::from_faces(&self.faces);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{FloatColor, NO_MATERIAL};
+    use na::Vector2;
+
+    /// Square of two triangles sharing the diagonal
+    fn quad() -> Vec<Face<Vector4<f32>>> {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: Vector4::new(x, y, 0.0, 1.0),
+            color: FloatColor {
+                r: x + 0.5,
+                g: y + 0.5,
+                b: 1.0,
+                a: 1.0,
+            },
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            uv: Vector2::new(x, y),
+            tangent: Vector3::zeros(),
+        };
+        let (a, b, c, d) = (
+            vertex(-0.5, -0.5),
+            vertex(0.5, -0.5),
+            vertex(0.5, 0.5),
+            vertex(-0.5, 0.5),
+        );
+        return vec![
+            Face {
+                v0: a,
+                v1: b,
+                v2: c,
+                material_id: NO_MATERIAL,
+            },
+            Face {
+                v0: a,
+                v1: c,
+                v2: d,
+                material_id: 0,
+            },
+        ];
+    }
+
+    #[test]
+    fn test_from_faces_shares_equal_vertices() {
+        let faces = quad();
+
+        let indexed = IndexedMesh::from_faces(&faces);
+
+        assert_eq!(indexed.vertices.len(), 4);
+        assert_eq!(indexed.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(indexed.material_ids, vec![NO_MATERIAL, 0]);
+        let faces_again = indexed.to_faces();
+        assert_eq!(faces_again.len(), 2);
+        assert_eq!(faces_again[1].v2.position, faces[1].v2.position);
+        assert_eq!(faces_again[1].material_id, 0);
+    }
+
+    #[test]
+    fn test_weld_vertices_shares_the_corners_of_a_cube() {
+        let mut mesh = Mesh::new();
+        // Two triangles for each side with the normal of the side, the corners of the
+        // sides are shifted by less than the welding distance
+        for axis in 0..3 {
+            for &side in [-1.0f32, 1.0].iter() {
+                let mut normal = Vector3::zeros();
+                normal[axis] = side;
+                let mut u = Vector3::zeros();
+                u[(axis + 1) % 3] = 1.0;
+                let v = normal.cross(&u);
+                let corner = |a: f32, b: f32| {
+                    let p = (normal + u * a + v * b) * 0.5 + Vector3::repeat(1e-4 * side);
+                    return Vertex {
+                        position: Vector4::new(p.x, p.y, p.z, 1.0),
+                        color: FloatColor::WHITE,
+                        normal,
+                        uv: Vector2::zeros(),
+                        tangent: Vector3::zeros(),
+                    };
+                };
+                let quad = [
+                    corner(-1.0, -1.0),
+                    corner(1.0, -1.0),
+                    corner(1.0, 1.0),
+                    corner(-1.0, 1.0),
+                ];
+                for &(i, j) in [(1, 2), (2, 3)].iter() {
+                    mesh.faces.push(Face {
+                        v0: quad[0],
+                        v1: quad[i],
+                        v2: quad[j],
+                        material_id: NO_MATERIAL,
+                    });
+                }
+            }
+        }
+        assert_eq!(IndexedMesh::from_faces(&mesh.faces).vertices.len(), 24);
+
+        let indexed = mesh.weld_vertices(1e-3);
+
+        assert_eq!(indexed.vertices.len(), 8);
+        assert_eq!(indexed.triangles.len(), 12);
+        for v in indexed.vertices.iter() {
+            let outward = v.position.xyz().normalize();
+            assert!(v.normal.dot(&outward) > 0.9);
+        }
+    }
+
+    #[test]
+    fn test_render_matches_face_list() {
+        let mut mesh = Mesh::new();
+        mesh.faces = quad();
+        mesh.position = Vector4::new(0.2, 0.0, -3.0, 1.0);
+        mesh.angle = Vector3::new(0.3, 0.4, 0.0);
+        let indexed = IndexedMesh::from_faces(&mesh.faces);
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+
+        let mut expected = DisplayBuffer::new(40, 30, 4);
+        mesh.render(eye, lookat, &mut expected);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        indexed.render(&mesh, eye, lookat, &mut buffer, None, 0.0);
+
+        assert!(expected.data.iter().any(|&c| c != 0));
+        assert_eq!(buffer.data, expected.data);
+    }
+}
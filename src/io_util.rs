@@ -0,0 +1,8 @@
+// Helpers shared by the model file readers
+
+use std::io;
+
+/// Error of a file with malformed or unsupported contents
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+}
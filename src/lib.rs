@@ -1,4 +1,29 @@
+#![allow(
+    clippy::needless_return,
+    clippy::assign_op_pattern,
+    clippy::legacy_numeric_constants,
+    clippy::new_without_default,
+    clippy::redundant_field_names
+)]
+
 extern crate nalgebra as na;
 
+pub mod animation;
+pub mod camera;
+pub mod clipping;
 pub mod core;
+pub mod export;
+pub mod framebuffer;
+pub mod gizmo;
+pub mod grid;
+pub mod indexed;
+mod io_util;
+pub mod ply;
+pub mod postprocess;
 pub mod rasterization;
+pub mod scene;
+pub mod simplify;
+pub mod stl;
+pub mod text;
+pub mod texture;
+pub mod wavefront;
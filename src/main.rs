@@ -1,176 +1,241 @@
+#![allow(clippy::needless_return, clippy::unnecessary_cast)]
+
 extern crate nalgebra as na;
-extern crate obj;
 extern crate renderer;
 extern crate minifb;
 
-use na::{Vector3, Vector4};
+use na::Vector3;
 use renderer::*;
 use std::env;
-use obj::*;
-use std::fs::File;
-use std::io::BufReader;
-use minifb::{Key, Window, WindowOptions};
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
 
 const FPS: usize = 60;
 const WIN_WIDTH: usize = 800;
 const WIN_HEIGHT: usize = 600;
+const WIN_TITLE: &str =
+    "Test - ESC to exit, T shading, O outlines, S shadows, W wireframe, F fog, N normal maps";
 
-fn load_model_from_file(file_name: &String) -> core::Mesh {
-    let mut model = core::Mesh::new();
-    let f = match File::open(file_name) {
-        Ok(v) => v,
-        Err(_e) => {
-            println!("Error: Could not open file {}", file_name);
-            return model;
-        }
-    };
+/// Frame rate and render time averaged over one second
+struct FrameStats {
+    start: Instant,
+    frames: u32,
+    render_time: Duration,
+}
 
-    let input = BufReader::new(f);
-    let obj: Obj = load_obj(input).unwrap();
-
-    let mut f = 0;
-    while f < obj.indices.len() {
-        assert!(f + 2 < obj.indices.len());
-        let white = renderer::core::Color {
-            r: 255,
-            g: 255,
-            b: 255,
-            a: 255,
+impl FrameStats {
+    fn new() -> FrameStats {
+        return FrameStats {
+            start: Instant::now(),
+            frames: 0,
+            render_time: Duration::ZERO,
         };
+    }
 
-        let i = obj.indices[f] as usize;
-        let j = obj.indices[f + 1] as usize;
-        let k = obj.indices[f + 2] as usize;
-
-        model.faces.push(core::Face {
-            v0: renderer::core::Vertex {
-                position: Vector4::new(
-                    obj.vertices[i].position[0],
-                    obj.vertices[i].position[1],
-                    obj.vertices[i].position[2],
-                    1.0,
-                ),
-                color: white,
-                normal: Vector3::new(
-                    obj.vertices[i].normal[0],
-                    obj.vertices[i].normal[1],
-                    obj.vertices[i].normal[2],
-                ),
-            },
-            v1: renderer::core::Vertex {
-                position: Vector4::new(
-                    obj.vertices[j].position[0],
-                    obj.vertices[j].position[1],
-                    obj.vertices[j].position[2],
-                    1.0,
-                ),
-                color: white,
-                normal: Vector3::new(
-                    obj.vertices[j].normal[0],
-                    obj.vertices[j].normal[1],
-                    obj.vertices[j].normal[2],
-                ),
-            },
-            v2: renderer::core::Vertex {
-                position: Vector4::new(
-                    obj.vertices[k].position[0],
-                    obj.vertices[k].position[1],
-                    obj.vertices[k].position[2],
-                    1.0,
-                ),
-                color: white,
-                normal: Vector3::new(
-                    obj.vertices[k].normal[0],
-                    obj.vertices[k].normal[1],
-                    obj.vertices[k].normal[2],
-                ),
-            },
-        });
+    /// Add a frame, returns the frames per second and the average render time in
+    /// milliseconds once a second has passed
+    fn record(&mut self, render_time: Duration) -> Option<(f32, f32)> {
+        self.frames += 1;
+        self.render_time += render_time;
+        let elapsed = self.start.elapsed().as_secs_f32();
+        if elapsed < 1.0 {
+            return None;
+        }
 
-        f = f + 3;
+        let fps = self.frames as f32 / elapsed;
+        let ms = self.render_time.as_secs_f32() * 1000.0 / self.frames as f32;
+        *self = FrameStats::new();
+        return Some((fps, ms));
     }
+}
 
-    return model;
+/// Where the vertex normals of a loaded OBJ model come from, PLY models always use the normals
+/// of the file or the face normals when the file has none and STL models the facet normals
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum NormalSource {
+    /// Normals of the file, corners without a normal get the normal of the face
+    Imported,
+    /// Normal of the face at every corner for flat shading, the normals of the file are ignored
+    Computed,
+    /// Average of the normals of the faces around each vertex position for smooth shading
+    Smooth,
 }
 
-fn load_default_model() -> core::Mesh {
-    let mut model = core::Mesh::new();
-    let red = renderer::core::Color {
-        r: 255,
-        g: 0,
-        b: 0,
-        a: 255,
-    };
-    let green = renderer::core::Color {
-        r: 0,
-        g: 255,
-        b: 0,
-        a: 255,
+impl NormalSource {
+    fn parse(name: &str) -> Option<NormalSource> {
+        return match name {
+            "imported" => Some(NormalSource::Imported),
+            "computed" => Some(NormalSource::Computed),
+            "smooth" => Some(NormalSource::Smooth),
+            _ => None,
+        };
+    }
+}
+
+fn load_model_from_file(file_name: &String, normals: NormalSource) -> core::Mesh {
+    let extension = Path::new(file_name).extension().and_then(|e| e.to_str());
+    let loaded = match extension.map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("ply") => ply::load_ply(file_name),
+        Some("stl") => stl::load_stl(file_name),
+        _ => wavefront::load_obj(file_name).map(|mut model| {
+            match normals {
+                NormalSource::Imported => {}
+                NormalSource::Computed => model.compute_flat_normals(),
+                NormalSource::Smooth => model.compute_smooth_normals(),
+            }
+            model.compute_tangents();
+            model.sort_faces_by_material();
+            return model;
+        }),
     };
-    let blue = renderer::core::Color {
-        r: 0,
-        g: 0,
-        b: 255,
-        a: 255,
+    return match loaded {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Error: Could not load file {}: {}", file_name, e);
+            core::Mesh::new()
+        }
     };
-    let side_len = 1.0;
-
-    model.faces.push(core::Face {
-        v0: renderer::core::Vertex {
-            position: Vector4::new(0.0, side_len, 0.0, 1.0),
-            color: red,
-            normal: Vector3::new(0.0, 0.0, 1.0),
-        },
-        v1: renderer::core::Vertex {
-            position: Vector4::new(-side_len/2.0, 0.0, 0.0, 1.0),
-            color: green,
-            normal: Vector3::new(0.0, 0.0, 1.0),
-        },
-        v2: renderer::core::Vertex {
-            position: Vector4::new(side_len/2.0, 0.0, 0.0, 1.0),
-            color: blue,
-            normal: Vector3::new(0.0, 0.0, 1.0),
-        },
-    });
+}
+
+/// Save a copy of the frame to a timestamped PNG file without blocking the render loop
+fn save_screenshot(db: &core::DisplayBuffer) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let file_name = format!("screenshot-{}.png", timestamp);
+    let frame = db.clone();
 
-    return model;
+    thread::spawn(move || match frame.save_png(&file_name) {
+        Ok(()) => println!("Saved screenshot {}", file_name),
+        Err(e) => println!("Error: Could not save screenshot {}: {}", file_name, e),
+    });
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let mut model = if args.len() == 2 {
-        load_model_from_file(&args[1])
-    } else {
-        println!("Usage: renderer [FILE]");
-        println!("No model file given. Loading default model");
-        load_default_model()
+    let usage = "Usage: renderer [FILE.obj [imported|computed|smooth] | FILE.ply | FILE.stl]";
+    let model = match args.len() {
+        1 => {
+            println!("{}", usage);
+            println!("No model file given. Loading default model");
+            core::default_triangle()
+        }
+        2 | 3 => {
+            let normals = match args.get(2) {
+                None => NormalSource::Imported,
+                Some(name) => match NormalSource::parse(name) {
+                    Some(normals) => normals,
+                    None => {
+                        println!("Error: Unknown normal source {}", name);
+                        println!("{}", usage);
+                        process::exit(1);
+                    }
+                },
+            };
+            let mut model = load_model_from_file(&args[1], normals);
+            model.center_and_normalize();
+            model
+        }
+        _ => {
+            println!("Error: Unexpected argument {}", args[3]);
+            println!("{}", usage);
+            process::exit(1);
+        }
     };
+    let mut scene = scene::Scene::new();
+    scene.meshes.push(model);
 
-    model.translate(Vector3::new(0.0, 0.0, -6.0));
+    // Sway the model back and forth in front of the camera
+    let mut animation = animation::Animation::new();
+    let keyframes = [
+        (0.0, 0.0, 0.0),
+        (2.0, 0.5, 0.6),
+        (4.0, 0.0, 0.0),
+        (6.0, -0.5, -0.6),
+        (8.0, 0.0, 0.0),
+    ];
+    for &(t, height, angle) in keyframes.iter() {
+        animation.add_keyframe(
+            t,
+            Vector3::new(0.0, height, -6.0),
+            Vector3::new(0.0, angle, 0.0),
+        );
+    }
+    let mut outlines = false;
+    let mut shadows = false;
+    let light_pos = Vector3::new(3.0, 8.0, -4.0);
+    let outline_color = core::Color::BLACK;
+    let mut time = 0.0;
+    let mut last_frame = Instant::now();
 
-    let eye_pos = Vector3::new(0.0, 0.0, 0.0);
+    let eye_pos = Vector3::new(0.0, 1.5, 0.0);
     let mut _vel = Vector3::new(0.0, 0.0, 0.0);
     let mut db = core::DisplayBuffer::new(WIN_WIDTH as usize, WIN_HEIGHT as usize, 4);
+    db.state.background = core::Background::VerticalGradient(
+        core::Color::from_hex(0x283C_6EFF),
+        core::Color::from_hex(0x0A0A_14FF),
+    );
+    // Fog in the color of the horizon, toggled with F
+    let fog = core::Fog::Exponential {
+        color: core::Color::from_hex(0x1923_41FF),
+        density: 0.08,
+    };
+    db.state.fog = Some(fog);
+    let mut framebuffer = framebuffer::Framebuffer::new(db);
     let mut _mouselook_enabled = false;
-    let lookat = Vector3::new(0.0, 0.0, -1.0);
+    let lookat = Vector3::new(0.0, 0.0, -6.0);
+    let grid = grid::Grid::new(10.0, 1.0);
+    let origin_axes = gizmo::AxisGizmo::new(Vector3::zeros(), 1.0);
     let mut buffer: Vec<u32> = vec![0; WIN_WIDTH * WIN_HEIGHT];
 
-    let mut window = Window::new(
-        "Test - ESC to exit",
-        WIN_WIDTH,
-        WIN_HEIGHT,
-        WindowOptions::default(),
-    )
-    .unwrap_or_else(|e| {
-        panic!("{}", e);
-    });
+    let mut window = Window::new(WIN_TITLE, WIN_WIDTH, WIN_HEIGHT, WindowOptions::default())
+        .unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
 
     window.set_target_fps(FPS);
+    let mut stats = FrameStats::new();
+    let mut last_stats = (0.0, 0.0);
+    let text_color = core::Color::WHITE;
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
+        let now = Instant::now();
+        let delta = (now - last_frame).as_secs_f32();
+        time = (time + delta) % animation.duration();
+        scene.time += delta;
+        last_frame = now;
+        animation.apply(time, &mut scene.meshes[0]);
+
+        // The render time covers drawing the frame but not presenting it, the window waits
+        // there for the target frame rate
+        let render_start = Instant::now();
+        let db = framebuffer.back();
         db.clear();
-        model.render(eye_pos, lookat, &mut db);
+        grid.render(eye_pos, lookat, db);
+        origin_axes.render(eye_pos, lookat, db);
+        let model_axes = gizmo::AxisGizmo::new(scene.meshes[0].position.xyz(), 0.75);
+        model_axes.render(eye_pos, lookat, db);
+        scene.render(eye_pos, lookat, db, None);
+        if shadows {
+            let shadow_map = scene.render_shadow_map(light_pos, lookat, 512, 0.0005);
+            db.apply_shadows(eye_pos, lookat, &shadow_map, 0.5);
+        }
+        if outlines {
+            db.draw_outlines(0.001, 1, outline_color);
+        }
+        let face_count: usize = scene.meshes.iter().map(|m| m.face_count()).sum();
+        let overlay = format!(
+            "FPS {:.1} ({:.1} MS)\nFACES {}\nEYE {:.1} {:.1} {:.1}",
+            last_stats.0, last_stats.1, face_count, eye_pos.x, eye_pos.y, eye_pos.z
+        );
+        db.draw_text(4, WIN_HEIGHT - 5, &overlay, text_color);
+        framebuffer.swap();
 
+        // Present the finished frame from the front buffer
+        let db = framebuffer.front();
         for i in 0..WIN_WIDTH {
             for j in 0..WIN_HEIGHT {
                 let idx = (i + j * WIN_WIDTH) * 4;
@@ -184,9 +249,65 @@ fn main() {
             }
         }
 
+        let render_time = render_start.elapsed();
         window
             .update_with_buffer(&buffer, WIN_WIDTH, WIN_HEIGHT)
             .unwrap();
+        if let Some((fps, ms)) = stats.record(render_time) {
+            window.set_title(&format!("{} - {:.1} FPS, {:.1} ms", WIN_TITLE, fps, ms));
+            last_stats = (fps, ms);
+        }
+
+        if window.is_key_pressed(Key::F12, KeyRepeat::No) {
+            save_screenshot(framebuffer.front());
+        }
+        if window.is_key_pressed(Key::W, KeyRepeat::No) {
+            let mesh = &mut scene.meshes[0];
+            mesh.render_mode = match mesh.render_mode {
+                core::RenderMode::Shaded => core::RenderMode::Wireframe { width: 1.5 },
+                core::RenderMode::Wireframe { .. } => core::RenderMode::FilledWireframe {
+                    width: 1.0,
+                    color: core::Color::BLACK,
+                    offset: -0.0002,
+                },
+                core::RenderMode::FilledWireframe { .. } => core::RenderMode::Points {
+                    density: 4,
+                    radius: 1.0,
+                },
+                core::RenderMode::Points { .. } => core::RenderMode::FaceIds,
+                _ => core::RenderMode::Shaded,
+            };
+        }
+        if window.is_key_pressed(Key::O, KeyRepeat::No) {
+            outlines = !outlines;
+        }
+        if window.is_key_pressed(Key::S, KeyRepeat::No) {
+            shadows = !shadows;
+            // The shadows are cast by the light that shades the meshes
+            for mesh in scene.meshes.iter_mut() {
+                mesh.light_position = if shadows { Some(light_pos) } else { None };
+            }
+        }
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            let db = framebuffer.back();
+            db.state.fog = if db.state.fog.is_some() {
+                None
+            } else {
+                Some(fog)
+            };
+        }
+        if window.is_key_pressed(Key::N, KeyRepeat::No) {
+            let mesh = &mut scene.meshes[0];
+            mesh.normal_mapping = !mesh.normal_mapping;
+        }
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            let mesh = &mut scene.meshes[0];
+            mesh.shading_model = match mesh.shading_model {
+                core::ShadingModel::Smooth => core::ShadingModel::Toon { bands: 3 },
+                core::ShadingModel::Toon { .. } => core::ShadingModel::Unlit,
+                core::ShadingModel::Unlit => core::ShadingModel::Smooth,
+            };
+        }
     }
 
 }
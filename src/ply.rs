@@ -0,0 +1,451 @@
+// Loading meshes from PLY (polygon file format) files
+//
+// A PLY file starts with a text header that declares the elements of the file (vertices,
+// faces and possibly others) and the properties of each element, followed by the elements in
+// ASCII or binary form. The vertices are read with their positions, and the normals, colors
+// and texture coordinates when the file has them. Faces are split into triangles.
+//
+// `load_ply` is not available on wasm32, where there is no file system. Use `parse_ply` instead.
+
+use core::{Face, FloatColor, Mesh, Vertex, NO_MATERIAL};
+use io_util::invalid_data;
+use na::{Vector2, Vector3, Vector4};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+/// Type of a scalar property value
+#[derive(Copy, Clone)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> io::Result<Scalar> {
+        return match name {
+            "char" | "int8" => Ok(Scalar::I8),
+            "uchar" | "uint8" => Ok(Scalar::U8),
+            "short" | "int16" => Ok(Scalar::I16),
+            "ushort" | "uint16" => Ok(Scalar::U16),
+            "int" | "int32" => Ok(Scalar::I32),
+            "uint" | "uint32" => Ok(Scalar::U32),
+            "float" | "float32" => Ok(Scalar::F32),
+            "double" | "float64" => Ok(Scalar::F64),
+            _ => Err(invalid_data(&format!("Unknown property type {}", name))),
+        };
+    }
+
+    fn size(&self) -> usize {
+        return match *self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        };
+    }
+
+    /// Value from little-endian bytes of the size of the type
+    fn read_le(&self, b: &[u8]) -> f64 {
+        return match *self {
+            Scalar::I8 => b[0] as i8 as f64,
+            Scalar::U8 => b[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]),
+        };
+    }
+}
+
+/// Property of an element, a single value or a list of values with a count before them
+enum Property {
+    Scalar(String, Scalar),
+    List(String, Scalar, Scalar),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        return match *self {
+            Property::Scalar(ref name, _) | Property::List(ref name, _, _) => name,
+        };
+    }
+}
+
+/// Element declared in the header, e.g. the vertices or the faces
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Values of the body of the file in ASCII or binary little-endian form
+enum Body<'a> {
+    Ascii(std::str::SplitWhitespace<'a>),
+    Binary(&'a [u8]),
+}
+
+impl<'a> Body<'a> {
+    fn next(&mut self, kind: Scalar) -> io::Result<f64> {
+        match *self {
+            Body::Ascii(ref mut tokens) => {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| invalid_data("Unexpected end of data"))?;
+                return token
+                    .parse()
+                    .map_err(|_| invalid_data(&format!("Invalid number {}", token)));
+            }
+            Body::Binary(ref mut bytes) => {
+                if bytes.len() < kind.size() {
+                    return Err(invalid_data("Unexpected end of data"));
+                }
+                let (value, rest) = bytes.split_at(kind.size());
+                *bytes = rest;
+                return Ok(kind.read_le(value));
+            }
+        }
+    }
+
+    /// Values of all properties of one element, lists are flattened after their count
+    fn read_row(&mut self, properties: &[Property], row: &mut Vec<Vec<f64>>) -> io::Result<()> {
+        row.clear();
+        for property in properties.iter() {
+            match *property {
+                Property::Scalar(_, kind) => row.push(vec![self.next(kind)?]),
+                Property::List(_, count, item) => {
+                    let n = self.next(count)? as usize;
+                    let values = (0..n)
+                        .map(|_| self.next(item))
+                        .collect::<io::Result<Vec<f64>>>()?;
+                    row.push(values);
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Parse the header, returns the elements, whether the body is binary and the body bytes
+fn parse_header(data: &[u8]) -> io::Result<(Vec<Element>, bool, &[u8])> {
+    let mut elements: Vec<Element> = Vec::new();
+    let mut binary = None;
+    let mut rest = data;
+    let mut first = true;
+
+    loop {
+        let end = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| invalid_data("Missing end_header"))?;
+        let line = std::str::from_utf8(&rest[..end])
+            .map_err(|_| invalid_data("Header is not text"))?
+            .trim();
+        rest = &rest[end + 1..];
+
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if first {
+            if line != "ply" {
+                return Err(invalid_data("Not a PLY file"));
+            }
+            first = false;
+            continue;
+        }
+        match args.as_slice() {
+            ["format", "ascii", _] => binary = Some(false),
+            ["format", "binary_little_endian", _] => binary = Some(true),
+            ["format", format, _] => {
+                return Err(invalid_data(&format!("Unsupported format {}", format)));
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid_data("Invalid element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let property = Property::List(
+                    name.to_string(),
+                    Scalar::parse(count)?,
+                    Scalar::parse(item)?,
+                );
+                elements
+                    .last_mut()
+                    .ok_or_else(|| invalid_data("Property before element"))?
+                    .properties
+                    .push(property);
+            }
+            ["property", kind, name] => {
+                let property = Property::Scalar(name.to_string(), Scalar::parse(kind)?);
+                elements
+                    .last_mut()
+                    .ok_or_else(|| invalid_data("Property before element"))?
+                    .properties
+                    .push(property);
+            }
+            ["end_header"] => break,
+            _ => {}
+        }
+    }
+
+    let binary = binary.ok_or_else(|| invalid_data("Missing format"))?;
+    return Ok((elements, binary, rest));
+}
+
+/// Parse a mesh from the contents of a PLY file
+///
+/// Vertices without normals get the normal of each face they belong to (flat shading) and
+/// vertices without colors are white. Colors stored as integers are in the range [0, 255],
+/// colors stored as floats in [0, 1]. Faces with more than three corners are split into a
+/// fan of triangles, their corners are in counter-clockwise order. The tangents are computed
+/// from the texture coordinates, see `Mesh::compute_tangents`.
+///
+/// # Arguments
+///
+/// * `data` - Contents of an ASCII or binary little-endian PLY file
+pub fn parse_ply(data: &[u8]) -> io::Result<Mesh> {
+    let (elements, binary, body) = parse_header(data)?;
+    let mut body = if binary {
+        Body::Binary(body)
+    } else {
+        let text = std::str::from_utf8(body).map_err(|_| invalid_data("Data is not text"))?;
+        Body::Ascii(text.split_whitespace())
+    };
+
+    let mut vertices: Vec<Vertex<Vector4<f32>>> = Vec::new();
+    let mut has_normals = false;
+    let mut polygons: Vec<Vec<usize>> = Vec::new();
+    let mut row = Vec::new();
+
+    for element in elements.iter() {
+        let column = |name: &str| element.properties.iter().position(|p| p.name() == name);
+        let columns = |names: &[&str]| names.iter().map(|&n| column(n)).collect::<Vec<_>>();
+
+        match element.name.as_str() {
+            "vertex" => {
+                let position = columns(&["x", "y", "z"]);
+                let normal = columns(&["nx", "ny", "nz"]);
+                let color = columns(&["red", "green", "blue", "alpha"]);
+                let uv = column("u").or(column("s")).or(column("texture_u"));
+                let uv = uv.zip(column("v").or(column("t")).or(column("texture_v")));
+                if position.iter().any(Option::is_none) {
+                    return Err(invalid_data("Vertex without a position"));
+                }
+                // The vertex values are single numbers, a list in their place may be empty
+                let mut used: Vec<usize> =
+                    position.iter().chain(&normal).flatten().copied().collect();
+                used.extend(color.iter().flatten());
+                used.extend(uv.iter().flat_map(|&(u, v)| vec![u, v]));
+                if used
+                    .iter()
+                    .any(|&i| matches!(element.properties[i], Property::List(..)))
+                {
+                    return Err(invalid_data("Vertex value declared as a list"));
+                }
+                has_normals = normal.iter().all(Option::is_some);
+
+                // Integer colors are bytes, float colors are already in [0, 1]
+                let color_scale: Vec<f32> = color
+                    .iter()
+                    .map(|c| match c.map(|i| &element.properties[i]) {
+                        Some(&Property::Scalar(_, Scalar::F32))
+                        | Some(&Property::Scalar(_, Scalar::F64)) => 1.0,
+                        _ => 1.0 / 255.0,
+                    })
+                    .collect();
+
+                for _ in 0..element.count {
+                    body.read_row(&element.properties, &mut row)?;
+                    let value = |i: Option<usize>| i.map(|i| row[i][0] as f32);
+                    let channel = |k: usize| value(color[k]).map_or(1.0, |c| c * color_scale[k]);
+                    vertices.push(Vertex {
+                        position: Vector4::new(
+                            value(position[0]).unwrap(),
+                            value(position[1]).unwrap(),
+                            value(position[2]).unwrap(),
+                            1.0,
+                        ),
+                        color: FloatColor {
+                            r: channel(0),
+                            g: channel(1),
+                            b: channel(2),
+                            a: channel(3),
+                        },
+                        normal: if has_normals {
+                            Vector3::new(
+                                value(normal[0]).unwrap(),
+                                value(normal[1]).unwrap(),
+                                value(normal[2]).unwrap(),
+                            )
+                        } else {
+                            Vector3::zeros()
+                        },
+                        uv: uv.map_or(Vector2::zeros(), |(u, v)| {
+                            Vector2::new(value(Some(u)).unwrap(), value(Some(v)).unwrap())
+                        }),
+                        tangent: Vector3::zeros(),
+                    });
+                }
+            }
+            "face" => {
+                let indices = column("vertex_indices")
+                    .or(column("vertex_index"))
+                    .ok_or_else(|| invalid_data("Face without vertex indices"))?;
+                for _ in 0..element.count {
+                    body.read_row(&element.properties, &mut row)?;
+                    if row[indices].iter().any(|&i| i < 0.0) {
+                        return Err(invalid_data("Negative vertex index"));
+                    }
+                    polygons.push(row[indices].iter().map(|&i| i as usize).collect());
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    body.read_row(&element.properties, &mut row)?;
+                }
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new();
+    for polygon in polygons.iter() {
+        if polygon.iter().any(|&i| i >= vertices.len()) {
+            return Err(invalid_data("Face refers to a missing vertex"));
+        }
+        for i in 1..polygon.len().saturating_sub(1) {
+            let mut face = Face {
+                v0: vertices[polygon[0]],
+                v1: vertices[polygon[i]],
+                v2: vertices[polygon[i + 1]],
+                material_id: NO_MATERIAL,
+            };
+            if !has_normals {
+                let (p0, p1, p2) = (face.v0.position, face.v1.position, face.v2.position);
+                let n = (p1 - p0).xyz().cross(&(p2 - p0).xyz());
+                let n = n.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros);
+                face.v0.normal = n;
+                face.v1.normal = n;
+                face.v2.normal = n;
+            }
+            mesh.faces.push(face);
+        }
+    }
+    mesh.compute_tangents();
+    return Ok(mesh);
+}
+
+/// Load a mesh from a PLY file, see `parse_ply`
+///
+/// # Arguments
+///
+/// * `path` - Path of the file to load
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_ply<P: AsRef<Path>>(path: P) -> io::Result<Mesh> {
+    return parse_ply(&fs::read(path)?);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ascii_with_colors_and_computed_normals() {
+        let source = "ply\n\
+                      format ascii 1.0\n\
+                      comment quad in the xy plane\n\
+                      element vertex 4\n\
+                      property float x\n\
+                      property float y\n\
+                      property float z\n\
+                      property uchar red\n\
+                      property uchar green\n\
+                      property uchar blue\n\
+                      element face 1\n\
+                      property list uchar int vertex_indices\n\
+                      end_header\n\
+                      0 0 0 255 0 0\n\
+                      1 0 0 0 255 0\n\
+                      1 1 0 0 0 255\n\
+                      0 1 0 255 255 255\n\
+                      4 0 1 2 3\n";
+
+        let mesh = parse_ply(source.as_bytes()).unwrap();
+
+        assert_eq!(mesh.face_count(), 2);
+        let face = mesh.faces[1];
+        assert_eq!(face.v1.position, Vector4::new(1.0, 1.0, 0.0, 1.0));
+        assert_eq!(face.v1.color.b, 1.0);
+        assert_eq!(face.v1.color.r, 0.0);
+        assert_eq!(face.v0.normal, Vector3::new(0.0, 0.0, 1.0));
+        assert!(mesh.validate_winding().is_empty());
+    }
+
+    #[test]
+    fn test_parse_rejects_list_positions_and_negative_indices() {
+        let header = |x: &str| {
+            format!(
+                "ply\nformat ascii 1.0\nelement vertex 3\n{}\nproperty float y\n\
+                 property float z\nelement face 1\nproperty list uchar int vertex_indices\n\
+                 end_header\n",
+                x
+            )
+        };
+        let list = header("property list uchar float x") + "0 0 0\n1 1 0\n1 0 1\n3 0 1 2\n";
+        let negative = header("property float x") + "0 0 0\n1 0 0\n0 1 0\n3 0 1 -1\n";
+
+        for source in [list, negative].iter() {
+            let error = parse_ply(source.as_bytes()).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_parse_binary_little_endian_with_normals() {
+        let mut data = b"ply\n\
+                         format binary_little_endian 1.0\n\
+                         element vertex 3\n\
+                         property float x\n\
+                         property float y\n\
+                         property float z\n\
+                         property float nx\n\
+                         property float ny\n\
+                         property float nz\n\
+                         element face 1\n\
+                         property list uchar uint vertex_indices\n\
+                         element extra 1\n\
+                         property short id\n\
+                         end_header\n"
+            .to_vec();
+        let vertices = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        for p in vertices.iter() {
+            for v in p.iter().chain([0.0f32, 1.0, 0.0].iter()) {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        data.push(3);
+        for i in 0..3u32 {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+        data.extend_from_slice(&7i16.to_le_bytes());
+
+        let mesh = parse_ply(&data).unwrap();
+
+        assert_eq!(mesh.face_count(), 1);
+        assert_eq!(mesh.faces[0].v2.position, Vector4::new(0.0, 1.0, 0.0, 1.0));
+        assert_eq!(mesh.faces[0].v2.normal, Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(mesh.faces[0].v2.color.r, 1.0);
+
+        assert!(parse_ply(&data[..data.len() - 1]).is_err());
+    }
+}
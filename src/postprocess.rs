@@ -0,0 +1,148 @@
+// Post-processing passes over a rendered display buffer
+//
+// The passes run after all geometry has been drawn and work on the pixel data and the depth
+// buffer only, so they are independent of the meshes that produced the image.
+
+use core::{Color, DisplayBuffer};
+use na::{Vector3, Vector4};
+use scene::ShadowMap;
+
+impl DisplayBuffer {
+    /// Draw outlines at the silhouettes and depth discontinuities of the rendered geometry
+    ///
+    /// A pixel is on an outline if any pixel within `thickness` pixels of it is farther away
+    /// by more than `threshold`, including the background. The outlines are drawn on the
+    /// side of the nearer geometry and the depth buffer is left unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - Smallest depth difference between neighbouring pixels that counts as an
+    ///   edge, in the depth range of the buffer
+    /// * `thickness` - Width of the outlines in pixels
+    /// * `color` - Color of the outlines
+    pub fn draw_outlines(&mut self, threshold: f32, thickness: usize, color: Color) {
+        let r = thickness as isize;
+        let mut edges = Vec::new();
+
+        for y in 0..self.height as isize {
+            for x in 0..self.width as isize {
+                let z = self.sample_depth(x, y);
+                if z == self.state.clear_depth {
+                    continue;
+                }
+
+                // Neighbours outside of the buffer repeat the nearest edge pixel, so the border
+                // of the buffer does not add edges
+                let farther =
+                    |dx: isize, dy: isize| self.sample_depth(x + dx, y + dy) - z > threshold;
+                let is_edge = (-r..=r).any(|dy| (-r..=r).any(|dx| farther(dx, dy)));
+                if is_edge {
+                    edges.push((self.height - y as usize - 1) * self.width + x as usize);
+                }
+            }
+        }
+
+        for index in edges {
+            self.write_pixel(index, color);
+        }
+    }
+
+    /// Darken the pixels whose geometry is in the shadow of a light
+    ///
+    /// The world position of each pixel is reconstructed from its depth with the camera that
+    /// the buffer was rendered with, and tested against the shadow map of the light. The pass
+    /// is skipped when the camera has no view to reconstruct the positions with, e.g. when
+    /// the eye is at the focus point.
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye the buffer was rendered with
+    /// * 'lookat' - Focus point of the eye
+    /// * `shadow_map` - Depth of the scene seen from the light
+    /// * `darkness` - Fraction of the color removed in the shadow, 0 leaves the pixels as they
+    ///   are and 1 makes them black
+    pub fn apply_shadows(
+        &mut self,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        shadow_map: &ShadowMap,
+        darkness: f32,
+    ) {
+        let view = self.view_matrix(eye, lookat);
+        let to_world = match (self.projection_matrix() * view).try_inverse() {
+            Some(m) if m.iter().all(|v| v.is_finite()) => m,
+            _ => return,
+        };
+        let keep = 1.0 - darkness.clamp(0.0, 1.0);
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let index = row * self.width + col;
+                let z = self.stored_depth(index);
+                if z == self.state.clear_depth {
+                    continue;
+                }
+
+                let y = self.height - row - 1;
+                let ndc = Vector4::new(
+                    col as f32 / self.width as f32 * 2.0 - 1.0,
+                    y as f32 / self.height as f32 * 2.0 - 1.0,
+                    self.unmap_depth(z),
+                    1.0,
+                );
+                let world = to_world * ndc;
+                if shadow_map.is_shadowed(world.xyz() / world.w) {
+                    for channel in 0..3 {
+                        let c = &mut self.data[index * self.bpp + channel];
+                        *c = (*c as f32 * keep) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_outlines_marks_silhouette() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let black = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(8, 8, 4);
+        for y in 1..7 {
+            for x in 1..7 {
+                buffer.set_pixel(x, y, 0.5, white);
+            }
+        }
+
+        buffer.draw_outlines(0.1, 2, black);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let index = ((8 - y - 1) * 8 + x) * 4;
+                let pixel = &buffer.data[index..index + 4];
+                let inside = (1..7).contains(&x) && (1..7).contains(&y);
+                let interior = (3..5).contains(&x) && (3..5).contains(&y);
+                if !inside {
+                    assert_eq!(pixel, [0, 0, 0, 0], "pixel ({}, {})", x, y);
+                } else if interior {
+                    assert_eq!(pixel, [255, 255, 255, 255], "pixel ({}, {})", x, y);
+                } else {
+                    assert_eq!(pixel, [0, 0, 0, 255], "pixel ({}, {})", x, y);
+                }
+            }
+        }
+    }
+}
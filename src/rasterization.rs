@@ -1,5 +1,7 @@
-use core::{Color, DisplayBuffer, Face, Renderable};
-use na::{Vector2, Vector3};
+use core::{
+    Color, DisplayBuffer, Face, FloatColor, FragmentShaderFn, Line3D, Rect, RenderMode, Renderable,
+};
+use na::{Vector2, Vector3, Vector4};
 
 /// Get barycentric coordinates for a point P with respect to a triangle ABC
 ///
@@ -17,7 +19,7 @@ use na::{Vector2, Vector3};
 /// - u, v, w can be used to interpolate the vertex attributes inside the triangle
 /// - u + v + w = 1
 ///
-fn get_barycentric(
+pub fn get_barycentric(
     a: Vector2<f32>,
     b: Vector2<f32>,
     c: Vector2<f32>,
@@ -41,54 +43,558 @@ fn get_barycentric(
     (u, v, w)
 }
 
-impl Renderable for Face<Vector3<f32>> {
-    /// Draw a color-filled face
-    fn render(&self, buffer: &mut DisplayBuffer) {
+/// Map a normal vector to a color, each component [-1, 1] maps to [0, 255]
+fn normal_to_color(n: Vector3<f32>) -> Color {
+    return Color {
+        r: ((n.x * 0.5 + 0.5) * 255.0) as u8,
+        g: ((n.y * 0.5 + 0.5) * 255.0) as u8,
+        b: ((n.z * 0.5 + 0.5) * 255.0) as u8,
+        a: 255,
+    };
+}
+
+/// Vertex attributes interpolated at a fragment (covered pixel) of a face
+pub struct FragmentInput {
+    /// Position in raster space, x and y in pixels and z the depth
+    pub position: Vector3<f32>,
+    /// Interpolated normal vector, unit length unless `RenderState::normalize_normals` is off
+    pub normal: Vector3<f32>,
+    /// Interpolated texture coordinates
+    pub uv: Vector2<f32>,
+    /// Interpolated tangent along the u texture coordinate, unit length unless
+    /// `RenderState::normalize_normals` is off or the vertices have no tangents
+    pub tangent: Vector3<f32>,
+    /// Change of the texture coordinates to the next pixel on the right
+    pub uv_dx: Vector2<f32>,
+    /// Change of the texture coordinates to the next pixel up
+    pub uv_dy: Vector2<f32>,
+    /// Interpolated shaded color
+    pub color: FloatColor,
+}
+
+/// Values shared by all fragments of a frame, passed to the fragment shader
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Uniforms {
+    /// Animation time in seconds
+    pub time: f32,
+    /// Width and height of the display buffer in pixels
+    pub resolution: Vector2<f32>,
+    /// World position of the camera eye
+    pub camera_position: Vector3<f32>,
+}
+
+impl Default for Uniforms {
+    fn default() -> Uniforms {
+        return Uniforms {
+            time: 0.0,
+            resolution: Vector2::zeros(),
+            camera_position: Vector3::zeros(),
+        };
+    }
+}
+
+/// Pixel covered by a face
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Fragment {
+    /// X coordinate in pixels, value 0 corresponds to left edge
+    pub x: usize,
+    /// Y coordinate in pixels, value 0 corresponds to bottom edge
+    pub y: usize,
+    /// Barycentric coordinates of the pixel with respect to the vertices of the face
+    pub barycentric: (f32, f32, f32),
+    /// Interpolated depth
+    pub z: f32,
+}
+
+impl Face<Vector3<f32>> {
+    /// Draw the face, coloring the fragments according to a render mode
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Display buffer (render target)
+    /// * `mode` - Determines what the fragments are colored by
+    pub fn render_with_mode(&self, buffer: &mut DisplayBuffer, mode: RenderMode) {
+        self.render_with_shader(buffer, mode, None, &Uniforms::default());
+    }
+
+    /// Draw the face, coloring the fragments with a fragment shader
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Display buffer (render target)
+    /// * `mode` - Determines what the fragments are colored by when there is no shader, the
+    ///   shader is not used in the wireframe mode
+    /// * `shader` - Computes the color of each fragment from the interpolated attributes
+    /// * `uniforms` - Values passed to the shader with every fragment
+    pub fn render_with_shader(
+        &self,
+        buffer: &mut DisplayBuffer,
+        mode: RenderMode,
+        shader: Option<&FragmentShaderFn>,
+        uniforms: &Uniforms,
+    ) {
+        if let RenderMode::Wireframe { width } = mode {
+            self.draw_edges(buffer, width, None, 0.0);
+            return;
+        }
+        if let RenderMode::Points { density, radius } = mode {
+            self.draw_points(buffer, density, radius, shader, uniforms);
+            return;
+        }
+
+        if self.raster_area().abs() <= buffer.state.min_face_area {
+            return;
+        }
+
+        // The drawable area is the buffer or the scissor rectangle inside of it
+        let area = buffer.state.scissor.unwrap_or(Rect {
+            x: 0,
+            y: 0,
+            width: buffer.width,
+            height: buffer.height,
+        });
+        let area = Rect {
+            width: area.width.min(buffer.width.saturating_sub(area.x)),
+            height: area.height.min(buffer.height.saturating_sub(area.y)),
+            ..area
+        };
+
+        // The texture coordinates are linear in screen space, so their derivatives are the
+        // same for all fragments of the face
+        let (uv_dx, uv_dy) = self.uv_derivatives();
+
+        for f in self.fragments(area) {
+            // The depth is known before shading, hidden fragments skip the interpolation of
+            // the attributes and the fragment shader
+            if buffer.reject_depth(f.x, f.y, f.z) {
+                continue;
+            }
+            let position = Vector3::new(f.x as f32, f.y as f32, f.z);
+            let input = self.interpolate(f.barycentric, position, (uv_dx, uv_dy), buffer);
+            let normal = input.normal;
+            let color = match (shader, mode) {
+                (Some(shader), _) => shader(&input, uniforms),
+                (None, RenderMode::Normals) => normal_to_color(normal),
+                (None, _) => buffer.encode_color(input.color),
+            };
+            if buffer.set_pixel_unchecked(f.x, f.y, f.z, color) && buffer.normals.is_some() {
+                // The normal target holds unit normals either way
+                let unit = if buffer.state.normalize_normals {
+                    normal
+                } else {
+                    normal.try_normalize(f32::EPSILON).unwrap_or(normal)
+                };
+                buffer.write_normal(f.x, f.y, unit);
+            }
+        }
+
+        if let RenderMode::FilledWireframe {
+            width,
+            color,
+            offset,
+        } = mode
+        {
+            self.draw_edges(buffer, width, Some(color), offset);
+        }
+    }
+
+    /// Attributes of the face interpolated at a point for the fragment shader
+    ///
+    /// # Arguments
+    ///
+    /// * `barycentric` - Barycentric coordinates of the point
+    /// * `position` - Raster position and depth of the point
+    /// * `uv_derivatives` - Change of the texture coordinates per pixel along x and y
+    /// * `buffer` - Display buffer, decides whether the normals are normalized
+    fn interpolate(
+        &self,
+        barycentric: (f32, f32, f32),
+        position: Vector3<f32>,
+        uv_derivatives: (Vector2<f32>, Vector2<f32>),
+        buffer: &DisplayBuffer,
+    ) -> FragmentInput {
+        let (w0, w1, w2) = barycentric;
+        let mut normal = w0 * self.v0.normal + w1 * self.v1.normal + w2 * self.v2.normal;
+        if buffer.state.normalize_normals {
+            normal = normal.try_normalize(f32::EPSILON).unwrap_or(normal);
+        }
+        let mut tangent = w0 * self.v0.tangent + w1 * self.v1.tangent + w2 * self.v2.tangent;
+        if buffer.state.normalize_normals {
+            tangent = tangent.try_normalize(f32::EPSILON).unwrap_or(tangent);
+        }
+        return FragmentInput {
+            position,
+            normal,
+            uv: w0 * self.v0.uv + w1 * self.v1.uv + w2 * self.v2.uv,
+            tangent,
+            uv_dx: uv_derivatives.0,
+            uv_dy: uv_derivatives.1,
+            color: w0 * self.v0.color + w1 * self.v1.color + w2 * self.v2.color,
+        };
+    }
+
+    /// Draw discs at a triangular grid of points on the face
+    ///
+    /// The edges are divided into `density` equal steps and a point is placed at every
+    /// barycentric coordinate that is a multiple of the step, including the corners. Each
+    /// disc has the interpolated color and depth of its center.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Display buffer (render target)
+    /// * `density` - Number of steps along each edge, at least 1
+    /// * `radius` - Radius of the discs in pixels
+    /// * `shader` - Computes the color of each point, the vertex colors are interpolated
+    ///   when not set
+    /// * `uniforms` - Values passed to the shader with every point
+    fn draw_points(
+        &self,
+        buffer: &mut DisplayBuffer,
+        density: u32,
+        radius: f32,
+        shader: Option<&FragmentShaderFn>,
+        uniforms: &Uniforms,
+    ) {
+        let steps = density.max(1);
+        let uv_derivatives = self.uv_derivatives();
+        for i in 0..=steps {
+            for j in 0..=(steps - i) {
+                let w1 = i as f32 / steps as f32;
+                let w2 = j as f32 / steps as f32;
+                let w0 = 1.0 - w1 - w2;
+                let p = w0 * self.v0.position + w1 * self.v1.position + w2 * self.v2.position;
+                let input = self.interpolate((w0, w1, w2), p, uv_derivatives, buffer);
+                let color = match shader {
+                    Some(shader) => shader(&input, uniforms),
+                    None => buffer.encode_color(input.color),
+                };
+                buffer.draw_disc(p.x, p.y, radius, p.z, color);
+            }
+        }
+    }
+
+    /// Draw antialiased lines along the edges of the face
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Display buffer (render target)
+    /// * `width` - Width of the lines in pixels
+    /// * `color` - Color of the lines, the color of the start vertex of each edge when not set
+    /// * `offset` - Offset added to the depth of the lines
+    fn draw_edges(
+        &self,
+        buffer: &mut DisplayBuffer,
+        width: f32,
+        color: Option<Color>,
+        offset: f32,
+    ) {
+        let edges = [
+            (&self.v0, &self.v1),
+            (&self.v1, &self.v2),
+            (&self.v2, &self.v0),
+        ];
+        let shift = Vector3::new(0.0, 0.0, offset);
+        for &(a, b) in edges.iter() {
+            let color = color.unwrap_or_else(|| buffer.encode_color(a.color));
+            draw_line(
+                buffer,
+                a.position + shift,
+                b.position + shift,
+                color,
+                width,
+                true,
+            );
+        }
+    }
+
+    /// Signed area of the face in square pixels, positive for counter-clockwise vertices
+    fn raster_area(&self) -> f32 {
+        let e1 = (self.v1.position - self.v0.position).xy();
+        let e2 = (self.v2.position - self.v0.position).xy();
+        return 0.5 * (e1.x * e2.y - e2.x * e1.y);
+    }
+
+    /// Change of the texture coordinates per pixel along the x and y axes
+    fn uv_derivatives(&self) -> (Vector2<f32>, Vector2<f32>) {
+        let e1 = (self.v1.position - self.v0.position).xy();
+        let e2 = (self.v2.position - self.v0.position).xy();
+        let denom = e1.x * e2.y - e2.x * e1.y;
+        if denom == 0.0 {
+            return (Vector2::zeros(), Vector2::zeros());
+        }
+
+        // Derivatives of the barycentric weights of v1 and v2
+        let du1 = self.v1.uv - self.v0.uv;
+        let du2 = self.v2.uv - self.v0.uv;
+        let uv_dx = (du1 * e2.y - du2 * e1.y) / denom;
+        let uv_dy = (du2 * e1.x - du1 * e2.x) / denom;
+        return (uv_dx, uv_dy);
+    }
+
+    /// Pixels covered by the face, without drawing them
+    ///
+    /// A pixel is covered when its center (integer coordinates) is inside the triangle or on
+    /// one of its edges. The fragments are produced row by row from the bottom left corner of
+    /// the bounding box of the triangle.
+    ///
+    /// # Arguments
+    ///
+    /// * `area` - Rectangle that limits the fragments, e.g. the display buffer
+    pub fn fragments(&self, area: Rect) -> impl Iterator<Item = Fragment> {
         // Bounding box for the triangle
         let all_x = [self.v0.position.x, self.v1.position.x, self.v2.position.x];
         let all_y = [self.v0.position.y, self.v1.position.y, self.v2.position.y];
-        let min_x = all_x.iter().fold(std::f32::MAX, |a, &b| a.min(b)) as usize;
-        let max_x = all_x.iter().fold(std::f32::MIN, |a, &b| a.max(b)) as usize;
-        let min_y = all_y.iter().fold(std::f32::MAX, |a, &b| a.min(b)) as usize;
-        let max_y = all_y.iter().fold(std::f32::MIN, |a, &b| a.max(b)) as usize;
+        let min_x = all_x.iter().fold(std::f32::MAX, |a, &b| a.min(b)).floor();
+        let max_x = all_x.iter().fold(std::f32::MIN, |a, &b| a.max(b)).ceil();
+        let min_y = all_y.iter().fold(std::f32::MAX, |a, &b| a.min(b)).floor();
+        let max_y = all_y.iter().fold(std::f32::MIN, |a, &b| a.max(b)).ceil();
 
-        if max_x >= buffer.width || max_y >= buffer.height {
-            return;
-        }
+        // Skip triangles that are completely outside of the area (empty ranges) and clamp the
+        // bounding box of the rest to the area
+        let left = area.x as f32;
+        let bottom = area.y as f32;
+        let right = (area.x + area.width) as f32 - 1.0;
+        let top = (area.y + area.height) as f32 - 1.0;
+        let (min_x, max_x, min_y, max_y) =
+            if max_x < left || max_y < bottom || min_x > right || min_y > top {
+                (1, 0, 1, 0)
+            } else {
+                (
+                    min_x.max(left) as usize,
+                    max_x.min(right) as usize,
+                    min_y.max(bottom) as usize,
+                    max_y.min(top) as usize,
+                )
+            };
 
-        for y in min_y..=max_y {
-            for x in min_x..=max_x {
-                let v0 = self.v0.position.remove_row(2);
-                let v1 = self.v1.position.remove_row(2);
-                let v2 = self.v2.position.remove_row(2);
-                let p = Vector2::new(x as f32, y as f32);
+        let v0 = self.v0.position.remove_row(2);
+        let v1 = self.v1.position.remove_row(2);
+        let v2 = self.v2.position.remove_row(2);
+        let z = Vector3::new(self.v0.position.z, self.v1.position.z, self.v2.position.z);
 
+        return (min_y..=max_y)
+            .flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+            .filter_map(move |(x, y)| {
+                let p = Vector2::new(x as f32, y as f32);
                 let (w0, w1, w2) = get_barycentric(v0, v1, v2, p);
                 if w0 >= 0.0 && w1 >= 0.0 && w2 >= 0.0 {
-                    let z =
-                        w0 * self.v0.position.z + w1 * self.v1.position.z + w2 * self.v2.position.z;
-                    let color = Color {
-                        r: (w0 * self.v0.color.r as f32
-                            + w1 * self.v1.color.r as f32
-                            + w2 * self.v2.color.r as f32) as u8,
-                        g: (w0 * self.v0.color.g as f32
-                            + w1 * self.v1.color.g as f32
-                            + w2 * self.v2.color.g as f32) as u8,
-                        b: (w0 * self.v0.color.b as f32
-                            + w1 * self.v1.color.b as f32
-                            + w2 * self.v2.color.b as f32) as u8,
-                        a: 255,
-                    };
-                    buffer.set_pixel(x, y, z, color);
+                    Some(Fragment {
+                        x,
+                        y,
+                        barycentric: (w0, w1, w2),
+                        z: w0 * z.x + w1 * z.y + w2 * z.z,
+                    })
+                } else {
+                    None
                 }
+            });
+    }
+}
+
+/// Draw a line segment between two points in raster space
+///
+/// The depth is interpolated linearly between the end points and tested like the depth of
+/// the faces, so lines are hidden behind solid geometry. The parts of the line outside of the
+/// buffer are skipped.
+///
+/// # Arguments
+///
+/// * `buffer` - Display buffer (render target)
+/// * `a` - Start point, x and y in pixels and z the depth
+/// * `b` - End point, x and y in pixels and z the depth
+/// * `color` - Color of the line
+/// * `width` - Width of the line in pixels, measured perpendicular to the line
+/// * `antialiased` - Blend the partially covered pixels at the edges of the line with the
+///   pixels behind them, otherwise pixels are drawn when at least half covered
+pub fn draw_line(
+    buffer: &mut DisplayBuffer,
+    a: Vector3<f32>,
+    b: Vector3<f32>,
+    color: Color,
+    width: f32,
+    antialiased: bool,
+) {
+    if buffer.width == 0 || buffer.height == 0 {
+        return;
+    }
+    // Clip the line to the buffer (Liang-Barsky) so that end points far outside of the
+    // buffer do not add steps. The margin keeps the edges of wide lines just outside of
+    // the buffer
+    let d = b - a;
+    let margin = 0.5 * width + 1.0;
+    let right = (buffer.width - 1) as f32 + margin;
+    let top = (buffer.height - 1) as f32 + margin;
+    let mut t0: f32 = 0.0;
+    let mut t1: f32 = 1.0;
+    let edges = [
+        (-d.x, a.x + margin),
+        (d.x, right - a.x),
+        (-d.y, a.y + margin),
+        (d.y, top - a.y),
+    ];
+    for &(p, q) in edges.iter() {
+        if p == 0.0 {
+            if q < 0.0 {
+                return;
             }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 {
+        return;
+    }
+    let start = a + d * t0;
+    let end = a + d * t1;
+
+    // The line is drawn as a span of pixels across the minor axis for each pixel along the
+    // major axis. The span is longer than the width for diagonal lines so that the width
+    // perpendicular to the line stays the same
+    let horizontal = d.x.abs() >= d.y.abs();
+    let major = d.x.abs().max(d.y.abs());
+    let half_span = if major > 0.0 {
+        0.5 * width * d.xy().norm() / major
+    } else {
+        0.5 * width
+    };
+
+    // One sample at each pixel center along the major axis
+    let (a_major, d_major) = if horizontal { (a.x, d.x) } else { (a.y, d.y) };
+    let (s_major, e_major) = if horizontal {
+        (start.x, end.x)
+    } else {
+        (start.y, end.y)
+    };
+    let first_along = s_major.min(e_major).round() as isize;
+    let last_along = s_major.max(e_major).round() as isize;
+    for along in first_along..=last_along {
+        let t = if major > 0.0 {
+            ((along as f32 - a_major) / d_major).max(t0).min(t1)
+        } else {
+            t0
+        };
+        let p = a + d * t;
+        let center = if horizontal { p.y } else { p.x };
+
+        let first = (center - half_span).round() as isize;
+        let last = (center + half_span).round() as isize;
+        for across in first..=last {
+            // Fraction of the pixel covered by the span
+            let top = (center + half_span).min(across as f32 + 0.5);
+            let bottom = (center - half_span).max(across as f32 - 0.5);
+            let coverage = (top - bottom).min(1.0);
+            if coverage <= 0.0 || (!antialiased && coverage < 0.5) {
+                continue;
+            }
+
+            let (x, y) = if horizontal {
+                (along, across)
+            } else {
+                (across, along)
+            };
+            if x < 0 || y < 0 {
+                continue;
+            }
+            let (x, y) = (x as usize, y as usize);
+            let c = match buffer.get_pixel(x, y) {
+                Some(behind) if antialiased && coverage < 1.0 => {
+                    color * coverage + behind * (1.0 - coverage)
+                }
+                _ => color,
+            };
+            buffer.set_pixel(x, y, p.z, c);
         }
     }
 }
 
+impl DisplayBuffer {
+    /// Draw a filled circle, e.g. a point sprite
+    ///
+    /// The whole disc has the same depth and is depth tested like the faces. The pixels at
+    /// the edge are blended with the pixels behind them by the fraction of the pixel inside
+    /// the circle.
+    ///
+    /// # Arguments
+    ///
+    /// * `cx` - X coordinate of the center in pixels
+    /// * `cy` - Y coordinate of the center in pixels
+    /// * `radius` - Radius of the circle in pixels
+    /// * `z` - Depth of the disc
+    /// * `color` - Color of the disc
+    pub fn draw_disc(&mut self, cx: f32, cy: f32, radius: f32, z: f32, color: Color) {
+        if radius <= 0.0 {
+            return;
+        }
+        let reach = radius + 0.5;
+        let x_min = (cx - reach).floor().max(0.0) as usize;
+        let y_min = (cy - reach).floor().max(0.0) as usize;
+        let x_max = ((cx + reach).ceil().max(0.0) as usize).min(self.width);
+        let y_max = ((cy + reach).ceil().max(0.0) as usize).min(self.height);
+
+        for y in y_min..y_max {
+            for x in x_min..x_max {
+                // Approximate the covered fraction by the distance of the pixel center to the
+                // edge, a pixel centered on the edge is half covered
+                let distance = Vector2::new(x as f32 - cx, y as f32 - cy).norm();
+                let coverage = (reach - distance).min(1.0);
+                if coverage <= 0.0 {
+                    continue;
+                }
+                let c = match self.get_pixel(x, y) {
+                    Some(behind) if coverage < 1.0 => color * coverage + behind * (1.0 - coverage),
+                    _ => color,
+                };
+                self.set_pixel(x, y, z, c);
+            }
+        }
+    }
+}
+
+impl Renderable for Face<Vector3<f32>> {
+    /// Draw a color-filled face
+    fn render(&self, buffer: &mut DisplayBuffer) {
+        self.render_with_mode(buffer, RenderMode::Shaded);
+    }
+}
+
+impl Renderable for Line3D {
+    /// Draw a line with the end points in clip space
+    ///
+    /// The part of the line in front of the near plane is cut off, the rest is projected to
+    /// raster space and depth tested against the faces.
+    fn render(&self, buffer: &mut DisplayBuffer) {
+        // Points behind the near plane of the projection have z < -w
+        let (mut a, mut b) = (self.a, self.b);
+        let (da, db) = (a.z + a.w, b.z + b.w);
+        if da < 0.0 && db < 0.0 {
+            return;
+        }
+        if da < 0.0 {
+            a = b + (a - b) * (db / (db - da));
+        } else if db < 0.0 {
+            b = a + (b - a) * (da / (da - db));
+        }
+
+        let to_raster = |p: Vector4<f32>| {
+            return Vector3::new(
+                (1.0 + p.x / p.w) * 0.5 * buffer.width as f32,
+                (1.0 + p.y / p.w) * 0.5 * buffer.height as f32,
+                buffer.map_depth(p.z / p.w),
+            );
+        };
+        let (a, b) = (to_raster(a), to_raster(b));
+        draw_line(buffer, a, b, self.color, 1.0, false);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::{Vertex, NO_MATERIAL};
+    use std::cell::Cell;
+    use std::rc::Rc;
 
     #[test]
     fn test_get_barycentric_ccw_inside() {
@@ -123,4 +629,556 @@ mod tests {
         assert!(w1 < 1.0);
         assert!(w2 < 1.0);
     }
+
+    fn vertex(x: f32, y: f32, normal: Vector3<f32>) -> Vertex<Vector3<f32>> {
+        return Vertex {
+            position: Vector3::new(x, y, 0.0),
+            color: FloatColor {
+                r: 1.0,
+                g: 0.0,
+                b: 0.0,
+                a: 1.0,
+            },
+            normal,
+            uv: Vector2::zeros(),
+            tangent: Vector3::zeros(),
+        };
+    }
+
+    #[test]
+    fn test_render_normals_mode_ignores_vertex_color() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let face = Face {
+            v0: vertex(0.0, 0.0, n),
+            v1: vertex(8.0, 0.0, n),
+            v2: vertex(0.0, 8.0, n),
+            material_id: NO_MATERIAL,
+        };
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+
+        face.render_with_mode(&mut buffer, RenderMode::Normals);
+
+        let index = ((buffer.height - 1 - 1) * buffer.width + 1) * buffer.bpp;
+        assert_eq!(buffer.data[index], 127);
+        assert_eq!(buffer.data[index + 1], 127);
+        assert_eq!(buffer.data[index + 2], 255);
+    }
+
+    #[test]
+    fn test_normalize_normals_toggles_fragment_normal_length() {
+        let face = Face {
+            v0: vertex(0.0, 0.0, Vector3::new(1.0, 0.0, 0.0)),
+            v1: vertex(8.0, 0.0, Vector3::new(0.0, 1.0, 0.0)),
+            v2: vertex(0.0, 8.0, Vector3::new(0.0, 0.0, 1.0)),
+            material_id: NO_MATERIAL,
+        };
+        let lengths = Rc::new(Cell::new((f32::MAX, 0.0f32)));
+        let record = lengths.clone();
+        let shader = move |f: &FragmentInput, _: &Uniforms| {
+            let (min, max) = record.get();
+            let length = f.normal.norm();
+            record.set((min.min(length), max.max(length)));
+            return Color::WHITE;
+        };
+
+        let uniforms = Uniforms::default();
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+        face.render_with_shader(&mut buffer, RenderMode::Shaded, Some(&shader), &uniforms);
+        let (min, max) = lengths.get();
+        assert!((min - 1.0).abs() < 1e-5 && (max - 1.0).abs() < 1e-5);
+
+        lengths.set((f32::MAX, 0.0));
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+        buffer.state.normalize_normals = false;
+        face.render_with_shader(&mut buffer, RenderMode::Shaded, Some(&shader), &uniforms);
+        assert!(lengths.get().0 < 0.9);
+    }
+
+    #[test]
+    fn test_render_with_shader_overrides_mode() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let mut face = Face {
+            v0: vertex(0.0, 0.0, n),
+            v1: vertex(8.0, 0.0, n),
+            v2: vertex(0.0, 8.0, n),
+            material_id: NO_MATERIAL,
+        };
+        face.v1.uv = Vector2::new(1.0, 0.0);
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+        let shader = |f: &FragmentInput, u: &Uniforms| Color {
+            r: (u.time * 10.0) as u8,
+            g: (f.uv.x * 255.0) as u8,
+            b: f.position.x as u8,
+            a: 255,
+        };
+        let uniforms = Uniforms {
+            time: 2.0,
+            ..Uniforms::default()
+        };
+
+        face.render_with_shader(&mut buffer, RenderMode::Normals, Some(&shader), &uniforms);
+
+        let index = ((buffer.height - 1 - 1) * buffer.width + 4) * buffer.bpp;
+        assert_eq!(buffer.data[index..index + 4], [20, 127, 4, 255]);
+    }
+
+    #[test]
+    fn test_hidden_fragments_are_not_shaded() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let mut near = Face {
+            v0: vertex(0.0, 0.0, n),
+            v1: vertex(8.0, 0.0, n),
+            v2: vertex(0.0, 8.0, n),
+            material_id: NO_MATERIAL,
+        };
+        near.v0.position.z = -0.5;
+        near.v1.position.z = -0.5;
+        near.v2.position.z = -0.5;
+        let far = Face {
+            v0: vertex(0.0, 0.0, n),
+            v1: vertex(4.0, 0.0, n),
+            v2: vertex(0.0, 4.0, n),
+            material_id: NO_MATERIAL,
+        };
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let shader = move |_: &FragmentInput, _: &Uniforms| {
+            counter.set(counter.get() + 1);
+            Color {
+                r: 0,
+                g: 0,
+                b: 255,
+                a: 255,
+            }
+        };
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+        buffer.enable_overdraw_counter();
+
+        near.render_with_mode(&mut buffer, RenderMode::Shaded);
+        let uniforms = Uniforms::default();
+        far.render_with_shader(&mut buffer, RenderMode::Shaded, Some(&shader), &uniforms);
+
+        assert_eq!(calls.get(), 0);
+        assert_eq!(buffer.overdraw.as_ref().unwrap().iter().max(), Some(&2));
+        assert!(buffer.data.chunks(4).all(|p| p[2] == 0));
+    }
+
+    #[test]
+    fn test_faces_below_min_area_are_skipped() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let sliver = Face {
+            v0: vertex(1.0, 1.0, n),
+            v1: vertex(8.0, 1.0, n),
+            v2: vertex(1.0, 1.2, n),
+            material_id: NO_MATERIAL,
+        };
+        let degenerate = Face {
+            v0: vertex(1.0, 1.0, n),
+            v1: vertex(4.0, 4.0, n),
+            v2: vertex(8.0, 8.0, n),
+            material_id: NO_MATERIAL,
+        };
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+        buffer.enable_overdraw_counter();
+
+        degenerate.render_with_mode(&mut buffer, RenderMode::Shaded);
+        assert!(buffer.overdraw.as_ref().unwrap().iter().all(|&n| n == 0));
+        sliver.render_with_mode(&mut buffer, RenderMode::Shaded);
+        assert!(buffer.overdraw.as_ref().unwrap().iter().any(|&n| n > 0));
+
+        buffer.clear();
+        buffer.state.min_face_area = 1.0;
+        sliver.render_with_mode(&mut buffer, RenderMode::Shaded);
+        assert!(buffer.overdraw.as_ref().unwrap().iter().all(|&n| n == 0));
+    }
+
+    #[test]
+    fn test_uv_derivatives_are_per_pixel_steps() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let mut face = Face {
+            v0: vertex(0.0, 0.0, n),
+            v1: vertex(8.0, 0.0, n),
+            v2: vertex(0.0, 4.0, n),
+            material_id: NO_MATERIAL,
+        };
+        face.v0.uv = Vector2::new(0.0, 0.0);
+        face.v1.uv = Vector2::new(1.0, 0.0);
+        face.v2.uv = Vector2::new(0.0, 1.0);
+
+        let (uv_dx, uv_dy) = face.uv_derivatives();
+
+        assert_eq!(uv_dx, Vector2::new(0.125, 0.0));
+        assert_eq!(uv_dy, Vector2::new(0.0, 0.25));
+    }
+
+    #[test]
+    fn test_render_wireframe_leaves_interior_empty() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let face = Face {
+            v0: vertex(1.0, 1.0, n),
+            v1: vertex(18.0, 1.0, n),
+            v2: vertex(1.0, 18.0, n),
+            material_id: NO_MATERIAL,
+        };
+        let mut buffer = DisplayBuffer::new(20, 20, 4);
+
+        face.render_with_mode(&mut buffer, RenderMode::Wireframe { width: 1.0 });
+
+        assert!(is_set(&buffer, 10, 1));
+        assert!(is_set(&buffer, 1, 10));
+        assert!(is_set(&buffer, 9, 10));
+        assert!(!is_set(&buffer, 5, 5));
+    }
+
+    #[test]
+    fn test_render_filled_wireframe_draws_visible_edges_over_fill() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let face = Face {
+            v0: vertex(1.0, 1.0, n),
+            v1: vertex(18.0, 1.0, n),
+            v2: vertex(1.0, 18.0, n),
+            material_id: NO_MATERIAL,
+        };
+        let mut nearer = Face {
+            v0: vertex(0.0, 0.0, n),
+            v1: vertex(8.0, 0.0, n),
+            v2: vertex(0.0, 8.0, n),
+            material_id: NO_MATERIAL,
+        };
+        for v in [&mut nearer.v0, &mut nearer.v1, &mut nearer.v2].iter_mut() {
+            v.position.z = -0.5;
+            v.color.r = 0.0;
+            v.color.g = 1.0;
+        }
+        let mode = RenderMode::FilledWireframe {
+            width: 1.0,
+            color: Color::BLUE,
+            offset: -0.01,
+        };
+        let mut buffer = DisplayBuffer::new(20, 20, 4);
+
+        nearer.render_with_mode(&mut buffer, RenderMode::Shaded);
+        face.render_with_mode(&mut buffer, mode);
+
+        assert_eq!(buffer.get_pixel(12, 1), Some(Color::BLUE));
+        assert_eq!(buffer.get_pixel(1, 12), Some(Color::BLUE));
+        assert_eq!(buffer.get_pixel(10, 5), Some(Color::RED));
+        assert_eq!(buffer.get_pixel(3, 1), Some(Color::GREEN));
+    }
+
+    #[test]
+    fn test_render_points_fills_more_with_higher_density() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let face = Face {
+            v0: vertex(1.0, 1.0, n),
+            v1: vertex(18.0, 1.0, n),
+            v2: vertex(1.0, 18.0, n),
+            material_id: NO_MATERIAL,
+        };
+        let covered = |buffer: &DisplayBuffer| {
+            return (0..20)
+                .flat_map(|y| (0..20).map(move |x| (x, y)))
+                .filter(|&(x, y)| is_set(buffer, x, y))
+                .count();
+        };
+        let points = |density: u32| {
+            let mut buffer = DisplayBuffer::new(20, 20, 4);
+            let mode = RenderMode::Points {
+                density,
+                radius: 0.5,
+            };
+            face.render_with_mode(&mut buffer, mode);
+            return covered(&buffer);
+        };
+
+        let mut filled = DisplayBuffer::new(20, 20, 4);
+        face.render_with_mode(&mut filled, RenderMode::Shaded);
+        let sparse = points(2);
+        let dense = points(8);
+
+        assert!(sparse > 0 && sparse < dense);
+        assert!(dense < covered(&filled));
+    }
+
+    #[test]
+    fn test_fragments_cover_right_triangle() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let mut face = Face {
+            v0: vertex(0.0, 0.0, n),
+            v1: vertex(4.0, 0.0, n),
+            v2: vertex(0.0, 4.0, n),
+            material_id: NO_MATERIAL,
+        };
+        face.v1.position.z = 1.0;
+        let area = Rect {
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 10,
+        };
+
+        let fragments: Vec<Fragment> = face.fragments(area).collect();
+
+        assert_eq!(fragments.len(), 15);
+        assert!(fragments.iter().all(|f| f.x + f.y <= 4));
+        assert_eq!(fragments[2].x, 2);
+        assert_eq!(fragments[2].y, 0);
+        assert_eq!(fragments[2].z, 0.5);
+        let clipped = Rect {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 9,
+        };
+        assert_eq!(face.fragments(clipped).count(), 5);
+    }
+
+    fn is_set(buffer: &DisplayBuffer, x: usize, y: usize) -> bool {
+        let index = ((buffer.height - y - 1) * buffer.width + x) * buffer.bpp;
+        return buffer.data[index + 3] != 0;
+    }
+
+    fn assert_covers_all_inside_pixels(face: &Face<Vector3<f32>>, buffer: &DisplayBuffer) {
+        let a = face.v0.position.remove_row(2);
+        let b = face.v1.position.remove_row(2);
+        let c = face.v2.position.remove_row(2);
+
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                let p = Vector2::new(x as f32, y as f32);
+                let (w0, w1, w2) = get_barycentric(a, b, c, p);
+                let inside = w0 >= 0.0 && w1 >= 0.0 && w2 >= 0.0;
+                assert_eq!(is_set(buffer, x, y), inside, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_fractional_bounding_box() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let face = Face {
+            v0: vertex(1.9, 1.1, n),
+            v1: vertex(7.5, 2.2, n),
+            v2: vertex(3.3, 8.7, n),
+            material_id: NO_MATERIAL,
+        };
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+
+        face.render(&mut buffer);
+
+        assert_covers_all_inside_pixels(&face, &buffer);
+    }
+
+    #[test]
+    fn test_render_clamps_to_buffer_edges() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let face = Face {
+            v0: vertex(-3.5, -2.5, n),
+            v1: vertex(14.2, 1.5, n),
+            v2: vertex(4.5, 12.8, n),
+            material_id: NO_MATERIAL,
+        };
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+
+        face.render(&mut buffer);
+
+        assert!(is_set(&buffer, 9, 2));
+        assert_covers_all_inside_pixels(&face, &buffer);
+    }
+
+    #[test]
+    fn test_render_scissor_leaves_border_untouched() {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let face = Face {
+            v0: vertex(-10.0, -10.0, n),
+            v1: vertex(30.0, -10.0, n),
+            v2: vertex(-10.0, 30.0, n),
+            material_id: NO_MATERIAL,
+        };
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+        let scissor = Rect {
+            x: 3,
+            y: 3,
+            width: 4,
+            height: 4,
+        };
+        buffer.state.scissor = Some(scissor);
+
+        face.render(&mut buffer);
+
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                assert_eq!(is_set(&buffer, x, y), scissor.contains(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_line_clips_to_buffer_and_tests_depth() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+        buffer.set_pixel(4, 5, 0.0, white);
+
+        draw_line(
+            &mut buffer,
+            Vector3::new(-20.0, 5.0, 0.5),
+            Vector3::new(30.0, 5.0, 0.5),
+            Color { a: 128, ..white },
+            1.0,
+            false,
+        );
+
+        for x in 0..buffer.width {
+            assert!(is_set(&buffer, x, 5));
+            assert!(!is_set(&buffer, x, 4));
+            assert!(!is_set(&buffer, x, 6));
+        }
+        let index = ((buffer.height - 5 - 1) * buffer.width + 4) * buffer.bpp;
+        assert_eq!(buffer.data[index + 3], 255);
+    }
+
+    #[test]
+    fn test_draw_line_width() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+
+        draw_line(
+            &mut buffer,
+            Vector3::new(1.0, 5.0, 0.0),
+            Vector3::new(8.0, 5.0, 0.0),
+            white,
+            3.0,
+            false,
+        );
+
+        for y in 0..buffer.height {
+            for x in 1..9 {
+                let inside = (4..7).contains(&y);
+                assert_eq!(is_set(&buffer, x, y), inside, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_line_antialiased_edges_blend() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(10, 10, 4);
+
+        draw_line(
+            &mut buffer,
+            Vector3::new(1.0, 5.0, 0.0),
+            Vector3::new(8.0, 5.0, 0.0),
+            white,
+            2.0,
+            true,
+        );
+
+        let pixel = |y: usize| {
+            let index = ((buffer.height - y - 1) * buffer.width + 4) * buffer.bpp;
+            buffer.data[index]
+        };
+        assert_eq!(pixel(5), 255);
+        assert_eq!(pixel(4), 128);
+        assert_eq!(pixel(6), 128);
+        assert_eq!(pixel(3), 0);
+    }
+
+    #[test]
+    fn test_draw_line_into_empty_buffer() {
+        let mut buffer = DisplayBuffer::new(0, 0, 4);
+
+        draw_line(
+            &mut buffer,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(8.0, 5.0, 0.0),
+            Color::WHITE,
+            1.0,
+            false,
+        );
+
+        assert!(buffer.data.is_empty());
+    }
+
+    #[test]
+    fn test_draw_disc_covers_radius() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(20, 20, 4);
+
+        buffer.draw_disc(10.0, 10.0, 3.0, 0.0, white);
+
+        assert_eq!(buffer.get_pixel(10, 10), Some(white));
+        assert_eq!(buffer.get_pixel(11, 12), Some(white));
+        assert_eq!(buffer.get_pixel(10, 13).map(|c| c.r), Some(128));
+        assert_eq!(buffer.get_pixel(13, 13).map(|c| c.a), Some(0));
+        assert_eq!(buffer.get_pixel(10, 15).map(|c| c.a), Some(0));
+        assert_eq!(buffer.z_buffer[9 * 20 + 10], 0.0);
+    }
+
+    #[test]
+    fn test_draw_disc_is_depth_tested() {
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let black = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let mut buffer = DisplayBuffer::new(20, 20, 4);
+        buffer.set_pixel(10, 10, -0.5, black);
+
+        buffer.draw_disc(10.0, 10.0, 2.0, 0.5, white);
+
+        assert_eq!(buffer.get_pixel(10, 10), Some(black));
+        assert_eq!(buffer.get_pixel(11, 10), Some(white));
+    }
+
+    #[test]
+    fn test_line_is_cut_at_the_near_plane() {
+        let mut buffer = DisplayBuffer::new(20, 20, 4);
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        // From the center of the view to a point behind the near plane on the right
+        let line = Line3D {
+            a: Vector4::new(0.0, 0.0, 0.0, 1.0),
+            b: Vector4::new(2.0, 0.0, -1.2, -1.0),
+            color: white,
+        };
+
+        line.render(&mut buffer);
+
+        let written: Vec<usize> = (0..20)
+            .filter(|&x| (0..20).any(|y| buffer.get_pixel(x, y) == Some(white)))
+            .collect();
+        assert!(!written.is_empty());
+        assert!(written.iter().all(|&x| x >= 10), "{:?}", written);
+    }
 }
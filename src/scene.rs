@@ -0,0 +1,729 @@
+// Scene of meshes rendered together with one camera
+
+use core::{
+    build_perspective_matrix, build_view_matrix, Color, DisplayBuffer, DrawContext,
+    FragmentShaderFn, Handedness, Line3D, Mesh, RenderState, Renderable, ANGLE_OF_VIEW, FAR_PLANE,
+    NEAR_PLANE,
+};
+use na::{Matrix4, Vector3, Vector4};
+use rasterization::{FragmentInput, Uniforms};
+
+/// Smallest distance to the near plane that `Scene::bounds_clip_planes` returns, geometry
+/// that reaches closer to the eye is clipped
+const MIN_NEAR_PLANE: f32 = 0.01;
+/// Fraction of the depth extent of the scene added in front of and behind it so that the
+/// nearest and farthest points are not clipped by rounding
+const CLIP_PLANE_MARGIN: f32 = 0.01;
+
+/// Copies of one mesh drawn at several places
+pub struct Instances {
+    /// Shared geometry, materials and render state of the copies. The position and the
+    /// angle of the mesh are not used
+    pub mesh: Mesh,
+    /// Model matrix of each copy, from the local space of the mesh to the world space
+    pub transforms: Vec<Matrix4<f32>>,
+}
+
+/// Collection of meshes and lines that are drawn into the same display buffer
+pub struct Scene {
+    /// Meshes of the scene in drawing order
+    pub meshes: Vec<Mesh>,
+    /// Instanced meshes, drawn after `meshes` in order, see `add_instanced`
+    pub instances: Vec<Instances>,
+    /// Line segments in world space (e.g. debug geometry), drawn after the meshes and depth
+    /// tested against them
+    pub lines: Vec<Line3D>,
+    /// Animation time in seconds passed to the fragment shaders
+    pub time: f32,
+    /// Fit the clipping planes of the display buffer to the bounding boxes of the visible
+    /// meshes and the lines on every render, see `bounds_clip_planes`. When not set, the
+    /// `RenderState::clip_planes` are used as they are.
+    pub fit_clip_planes: bool,
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        return Scene::new();
+    }
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        return Scene {
+            meshes: Vec::new(),
+            instances: Vec::new(),
+            lines: Vec::new(),
+            time: 0.0,
+            fit_clip_planes: false,
+        };
+    }
+
+    /// Add a mesh that is drawn once for each transform, e.g. the trees of a forest
+    ///
+    /// The faces are stored once and transformed with the model matrix of each copy in
+    /// place of the placement of the mesh, so many copies take little more memory than one.
+    /// The copies are drawn, depth rendered and fitted into the clipping planes like the
+    /// other meshes, but are not numbered in `render_ids`.
+    ///
+    /// # Arguments
+    ///
+    /// * `mesh` - Geometry and render state of the copies
+    /// * `transforms` - Model matrix of each copy
+    pub fn add_instanced(&mut self, mesh: Mesh, transforms: &[Matrix4<f32>]) {
+        self.instances.push(Instances {
+            mesh,
+            transforms: transforms.to_vec(),
+        });
+    }
+
+    /// Meshes with the model matrices of their visible copies, the meshes first
+    fn visible_models(&self) -> impl Iterator<Item = (&Mesh, Matrix4<f32>)> {
+        let meshes = self.meshes.iter().map(|m| (m, m.model_matrix()));
+        let instances = self
+            .instances
+            .iter()
+            .flat_map(|i| i.transforms.iter().map(move |&t| (&i.mesh, t)));
+        return meshes.chain(instances).filter(|&(m, _)| m.visible);
+    }
+
+    /// Distances (near, far) from the eye to clipping planes that enclose the scene
+    ///
+    /// The planes are fitted to the corners of the bounding box of each visible mesh in world
+    /// space and to the end points of the lines, so the depth range wraps the geometry with
+    /// a small margin. The near plane is kept at least 0.01 in front of the eye. Returns
+    /// `None` when nothing is in front of the eye.
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    pub fn bounds_clip_planes(
+        &self,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+    ) -> Option<(f32, f32)> {
+        let view = build_view_matrix(eye, lookat, Vector3::new(0.0, 1.0, 0.0));
+        let mut points: Vec<Vector4<f32>> = Vec::new();
+        for (mesh, model) in self.visible_models() {
+            if !mesh.faces.is_empty() {
+                points.extend_from_slice(&mesh.clip_space_bounds(view * model));
+            }
+        }
+        for line in self.lines.iter() {
+            points.push(view * line.a);
+            points.push(view * line.b);
+        }
+
+        // The camera looks down -z, so the distance in front of the eye is -z
+        let distances = points.iter().map(|p| -p.z / p.w);
+        let (near, far) = distances.fold((f32::MAX, f32::MIN), |(near, far), d| {
+            (near.min(d), far.max(d))
+        });
+        if far <= MIN_NEAR_PLANE {
+            return None;
+        }
+        let margin = (far - near) * CLIP_PLANE_MARGIN;
+        let near = (near - margin).max(MIN_NEAR_PLANE);
+        let far = (far + margin).max(near * (1.0 + CLIP_PLANE_MARGIN));
+        return Some((near, far));
+    }
+
+    /// Render all visible meshes and the lines of the scene into a display buffer
+    ///
+    /// When `fit_clip_planes` is set, the fitted planes are written to
+    /// `buffer.state.clip_planes` and stay there after the draw. Use `render_with_state` to
+    /// keep the planes of the buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    /// * `buffer` - Display buffer (render target)
+    /// * `shader` - Colors the fragments of all meshes when set, otherwise each mesh is drawn
+    ///   with its own fragment shader or render mode
+    pub fn render(
+        &self,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        buffer: &mut DisplayBuffer,
+        shader: Option<&FragmentShaderFn>,
+    ) {
+        if self.fit_clip_planes {
+            if let Some(planes) = self.bounds_clip_planes(eye, lookat) {
+                buffer.state.clip_planes = Some(planes);
+            }
+        }
+
+        for (mesh, model) in self.visible_models() {
+            let shader = shader.or(mesh.fragment_shader.as_deref());
+            let context = DrawContext::new(eye, lookat, buffer, shader, self.time);
+            mesh.draw(model, &context, buffer);
+        }
+
+        if !self.lines.is_empty() {
+            let view_projection = buffer.projection_matrix() * buffer.view_matrix(eye, lookat);
+            for line in self.lines.iter() {
+                line.transform(view_projection).render(buffer);
+            }
+        }
+    }
+
+    /// Render the scene with other pipeline settings than those of the buffer
+    ///
+    /// The settings of the buffer are replaced by the state for the draw and restored after
+    /// it, including the clipping planes that `fit_clip_planes` sets.
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    /// * `buffer` - Display buffer (render target)
+    /// * `shader` - Colors the fragments of all meshes when set, see `render`
+    /// * `state` - Pipeline settings of the draw
+    pub fn render_with_state(
+        &self,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        buffer: &mut DisplayBuffer,
+        shader: Option<&FragmentShaderFn>,
+        state: &RenderState,
+    ) {
+        let saved = buffer.state;
+        buffer.state = *state;
+        self.render(eye, lookat, buffer, shader);
+        buffer.state = saved;
+    }
+
+    /// Render only the depth of the scene as seen from a point, e.g. a light source
+    ///
+    /// The visible meshes go through the same transforms, culling and clipping as in `render`, but
+    /// the fragments are not shaded. Returns the depth buffer with the rows stored from the
+    /// top to the bottom, pixels not covered by any mesh have the depth `f32::MAX`.
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position to render from
+    /// * 'lookat' - Focus point of the eye
+    /// * `width` - Width of the depth buffer in pixels
+    /// * `height` - Height of the depth buffer in pixels
+    pub fn render_depth_from(
+        &self,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        width: usize,
+        height: usize,
+    ) -> Box<[f32]> {
+        let mut buffer = DisplayBuffer::new(width, height, 4);
+        let unshaded = |_: &FragmentInput, _: &Uniforms| Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+        for (mesh, model) in self.visible_models() {
+            let context = DrawContext::new(eye, lookat, &buffer, Some(&unshaded), self.time);
+            mesh.draw(model, &context, &mut buffer);
+        }
+        return buffer.z_buffer;
+    }
+
+    /// Render the ID of the nearest face at each pixel, e.g. for picking with the cursor
+    ///
+    /// The faces are numbered from 1 through all meshes in order, hidden meshes included, so
+    /// the IDs stay the same when meshes are shown or hidden. Pixels not covered by any face
+    /// have the ID 0. The visible meshes go through the same transforms, culling and clipping
+    /// as in `render`, but the faces are filled with their IDs whatever their render mode.
+    /// Returns the IDs with the rows stored from the top to the bottom, read them with
+    /// `read_id` and find the face of an ID with `face_of_id`.
+    ///
+    /// # Arguments
+    ///
+    /// * `eye` - Position of the camera eye
+    /// * 'lookat' - Focus point of the eye
+    /// * `width` - Width of the ID buffer in pixels
+    /// * `height` - Height of the ID buffer in pixels
+    pub fn render_ids(
+        &self,
+        eye: Vector3<f32>,
+        lookat: Vector3<f32>,
+        width: usize,
+        height: usize,
+    ) -> Box<[u32]> {
+        let mut buffer = DisplayBuffer::new(width, height, 4);
+        let mut first_id = 1;
+        for mesh in self.meshes.iter() {
+            if mesh.visible {
+                let mut context = DrawContext::new(eye, lookat, &buffer, None, self.time);
+                context.first_id = Some(first_id);
+                mesh.draw(mesh.model_matrix(), &context, &mut buffer);
+            }
+            first_id += mesh.faces.len() as u32;
+        }
+        return buffer
+            .data
+            .chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+    }
+
+    /// Mesh index and face index of a face ID of `render_ids`, `None` for the ID 0 and for
+    /// IDs past the last face
+    pub fn face_of_id(&self, id: u32) -> Option<(usize, usize)> {
+        let mut index = (id as usize).checked_sub(1)?;
+        for (mesh_index, mesh) in self.meshes.iter().enumerate() {
+            if index < mesh.faces.len() {
+                return Some((mesh_index, index));
+            }
+            index -= mesh.faces.len();
+        }
+        return None;
+    }
+
+    /// Render the depth of the scene from a light into a shadow map
+    ///
+    /// # Arguments
+    ///
+    /// * `light` - Position of the light
+    /// * 'lookat' - Point the light is aimed at, the shadows are cast inside the angle of view
+    /// * `size` - Width and height of the shadow map in pixels
+    /// * `bias` - Depth bias of the shadow test, see `ShadowMap::bias`
+    pub fn render_shadow_map(
+        &self,
+        light: Vector3<f32>,
+        lookat: Vector3<f32>,
+        size: usize,
+        bias: f32,
+    ) -> ShadowMap {
+        let view = build_view_matrix(light, lookat, Vector3::new(0.0, 1.0, 0.0));
+        let projection = build_perspective_matrix(
+            NEAR_PLANE,
+            FAR_PLANE,
+            ANGLE_OF_VIEW,
+            1.0,
+            Handedness::RightHanded,
+        );
+        return ShadowMap {
+            depth: self.render_depth_from(light, lookat, size, size),
+            size,
+            light_matrix: projection * view,
+            bias,
+        };
+    }
+}
+
+/// Depth of a scene as seen from a light, used to find the points in the shadow
+pub struct ShadowMap {
+    /// Depth buffer of the light pass in normalized device coordinates, rows from top to bottom
+    pub depth: Box<[f32]>,
+    /// Width and height of the depth buffer in pixels
+    pub size: usize,
+    /// Transforms world positions into the clip space of the light
+    pub light_matrix: Matrix4<f32>,
+    /// Depth added to the stored depth before the comparison
+    ///
+    /// Without a bias a surface shadows itself in a pattern of stripes (shadow acne) because
+    /// the stored depth is rounded to the pixels of the shadow map.
+    pub bias: f32,
+}
+
+impl ShadowMap {
+    /// Check whether a world position is hidden from the light by nearer geometry
+    ///
+    /// Positions outside the view of the light are not in the shadow.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - Position in world space
+    pub fn is_shadowed(&self, p: Vector3<f32>) -> bool {
+        let clip = self.light_matrix * Vector4::new(p.x, p.y, p.z, 1.0);
+        if clip.w <= 0.0 {
+            return false;
+        }
+        let size = self.size as f32;
+        let x = ((1.0 + clip.x / clip.w) * 0.5 * size).round();
+        let y = ((1.0 + clip.y / clip.w) * 0.5 * size).round();
+        if x < 0.0 || y < 0.0 || x >= size || y >= size {
+            return false;
+        }
+        let index = (self.size - y as usize - 1) * self.size + x as usize;
+        return clip.z / clip.w > self.depth[index] + self.bias;
+    }
+}
+
+/// ID of a pixel of an ID buffer of `Scene::render_ids`, 0 outside of the buffer
+///
+/// # Arguments
+///
+/// * `ids` - ID buffer with the rows stored from the top to the bottom
+/// * `width` - Width of the ID buffer in pixels
+/// * `x` - X coordinate in pixels, value 0 corresponds to left edge
+/// * `y` - Y coordinate in pixels, value 0 corresponds to bottom edge
+pub fn read_id(ids: &[u32], width: usize, x: usize, y: usize) -> u32 {
+    let height = ids.len() / width.max(1);
+    if x >= width || y >= height {
+        return 0;
+    }
+    return ids[(height - y - 1) * width + x];
+}
+
+/// Render a scene into tightly packed RGBA bytes
+///
+/// Returns `width * height * 4` bytes with one byte per channel in the order red, green,
+/// blue, alpha. The rows are stored from the top of the image to the bottom, so the bytes
+/// can be copied as they are into an image or a canvas (e.g. `ImageData`). Pixels not
+/// covered by any mesh are transparent black.
+///
+/// # Arguments
+///
+/// * `scene` - Scene to render
+/// * `eye` - Position of the camera eye
+/// * 'lookat' - Focus point of the eye
+/// * `width` - Width of the image in pixels
+/// * `height` - Height of the image in pixels
+pub fn render_to_rgba(
+    scene: &Scene,
+    eye: Vector3<f32>,
+    lookat: Vector3<f32>,
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    let mut buffer = DisplayBuffer::new(width, height, 4);
+    scene.render(eye, lookat, &mut buffer, None);
+    return buffer.data.into_vec();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{Face, FloatColor, RenderMode, Vertex, NO_MATERIAL};
+    use na::{Vector2, Vector4};
+
+    fn triangle_mesh(x: f32) -> Mesh {
+        let vertex = |px: f32, py: f32| Vertex {
+            position: Vector4::new(px, py, 0.0, 1.0),
+            color: FloatColor {
+                r: 1.0,
+                g: 0.0,
+                b: 0.0,
+                a: 1.0,
+            },
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            uv: Vector2::new(px, py),
+            tangent: Vector3::zeros(),
+        };
+        let mut mesh = Mesh::new();
+        mesh.faces.push(Face {
+            v0: vertex(0.0, 0.5),
+            v1: vertex(-0.5, -0.5),
+            v2: vertex(0.5, -0.5),
+            material_id: NO_MATERIAL,
+        });
+        mesh.position = Vector4::new(x, 0.0, -3.0, 1.0);
+        return mesh;
+    }
+
+    #[test]
+    fn test_render_with_shader_colors_all_meshes() {
+        let mut scene = Scene::new();
+        scene.meshes.push(triangle_mesh(-1.0));
+        scene.meshes.push(triangle_mesh(1.0));
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+        let blue = |_: &FragmentInput, _: &Uniforms| Color {
+            r: 0,
+            g: 0,
+            b: 255,
+            a: 255,
+        };
+
+        let mut default = DisplayBuffer::new(40, 30, 4);
+        scene.render(eye, lookat, &mut default, None);
+        let mut shaded = DisplayBuffer::new(40, 30, 4);
+        scene.render(eye, lookat, &mut shaded, Some(&blue));
+
+        let pixels = |buffer: &DisplayBuffer| -> Vec<[u8; 4]> {
+            buffer
+                .data
+                .chunks(4)
+                .filter(|p| p[3] != 0)
+                .map(|p| [p[0], p[1], p[2], p[3]])
+                .collect()
+        };
+        let default = pixels(&default);
+        let shaded = pixels(&shaded);
+        assert!(!shaded.is_empty());
+        assert_eq!(shaded.len(), default.len());
+        assert!(default.iter().all(|p| p[0] > 0 && p[2] == 0));
+        assert!(shaded.iter().all(|p| *p == [0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn test_render_skips_invisible_meshes() {
+        let mut scene = Scene::new();
+        scene.meshes.push(triangle_mesh(0.0));
+        scene.meshes[0].visible = false;
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        buffer.enable_overdraw_counter();
+
+        scene.render(eye, lookat, &mut buffer, None);
+
+        assert!(buffer.overdraw.as_ref().unwrap().iter().all(|&n| n == 0));
+        assert!(scene
+            .render_depth_from(eye, lookat, 40, 30)
+            .iter()
+            .all(|&z| z == f32::MAX));
+    }
+
+    #[test]
+    fn test_render_hides_lines_behind_meshes() {
+        let mut scene = Scene::new();
+        scene.meshes.push(triangle_mesh(0.0));
+        let green = Color {
+            r: 0,
+            g: 255,
+            b: 0,
+            a: 255,
+        };
+        scene.lines.push(Line3D {
+            a: Vector4::new(-3.0, 0.0, -4.0, 1.0),
+            b: Vector4::new(3.0, 0.0, -4.0, 1.0),
+            color: green,
+        });
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+
+        scene.render(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            &mut buffer,
+            None,
+        );
+
+        let column_has_line =
+            |x: usize| (0..buffer.height).any(|y| buffer.get_pixel(x, y) == Some(green));
+        assert!(column_has_line(5));
+        assert!(column_has_line(35));
+        assert!(!column_has_line(20));
+    }
+
+    #[test]
+    fn test_bounds_clip_planes_wrap_visible_meshes() {
+        let mut scene = Scene::new();
+        scene.meshes.push(triangle_mesh(0.0));
+        let mut far = triangle_mesh(0.0);
+        far.position.z = -20.0;
+        scene.meshes.push(far);
+        let mut hidden = triangle_mesh(0.0);
+        hidden.position.z = -50.0;
+        hidden.visible = false;
+        scene.meshes.push(hidden);
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+
+        let (near, far) = scene.bounds_clip_planes(eye, lookat).unwrap();
+
+        assert!(near > 2.5 && near < 3.0);
+        assert!(far > 20.0 && far < 21.0);
+        assert_eq!(scene.bounds_clip_planes(eye, -lookat), None);
+    }
+
+    #[test]
+    fn test_render_fits_clip_planes_unless_overridden() {
+        let mut scene = Scene::new();
+        let mut mesh = triangle_mesh(0.0);
+        mesh.position.z = -150.0;
+        scene.meshes.push(mesh);
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+        let drawn = |scene: &Scene, buffer: &mut DisplayBuffer| {
+            scene.render(eye, lookat, buffer, None);
+            return buffer.z_buffer.iter().any(|&z| z != f32::MAX);
+        };
+
+        // Beyond the default far plane
+        assert!(!drawn(&scene, &mut DisplayBuffer::new(400, 300, 4)));
+
+        scene.fit_clip_planes = true;
+        let mut buffer = DisplayBuffer::new(400, 300, 4);
+        assert!(drawn(&scene, &mut buffer));
+        let (near, far) = buffer.clip_planes();
+        assert!(near <= 150.0 && far > 150.0);
+
+        scene.fit_clip_planes = false;
+        let mut buffer = DisplayBuffer::new(400, 300, 4);
+        buffer.state.clip_planes = Some((0.1, 200.0));
+        assert!(drawn(&scene, &mut buffer));
+        assert_eq!(buffer.clip_planes(), (0.1, 200.0));
+    }
+
+    #[test]
+    fn test_render_to_rgba_stores_rows_top_down() {
+        let mut scene = Scene::new();
+        let mut mesh = triangle_mesh(0.0);
+        mesh.position.y = 1.0;
+        scene.meshes.push(mesh);
+
+        let rgba = render_to_rgba(
+            &scene,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            40,
+            30,
+        );
+
+        assert_eq!(rgba.len(), 40 * 30 * 4);
+        let written_rows: Vec<usize> = (0..30)
+            .filter(|row| {
+                rgba[row * 160..(row + 1) * 160]
+                    .chunks(4)
+                    .any(|p| p[3] != 0)
+            })
+            .collect();
+        assert!(!written_rows.is_empty());
+        assert!(written_rows.iter().all(|&row| row < 15));
+    }
+
+    #[test]
+    fn test_render_depth_from_matches_depth_of_render() {
+        let mut scene = Scene::new();
+        scene.meshes.push(triangle_mesh(0.0));
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+
+        let depth = scene.render_depth_from(eye, lookat, 40, 30);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        scene.render(eye, lookat, &mut buffer, None);
+
+        assert_eq!(depth.len(), 40 * 30);
+        assert_eq!(depth, buffer.z_buffer);
+        assert!(depth.iter().any(|&z| z < 1.0));
+        assert_eq!(depth[0], f32::MAX);
+    }
+
+    #[test]
+    fn test_render_ids_picks_the_face_under_a_pixel() {
+        let mut scene = Scene::new();
+        let mut left = triangle_mesh(-1.0);
+        left.faces.insert(0, left.faces[0]);
+        left.faces[0].v0.position.x += 10.0;
+        left.render_mode = RenderMode::Wireframe { width: 1.0 };
+        scene.meshes.push(left);
+        scene.meshes.push(triangle_mesh(1.0));
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+        let lookat = Vector3::new(0.0, 0.0, -1.0);
+
+        let ids = scene.render_ids(eye, lookat, 40, 30);
+
+        assert_eq!(ids.len(), 40 * 30);
+        assert_eq!(read_id(&ids, 40, 0, 29), 0);
+        assert_eq!(scene.face_of_id(read_id(&ids, 40, 12, 13)), Some((0, 1)));
+        assert_eq!(scene.face_of_id(read_id(&ids, 40, 28, 13)), Some((1, 0)));
+        assert_eq!(scene.face_of_id(0), None);
+        assert_eq!(scene.face_of_id(4), None);
+    }
+
+    #[test]
+    fn test_instances_draw_the_mesh_at_each_transform() {
+        let fragments = |scene: &Scene| {
+            let mut buffer = DisplayBuffer::new(40, 30, 4);
+            buffer.enable_overdraw_counter();
+            scene.render(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, -1.0),
+                &mut buffer,
+                None,
+            );
+            return buffer.overdraw.unwrap().iter().sum::<u32>();
+        };
+        let at = Matrix4::new_translation(&Vector3::new(0.5, 0.0, -3.0));
+        let mut one = Scene::new();
+        one.add_instanced(triangle_mesh(0.0), &[at]);
+        let mut three = Scene::new();
+        three.add_instanced(triangle_mesh(5.0), &[at, at, at]);
+
+        let single = fragments(&one);
+
+        assert!(single > 0);
+        assert_eq!(fragments(&three), 3 * single);
+        assert_eq!(three.instances[0].mesh.faces.len(), 1);
+    }
+
+    /// Square in the plane y = 0 facing up
+    fn quad_mesh(half_size: f32, position: Vector4<f32>) -> Mesh {
+        let vertex = |x: f32, z: f32| Vertex {
+            position: Vector4::new(x * half_size, 0.0, z * half_size, 1.0),
+            color: FloatColor::WHITE,
+            normal: Vector3::new(0.0, 1.0, 0.0),
+            uv: Vector2::new(x, z),
+            tangent: Vector3::zeros(),
+        };
+        let mut mesh = Mesh::new();
+        for &(a, b, c) in [
+            ((-1.0, 1.0), (1.0, 1.0), (1.0, -1.0)),
+            ((-1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)),
+        ]
+        .iter()
+        {
+            mesh.faces.push(Face {
+                v0: vertex(a.0, a.1),
+                v1: vertex(b.0, b.1),
+                v2: vertex(c.0, c.1),
+                material_id: NO_MATERIAL,
+            });
+        }
+        mesh.position = position;
+        return mesh;
+    }
+
+    #[test]
+    fn test_apply_shadows_darkens_plane_under_occluder() {
+        let plane = || quad_mesh(3.0, Vector4::new(0.0, -1.0, -4.0, 1.0));
+        let occluder = || quad_mesh(0.5, Vector4::new(0.0, 0.0, -4.0, 1.0));
+        let eye = Vector3::new(0.0, 0.5, 0.0);
+        let lookat = Vector3::new(0.0, -1.0, -4.0);
+        let light = Vector3::new(1.0, 4.0, -4.0);
+        let pixels = |meshes: Vec<Mesh>, shadow_caster: Option<&Scene>| {
+            let mut scene = Scene::new();
+            scene.meshes = meshes;
+            let mut buffer = DisplayBuffer::new(40, 30, 4);
+            scene.render(eye, lookat, &mut buffer, None);
+            let caster = shadow_caster.unwrap_or(&scene);
+            let shadow_map = caster.render_shadow_map(light, lookat, 64, 0.002);
+            let lit = buffer.data.clone();
+            buffer.apply_shadows(eye, lookat, &shadow_map, 0.5);
+            return (lit, buffer.data);
+        };
+
+        let (lit, shadowed) = pixels(vec![plane(), occluder()], None);
+        let (occluder_only, _) = pixels(vec![occluder()], None);
+        let (plane_lit, plane_shadowed) = pixels(vec![plane()], None);
+
+        let darkened: Vec<usize> = (0..lit.len() / 4)
+            .filter(|&i| lit[i * 4..i * 4 + 3] != shadowed[i * 4..i * 4 + 3])
+            .collect();
+        assert!(!darkened.is_empty());
+        for &i in darkened.iter() {
+            assert_eq!(occluder_only[i * 4 + 3], 0, "occluder shadows itself");
+            assert_eq!(shadowed[i * 4], lit[i * 4] / 2);
+        }
+        assert_eq!(plane_lit, plane_shadowed, "plane shadows itself");
+    }
+
+    #[test]
+    fn test_apply_shadows_skips_camera_without_view() {
+        let mut scene = Scene::new();
+        scene
+            .meshes
+            .push(quad_mesh(3.0, Vector4::new(0.0, -1.0, -4.0, 1.0)));
+        let eye = Vector3::new(0.0, 0.5, 0.0);
+        let mut buffer = DisplayBuffer::new(40, 30, 4);
+        scene.render(eye, Vector3::new(0.0, -1.0, -4.0), &mut buffer, None);
+        let shadow_map = scene.render_shadow_map(Vector3::new(1.0, 4.0, -4.0), eye, 64, 0.002);
+        let lit = buffer.data.clone();
+
+        buffer.apply_shadows(eye, eye, &shadow_map, 0.5);
+
+        assert_eq!(buffer.data, lit);
+    }
+}
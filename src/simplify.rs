@@ -0,0 +1,470 @@
+// Mesh simplification by edge collapses
+//
+// The decimation follows the quadric error metric of Garland and Heckbert: every vertex keeps
+// the sum of the squared distances to the planes of the triangles around it as a 4x4 matrix,
+// so the error of moving the vertex anywhere is a single quadratic form. The edge with the
+// smallest error is collapsed into one vertex at the position of least error, and the two
+// quadrics are added, until the face budget is reached.
+//
+// The vertices are welded by position first, so seams of the normals or the texture
+// coordinates do not keep the surface apart. The edges of an open surface get extra planes
+// perpendicular to the surface that hold the outline in place.
+
+use core::{triangle_normal, Face, Mesh, Vertex};
+use na::{Matrix4, Vector3, Vector4};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Weight of the planes that keep the open edges of the surface in place
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+/// Candidate collapse of an edge, ordered so that the heap pops the smallest error first
+struct Collapse {
+    error: f64,
+    /// Vertex that stays, moved to the target position
+    keep: usize,
+    /// Vertex merged into `keep`
+    remove: usize,
+    /// Versions of the two vertices when the candidate was made, a collapse of either vertex
+    /// makes the candidate stale
+    versions: (u32, u32),
+    target: Vector3<f64>,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        return other.error.total_cmp(&self.error);
+    }
+}
+
+/// Quadric of the squared distance to the plane through a point with a unit normal
+fn plane_quadric(normal: Vector3<f64>, point: Vector3<f64>, weight: f64) -> Matrix4<f64> {
+    let plane = Vector4::new(normal.x, normal.y, normal.z, -normal.dot(&point));
+    return plane * plane.transpose() * weight;
+}
+
+/// Squared distance of a point to the planes of a quadric
+fn quadric_error(q: &Matrix4<f64>, p: Vector3<f64>) -> f64 {
+    let v = Vector4::new(p.x, p.y, p.z, 1.0);
+    return v.dot(&(q * v));
+}
+
+/// Triangles of a mesh welded by position, with the state of the collapses
+struct Decimation {
+    positions: Vec<Vector3<f64>>,
+    /// Vertex of the input that gives each welded vertex its color and texture coordinates
+    attributes: Vec<Vertex<Vector4<f32>>>,
+    quadrics: Vec<Matrix4<f64>>,
+    versions: Vec<u32>,
+    removed: Vec<bool>,
+    triangles: Vec<[usize; 3]>,
+    material_ids: Vec<usize>,
+    alive: Vec<bool>,
+    /// Triangles around each vertex, may list triangles that are no longer alive
+    vertex_triangles: Vec<Vec<usize>>,
+}
+
+impl Decimation {
+    fn new(faces: &[Face<Vector4<f32>>]) -> Decimation {
+        let mut d = Decimation {
+            positions: Vec::new(),
+            attributes: Vec::new(),
+            quadrics: Vec::new(),
+            versions: Vec::new(),
+            removed: Vec::new(),
+            triangles: Vec::with_capacity(faces.len()),
+            material_ids: Vec::with_capacity(faces.len()),
+            alive: Vec::with_capacity(faces.len()),
+            vertex_triangles: Vec::new(),
+        };
+
+        let mut indices = HashMap::new();
+        for face in faces.iter() {
+            let mut triangle = [0; 3];
+            for (index, v) in triangle.iter_mut().zip([face.v0, face.v1, face.v2].iter()) {
+                let p = v.position.xyz() / v.position.w;
+                let key = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+                *index = *indices.entry(key).or_insert_with(|| {
+                    d.positions
+                        .push(Vector3::new(p.x as f64, p.y as f64, p.z as f64));
+                    d.attributes.push(*v);
+                    d.positions.len() - 1
+                });
+            }
+            // Triangles with two corners at the same position have no area to keep
+            if triangle[0] == triangle[1]
+                || triangle[1] == triangle[2]
+                || triangle[0] == triangle[2]
+            {
+                continue;
+            }
+            d.triangles.push(triangle);
+            d.material_ids.push(face.material_id);
+            d.alive.push(true);
+        }
+
+        let count = d.positions.len();
+        d.quadrics = vec![Matrix4::zeros(); count];
+        d.versions = vec![0; count];
+        d.removed = vec![false; count];
+        d.vertex_triangles = vec![Vec::new(); count];
+
+        let mut edge_triangles: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (t, triangle) in d.triangles.iter().enumerate() {
+            let n = d.normal(triangle);
+            let area = n.norm() * 0.5;
+            if let Some(n) = n.try_normalize(f64::EPSILON) {
+                let q = plane_quadric(n, d.positions[triangle[0]], area);
+                for &v in triangle.iter() {
+                    d.quadrics[v] += q;
+                }
+            }
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                d.vertex_triangles[a].push(t);
+                edge_triangles
+                    .entry((a.min(b), a.max(b)))
+                    .or_default()
+                    .push(t);
+            }
+        }
+
+        for (&(a, b), triangles) in edge_triangles.iter() {
+            if triangles.len() != 1 {
+                continue;
+            }
+            let edge = d.positions[b] - d.positions[a];
+            let n = d.normal(&d.triangles[triangles[0]]);
+            if let Some(side) = edge.cross(&n).try_normalize(f64::EPSILON) {
+                let q = plane_quadric(side, d.positions[a], BOUNDARY_WEIGHT * edge.norm_squared());
+                d.quadrics[a] += q;
+                d.quadrics[b] += q;
+            }
+        }
+
+        return d;
+    }
+
+    /// Normal of a triangle scaled by twice its area
+    fn normal(&self, triangle: &[usize; 3]) -> Vector3<f64> {
+        let p = |i: usize| self.positions[triangle[i]];
+        return (p(1) - p(0)).cross(&(p(2) - p(0)));
+    }
+
+    /// Distinct vertices that share an alive triangle with a vertex
+    fn neighbours(&self, v: usize) -> Vec<usize> {
+        let mut neighbours: Vec<usize> = self.vertex_triangles[v]
+            .iter()
+            .filter(|&&t| self.alive[t])
+            .flat_map(|&t| self.triangles[t].iter().cloned())
+            .filter(|&n| n != v)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        return neighbours;
+    }
+
+    /// Collapse of an edge into the point of least error
+    ///
+    /// The optimal point of the quadric is used when it lies near the edge. Flat or straight
+    /// neighbourhoods have a line or a plane of optimal points, there the better one of the
+    /// end points and the midpoint is used instead.
+    fn collapse(&self, keep: usize, remove: usize) -> Collapse {
+        let q = self.quadrics[keep] + self.quadrics[remove];
+        let (a, b) = (self.positions[keep], self.positions[remove]);
+        let mut candidates = vec![a, b, (a + b) * 0.5];
+
+        let system = q.fixed_slice::<na::U3, na::U3>(0, 0).into_owned();
+        if let Some(inverse) = system.try_inverse() {
+            let optimum = -(inverse * q.fixed_slice::<na::U3, na::U1>(0, 3));
+            if (optimum - (a + b) * 0.5).norm() <= (b - a).norm() {
+                candidates.push(optimum);
+            }
+        }
+
+        let (error, target) = candidates
+            .into_iter()
+            .map(|p| (quadric_error(&q, p), p))
+            .min_by(|x, y| x.0.total_cmp(&y.0))
+            .unwrap();
+        return Collapse {
+            error,
+            keep,
+            remove,
+            versions: (self.versions[keep], self.versions[remove]),
+            target,
+        };
+    }
+
+    /// Check that a collapse keeps the surface manifold and does not fold any triangle over
+    fn is_valid(&self, c: &Collapse) -> bool {
+        // The two vertices may only share the neighbours opposite of the edge, otherwise the
+        // collapse pinches the surface into a non-manifold edge
+        let shared_triangles = self.vertex_triangles[c.keep]
+            .iter()
+            .filter(|&&t| self.alive[t] && self.triangles[t].contains(&c.remove))
+            .count();
+        let keep_neighbours = self.neighbours(c.keep);
+        let shared_neighbours = self
+            .neighbours(c.remove)
+            .iter()
+            .filter(|n| keep_neighbours.binary_search(n).is_ok())
+            .count();
+        if shared_neighbours > shared_triangles {
+            return false;
+        }
+
+        for &v in [c.keep, c.remove].iter() {
+            for &t in self.vertex_triangles[v].iter() {
+                let triangle = self.triangles[t];
+                if !self.alive[t] || (triangle.contains(&c.keep) && triangle.contains(&c.remove)) {
+                    continue;
+                }
+                let before = self.normal(&triangle);
+                let p = |i: usize| match triangle[i] {
+                    corner if corner == v => c.target,
+                    corner => self.positions[corner],
+                };
+                let after = (p(1) - p(0)).cross(&(p(2) - p(0)));
+                if after.dot(&before) < 0.0 {
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
+
+    /// Move `keep` to the target and replace `remove` with it, returns the number of
+    /// triangles that collapsed away
+    fn apply(&mut self, c: &Collapse) -> usize {
+        self.positions[c.keep] = c.target;
+        let q = self.quadrics[c.remove];
+        self.quadrics[c.keep] += q;
+        self.removed[c.remove] = true;
+        self.versions[c.keep] += 1;
+
+        let mut collapsed = 0;
+        for t in std::mem::take(&mut self.vertex_triangles[c.remove]) {
+            if !self.alive[t] {
+                continue;
+            }
+            if self.triangles[t].contains(&c.keep) {
+                self.alive[t] = false;
+                collapsed += 1;
+            } else {
+                for v in self.triangles[t].iter_mut().filter(|v| **v == c.remove) {
+                    *v = c.keep;
+                }
+                self.vertex_triangles[c.keep].push(t);
+            }
+        }
+        let alive = &self.alive;
+        self.vertex_triangles[c.keep].retain(|&t| alive[t]);
+        return collapsed;
+    }
+}
+
+impl Mesh {
+    /// Reduce the number of faces by collapsing edges, e.g. for dense scanned models
+    ///
+    /// Returns a new mesh of about `target_faces` faces with the placement, materials, textures
+    /// and render state of this mesh, see `with_faces`. Vertices are merged by position and keep the color and the
+    /// texture coordinates of one of the merged vertices, the normals are recomputed as smooth
+    /// normals. The face count may stay above the target when no edge can collapse without
+    /// folding the surface, and drops by two faces per collapse on a closed surface.
+    ///
+    /// # Arguments
+    ///
+    /// * `target_faces` - Number of faces to reduce the mesh to
+    pub fn simplify(&self, target_faces: usize) -> Mesh {
+        let mut d = Decimation::new(&self.faces);
+
+        let mut edges: Vec<(usize, usize)> = d
+            .triangles
+            .iter()
+            .flat_map(|t| (0..3).map(move |i| (t[i], t[(i + 1) % 3])))
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        let mut heap: BinaryHeap<Collapse> = edges.iter().map(|&(a, b)| d.collapse(a, b)).collect();
+
+        let mut face_count = d.triangles.len();
+        while face_count > target_faces {
+            let c = match heap.pop() {
+                Some(c) => c,
+                None => break,
+            };
+            if d.removed[c.keep]
+                || d.removed[c.remove]
+                || c.versions != (d.versions[c.keep], d.versions[c.remove])
+                || !d.is_valid(&c)
+            {
+                continue;
+            }
+            face_count -= d.apply(&c);
+            for n in d.neighbours(c.keep) {
+                heap.push(d.collapse(c.keep, n));
+            }
+        }
+
+        let position = |v: usize| {
+            let p = d.positions[v];
+            return Vector3::new(p.x as f32, p.y as f32, p.z as f32);
+        };
+        let alive: Vec<usize> = (0..d.triangles.len()).filter(|&t| d.alive[t]).collect();
+        let mut normals = vec![Vector3::zeros(); d.positions.len()];
+        for &t in alive.iter() {
+            let [a, b, c] = d.triangles[t];
+            let n = triangle_normal(position(a), position(b), position(c), self.winding);
+            for &v in d.triangles[t].iter() {
+                normals[v] += n;
+            }
+        }
+
+        let vertex = |v: usize| Vertex {
+            position: Vector4::new(position(v).x, position(v).y, position(v).z, 1.0),
+            normal: normals[v]
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(Vector3::zeros),
+            ..d.attributes[v]
+        };
+        let faces = alive
+            .iter()
+            .map(|&t| {
+                let [a, b, c] = d.triangles[t];
+                return Face {
+                    v0: vertex(a),
+                    v1: vertex(b),
+                    v2: vertex(c),
+                    material_id: d.material_ids[t],
+                };
+            })
+            .collect();
+        return self.with_faces(faces);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{Color, FloatColor, Material};
+    use na::Vector2;
+    use rasterization::{FragmentInput, Uniforms};
+    use std::rc::Rc;
+    use texture::Texture;
+
+    #[test]
+    fn test_simplify_sphere_keeps_shape() {
+        let sphere = Mesh::uv_sphere(16, 32);
+
+        let simplified = sphere.simplify(150);
+
+        let count = simplified.face_count();
+        assert!((140..=150).contains(&count), "{} faces", count);
+        assert!(simplified.validate_winding().is_empty());
+        // Every vertex stays close to the sphere, and every vertex of the sphere is close to
+        // a vertex of the simplified mesh
+        assert!(simplified
+            .vertices()
+            .all(|v| (v.position.xyz().norm() - 1.0).abs() < 0.05));
+        let points: Vec<Vector3<f32>> = simplified.vertices().map(|v| v.position.xyz()).collect();
+        for face in sphere.faces.iter() {
+            let p = face.v0.position.xyz();
+            let nearest = points
+                .iter()
+                .map(|s| (s - p).norm())
+                .fold(f32::MAX, f32::min);
+            assert!(nearest < 0.4, "{}", nearest);
+        }
+    }
+
+    #[test]
+    fn test_simplify_keeps_the_state_of_the_mesh() {
+        let mut sphere = Mesh::uv_sphere(8, 16);
+        sphere.textures.push(Texture::new(1, 1, &[Color::WHITE]));
+        sphere.materials.push(Material {
+            ambient: Color::BLACK,
+            diffuse: Color::WHITE,
+            specular: Color::BLACK,
+            shininess: 1.0,
+            normal_map: Some(0),
+        });
+        sphere.position = Vector4::new(1.0, 2.0, -3.0, 1.0);
+        sphere.max_faces = Some(50);
+        sphere.normal_mapping = false;
+        sphere.cache_transforms = true;
+        sphere.fragment_shader = Some(Rc::new(|_: &FragmentInput, _: &Uniforms| Color::RED));
+
+        let simplified = sphere.simplify(100);
+
+        assert_eq!(simplified.position, sphere.position);
+        assert_eq!(simplified.max_faces, Some(50));
+        assert!(!simplified.normal_mapping);
+        assert!(simplified.cache_transforms);
+        assert_eq!(simplified.materials, sphere.materials);
+        assert_eq!(simplified.textures.len(), 1);
+        assert!(simplified.fragment_shader.is_some());
+    }
+
+    #[test]
+    fn test_simplify_keeps_the_outline_of_a_flat_grid() {
+        let mut grid = Mesh::new();
+        let vertex = |x: usize, y: usize| Vertex {
+            position: Vector4::new(x as f32, y as f32, 0.0, 1.0),
+            color: FloatColor::WHITE,
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            uv: Vector2::zeros(),
+            tangent: Vector3::zeros(),
+        };
+        for y in 0..4 {
+            for x in 0..4 {
+                let (a, b) = (vertex(x, y), vertex(x + 1, y));
+                let (c, d) = (vertex(x + 1, y + 1), vertex(x, y + 1));
+                for &(v0, v1, v2) in [(a, b, c), (a, c, d)].iter() {
+                    grid.faces.push(Face {
+                        v0,
+                        v1,
+                        v2,
+                        material_id: 0,
+                    });
+                }
+            }
+        }
+
+        let simplified = grid.simplify(2);
+
+        assert_eq!(simplified.face_count(), 2);
+        assert!(simplified.vertices().all(|v| v.position.z == 0.0
+            && v.normal == Vector3::new(0.0, 0.0, 1.0)
+            && v.position.x.fract() == 0.0
+            && v.position.y.fract() == 0.0));
+        let area: f32 = simplified
+            .faces
+            .iter()
+            .map(|f| {
+                (f.v1.position - f.v0.position)
+                    .xyz()
+                    .cross(&(f.v2.position - f.v0.position).xyz())
+                    .norm()
+                    * 0.5
+            })
+            .sum();
+        assert!((area - 16.0).abs() < 1e-4, "{}", area);
+        assert_eq!(simplified.faces[0].material_id, 0);
+    }
+}
@@ -0,0 +1,221 @@
+// Loading meshes from STL (stereolithography) files
+//
+// An STL file is a list of triangles, each with its own three corners and a facet normal.
+// There are no shared vertices, vertex normals, colors or texture coordinates, so every
+// vertex of a triangle gets the facet normal and the models are flat shaded.
+//
+// `load_stl` is not available on wasm32, where there is no file system. Use `parse_stl` instead.
+
+use core::{Face, FloatColor, Mesh, Vertex, NO_MATERIAL};
+use io_util::invalid_data;
+use na::{Vector2, Vector3, Vector4};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+/// Size of the header of a binary file, followed by the triangle count
+const HEADER_SIZE: usize = 80;
+/// Size of a triangle in a binary file: normal, three corners and an attribute byte count
+const TRIANGLE_SIZE: usize = 50;
+
+/// Face with the facet normal at every vertex
+///
+/// The normal of the corners is used when the facet normal of the file is zero, which some
+/// exporters write to leave it to the reader.
+fn facet(normal: Vector3<f32>, corners: [Vector3<f32>; 3]) -> Face<Vector4<f32>> {
+    let normal = match normal.try_normalize(f32::EPSILON) {
+        Some(n) => n,
+        None => (corners[1] - corners[0])
+            .cross(&(corners[2] - corners[0]))
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::zeros),
+    };
+    let vertex = |p: Vector3<f32>| Vertex {
+        position: Vector4::new(p.x, p.y, p.z, 1.0),
+        color: FloatColor::WHITE,
+        normal,
+        uv: Vector2::zeros(),
+        tangent: Vector3::zeros(),
+    };
+    return Face {
+        v0: vertex(corners[0]),
+        v1: vertex(corners[1]),
+        v2: vertex(corners[2]),
+        material_id: NO_MATERIAL,
+    };
+}
+
+fn parse_binary(data: &[u8]) -> io::Result<Mesh> {
+    let vector = |b: &[u8]| {
+        let f = |i: usize| f32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+        return Vector3::new(f(0), f(4), f(8));
+    };
+
+    let mut mesh = Mesh::new();
+    for triangle in data[HEADER_SIZE + 4..].chunks_exact(TRIANGLE_SIZE) {
+        let corners = [
+            vector(&triangle[12..]),
+            vector(&triangle[24..]),
+            vector(&triangle[36..]),
+        ];
+        mesh.faces.push(facet(vector(triangle), corners));
+    }
+    return Ok(mesh);
+}
+
+fn parse_ascii(source: &str) -> io::Result<Mesh> {
+    let mut tokens = source.split_whitespace();
+    let vector = |tokens: &mut std::str::SplitWhitespace| -> io::Result<Vector3<f32>> {
+        let mut v = Vector3::zeros();
+        for i in 0..3 {
+            let token = tokens
+                .next()
+                .ok_or_else(|| invalid_data("Unexpected end of file"))?;
+            v[i] = token
+                .parse()
+                .map_err(|_| invalid_data(&format!("Invalid number {}", token)))?;
+        }
+        return Ok(v);
+    };
+
+    let mut mesh = Mesh::new();
+    let mut normal = Vector3::zeros();
+    let mut corners = Vec::with_capacity(3);
+    while let Some(token) = tokens.next() {
+        match token {
+            "normal" => normal = vector(&mut tokens)?,
+            "vertex" => corners.push(vector(&mut tokens)?),
+            "endloop" => {
+                if corners.len() != 3 {
+                    return Err(invalid_data("Facet without three vertices"));
+                }
+                mesh.faces
+                    .push(facet(normal, [corners[0], corners[1], corners[2]]));
+                corners.clear();
+            }
+            _ => {}
+        }
+    }
+    return Ok(mesh);
+}
+
+/// Parse a mesh from the contents of an STL file
+///
+/// Both ASCII and binary files are supported. A file is read as binary when its size matches
+/// the triangle count of the binary header, because binary files may also start with `solid`.
+/// The vertices are white and get the facet normal of their triangle.
+///
+/// # Arguments
+///
+/// * `data` - Contents of an STL file
+pub fn parse_stl(data: &[u8]) -> io::Result<Mesh> {
+    if data.len() >= HEADER_SIZE + 4 {
+        let c = &data[HEADER_SIZE..HEADER_SIZE + 4];
+        let count = u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as usize;
+        // The count is read from the file, the size overflows on 32 bit targets for large ones
+        let size = count
+            .checked_mul(TRIANGLE_SIZE)
+            .and_then(|n| n.checked_add(HEADER_SIZE + 4));
+        if size == Some(data.len()) {
+            return parse_binary(data);
+        }
+    }
+    if data.starts_with(b"solid") {
+        let source = std::str::from_utf8(data).map_err(|_| invalid_data("File is not text"))?;
+        return parse_ascii(source);
+    }
+    return Err(invalid_data("Not an STL file"));
+}
+
+/// Load a mesh from an STL file, see `parse_stl`
+///
+/// # Arguments
+///
+/// * `path` - Path of the file to load
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_stl<P: AsRef<Path>>(path: P) -> io::Result<Mesh> {
+    return parse_stl(&fs::read(path)?);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Binary STL of a unit cube, two triangles for each side
+    fn binary_cube() -> Vec<u8> {
+        let mut data = vec![0u8; HEADER_SIZE];
+        data.extend_from_slice(&12u32.to_le_bytes());
+        for axis in 0..3 {
+            for &side in [-1.0f32, 1.0].iter() {
+                let unit = |i: usize| {
+                    let mut v: Vector3<f32> = Vector3::zeros();
+                    v[i % 3] = 1.0;
+                    return v;
+                };
+                let normal = unit(axis) * side;
+                let u = unit(axis + 1);
+                let v = unit(axis + 2) * side;
+                let quad = [
+                    normal - u - v,
+                    normal + u - v,
+                    normal + u + v,
+                    normal - u + v,
+                ];
+                for corners in [[0, 1, 2], [0, 2, 3]].iter() {
+                    for value in normal.iter() {
+                        data.extend_from_slice(&value.to_le_bytes());
+                    }
+                    for &i in corners.iter() {
+                        for value in (quad[i] * 0.5).iter() {
+                            data.extend_from_slice(&value.to_le_bytes());
+                        }
+                    }
+                    data.extend_from_slice(&[0, 0]);
+                }
+            }
+        }
+        return data;
+    }
+
+    #[test]
+    fn test_parse_binary_cube() {
+        let mesh = parse_stl(&binary_cube()).unwrap();
+
+        assert_eq!(mesh.face_count(), 12);
+        assert!(mesh.validate_winding().is_empty());
+        let face = mesh.faces[0];
+        assert_eq!(face.v0.normal, Vector3::new(-1.0, 0.0, 0.0));
+        assert_eq!(face.v2.normal, face.v0.normal);
+        assert!(mesh
+            .vertices()
+            .all(|v| v.position.xyz().iter().all(|c| c.abs() == 0.5)));
+
+        let mut truncated = binary_cube();
+        truncated.pop();
+        assert!(parse_stl(&truncated).is_err());
+        let mut huge_count = binary_cube();
+        huge_count[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_stl(&huge_count).is_err());
+    }
+
+    #[test]
+    fn test_parse_ascii_computes_missing_normals() {
+        let source = "solid triangle\n\
+                      facet normal 0 0 0\n\
+                      outer loop\n\
+                      vertex 0 0 0\n\
+                      vertex 1 0 0\n\
+                      vertex 0 1 0\n\
+                      endloop\n\
+                      endfacet\n\
+                      endsolid triangle\n";
+
+        let mesh = parse_stl(source.as_bytes()).unwrap();
+
+        assert_eq!(mesh.face_count(), 1);
+        assert_eq!(mesh.faces[0].v1.position, Vector4::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(mesh.faces[0].v1.normal, Vector3::new(0.0, 0.0, 1.0));
+    }
+}
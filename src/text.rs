@@ -0,0 +1,138 @@
+// Text drawing with an embedded bitmap font
+//
+// The font has 5x7 pixel glyphs for digits, upper case letters and common punctuation, lower
+// case letters are drawn in upper case. The text is meant for debug overlays, so it is
+// written over the image without a depth test.
+
+use core::{Color, DisplayBuffer};
+
+/// Width of a glyph in pixels
+pub const GLYPH_WIDTH: usize = 5;
+/// Height of a glyph in pixels
+pub const GLYPH_HEIGHT: usize = 7;
+/// Horizontal distance from the start of a glyph to the next one
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+/// Vertical distance from the top of a line to the next one
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+/// Rows of a glyph from the top, the lowest 5 bits of a row are the pixels from left to right
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    return match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    };
+}
+
+impl DisplayBuffer {
+    /// Draw text over the contents of the buffer
+    ///
+    /// The pixels are written without a depth test and the depth buffer is left unchanged.
+    /// Characters without a glyph are drawn as a question mark, a newline starts a new line
+    /// below. Pixels outside of the buffer are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - X coordinate of the left edge of the text, value 0 corresponds to left edge
+    /// * `y` - Y coordinate of the top edge of the text, value 0 corresponds to bottom edge
+    /// * `text` - Text to draw
+    /// * `color` - Color of the glyphs
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, color: Color) {
+        for (line, line_text) in text.lines().enumerate() {
+            for (column, c) in line_text.chars().enumerate() {
+                let left = x + column * ADVANCE;
+                for (row, bits) in glyph(c).iter().enumerate() {
+                    let py = match y.checked_sub(line * LINE_HEIGHT + row) {
+                        Some(py) if py < self.height => py,
+                        _ => continue,
+                    };
+                    for bit in 0..GLYPH_WIDTH {
+                        let px = left + bit;
+                        if px < self.width && bits & (0x10 >> bit) != 0 {
+                            self.write_pixel((self.height - py - 1) * self.width + px, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_set(buffer: &DisplayBuffer, x: usize, y: usize) -> bool {
+        return buffer.get_pixel(x, y).unwrap().a != 0;
+    }
+
+    #[test]
+    fn test_draw_text_writes_glyph_rows_from_the_top() {
+        let mut buffer = DisplayBuffer::new(20, 20, 4);
+
+        buffer.draw_text(2, 18, "1\nL", Color::WHITE);
+
+        // Top row of "1" has only the middle pixel, the bottom row three pixels
+        assert!(is_set(&buffer, 4, 18));
+        assert!(!is_set(&buffer, 3, 18));
+        assert!((3..6).all(|x| is_set(&buffer, x, 12)));
+        // "L" on the next line has a full bottom row
+        assert!((2..7).all(|x| is_set(&buffer, x, 18 - LINE_HEIGHT - 6)));
+        assert!(buffer.z_buffer.iter().all(|&z| z == f32::MAX));
+    }
+
+    #[test]
+    fn test_draw_text_clips_at_the_edges() {
+        let mut buffer = DisplayBuffer::new(8, 4, 4);
+
+        buffer.draw_text(4, 2, "HELLO", Color::WHITE);
+
+        assert!(is_set(&buffer, 4, 2));
+        assert!(is_set(&buffer, 4, 0));
+    }
+}
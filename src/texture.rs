@@ -0,0 +1,368 @@
+// Textures sampled by the fragment shaders
+//
+// A texture keeps a chain of mip levels, each half the size of the previous one. A surface
+// that is far away covers many texels per pixel, sampling the full resolution level would
+// skip most of them and alias. The level is selected from the derivatives of the texture
+// coordinates across the pixel so that one texel covers about one pixel, and filtering
+// bilinearly within the two nearest levels and linearly between them (trilinear) hides the
+// switches between the levels.
+
+use core::{orthonormal_tangent, Color, ColorSpace, FloatColor};
+use na::{Vector2, Vector3};
+
+/// Image of a single mip level
+#[derive(Clone)]
+struct Level {
+    width: usize,
+    height: usize,
+    /// Texel colors row by row from the top of the image
+    texels: Vec<FloatColor>,
+}
+
+impl Level {
+    /// Color of a texel, the coordinates wrap around (repeat) at the edges
+    fn texel(&self, x: isize, y: isize) -> FloatColor {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+        return self.texels[y * self.width + x];
+    }
+
+    /// Interpolate the four texels nearest to the texture coordinates
+    fn sample_bilinear(&self, uv: Vector2<f32>) -> FloatColor {
+        // Texel centers are at half-integer coordinates, v grows from the bottom up
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let top = self.texel(x0, y0) * (1.0 - tx) + self.texel(x0 + 1, y0) * tx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - tx) + self.texel(x0 + 1, y0 + 1) * tx;
+        return top * (1.0 - ty) + bottom * ty;
+    }
+
+    /// Level of half the size with each texel the average of the texels it covers
+    fn downsample(&self) -> Level {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                // With an odd size the last texel of the half covers the remaining texel too
+                let x_end = if x + 1 == width {
+                    self.width
+                } else {
+                    x * 2 + 2
+                };
+                let y_end = if y + 1 == height {
+                    self.height
+                } else {
+                    y * 2 + 2
+                };
+                let (xs, ys) = ((x * 2)..x_end, (y * 2)..y_end);
+                let count = (xs.len() * ys.len()) as f32;
+                let mut sum = FloatColor {
+                    r: 0.0,
+                    g: 0.0,
+                    b: 0.0,
+                    a: 0.0,
+                };
+                for sy in ys {
+                    for sx in xs.clone() {
+                        sum = sum + self.texels[sy * self.width + sx];
+                    }
+                }
+                texels.push(sum * (1.0 / count));
+            }
+        }
+
+        return Level {
+            width,
+            height,
+            texels,
+        };
+    }
+}
+
+/// Image mapped onto surfaces with texture coordinates
+///
+/// Texture coordinates (0, 0) are at the bottom left corner of the image and (1, 1) at the
+/// top right corner. Coordinates outside of [0, 1] repeat the image.
+#[derive(Clone)]
+pub struct Texture {
+    /// Mip levels from the full resolution image down, only the full image before
+    /// `generate_mipmaps`
+    levels: Vec<Level>,
+}
+
+impl Texture {
+    /// Create a texture from the colors of its texels, the colors are linear intensities
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - Width of the image in texels
+    /// * `height` - Height of the image in texels
+    /// * `texels` - Colors row by row from the top of the image
+    pub fn new(width: usize, height: usize, texels: &[Color]) -> Texture {
+        return Texture::with_color_space(width, height, texels, ColorSpace::Linear);
+    }
+
+    /// Create a texture from the colors of its texels in a color space
+    ///
+    /// The texels are converted into linear intensities once here, so the mip levels
+    /// average and the samples return linear colors that can be lit directly. Images such
+    /// as photos and painted color maps are usually sRGB encoded, data such as normal or
+    /// height maps is linear and should stay unconverted.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - Width of the image in texels
+    /// * `height` - Height of the image in texels
+    /// * `texels` - Colors row by row from the top of the image
+    /// * `color_space` - Encoding of the colors of the texels
+    pub fn with_color_space(
+        width: usize,
+        height: usize,
+        texels: &[Color],
+        color_space: ColorSpace,
+    ) -> Texture {
+        assert!(width > 0 && height > 0, "Texture has no texels");
+        assert_eq!(
+            texels.len(),
+            width * height,
+            "Texel count does not match the size"
+        );
+        return Texture {
+            levels: vec![Level {
+                width,
+                height,
+                texels: texels
+                    .iter()
+                    .map(|&c| color_space.color_to_linear(FloatColor::from(c)))
+                    .collect(),
+            }],
+        };
+    }
+
+    /// Width of the full resolution image in texels
+    pub fn width(&self) -> usize {
+        return self.levels[0].width;
+    }
+
+    /// Height of the full resolution image in texels
+    pub fn height(&self) -> usize {
+        return self.levels[0].height;
+    }
+
+    /// Number of mip levels, including the full resolution image
+    pub fn level_count(&self) -> usize {
+        return self.levels.len();
+    }
+
+    /// Generate the chain of mip levels down to a single texel
+    pub fn generate_mipmaps(&mut self) {
+        self.levels.truncate(1);
+        loop {
+            let last = &self.levels[self.levels.len() - 1];
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            self.levels.push(next);
+        }
+    }
+
+    /// Sample a single mip level with bilinear filtering
+    ///
+    /// # Arguments
+    ///
+    /// * `uv` - Texture coordinates
+    /// * `level` - Mip level, the last level is used when there are fewer levels
+    pub fn sample_level(&self, uv: Vector2<f32>, level: usize) -> FloatColor {
+        let level = level.min(self.levels.len() - 1);
+        return self.levels[level].sample_bilinear(uv);
+    }
+
+    /// Sample the texture with trilinear filtering
+    ///
+    /// The mip level is selected from the change of the texture coordinates between adjacent
+    /// pixels (e.g. `FragmentInput::uv_dx` and `uv_dy`). Without mip levels the full
+    /// resolution image is sampled bilinearly.
+    ///
+    /// # Arguments
+    ///
+    /// * `uv` - Texture coordinates
+    /// * `uv_dx` - Change of the texture coordinates to the next pixel on the right
+    /// * `uv_dy` - Change of the texture coordinates to the next pixel up
+    pub fn sample(&self, uv: Vector2<f32>, uv_dx: Vector2<f32>, uv_dy: Vector2<f32>) -> FloatColor {
+        let size = Vector2::new(self.width() as f32, self.height() as f32);
+        let footprint = uv_dx
+            .component_mul(&size)
+            .norm()
+            .max(uv_dy.component_mul(&size).norm());
+        let lod = footprint
+            .max(1.0)
+            .log2()
+            .min((self.levels.len() - 1) as f32);
+
+        let level = lod.floor() as usize;
+        let t = lod - level as f32;
+        let near = self.sample_level(uv, level);
+        if t == 0.0 {
+            return near;
+        }
+        return near * (1.0 - t) + self.sample_level(uv, level + 1) * t;
+    }
+
+    /// Sample the texture as a tangent space normal map and perturb a surface normal
+    ///
+    /// The red, green and blue channels [0, 1] map to the x (along the tangent), y (along the
+    /// bitangent, normal x tangent) and z (along the normal) components [-1, 1], so the flat
+    /// color (0.5, 0.5, 1.0) keeps the normal unchanged. The texture should be linear. A
+    /// surface without a tangent keeps its normal.
+    ///
+    /// # Arguments
+    ///
+    /// * `normal` - Interpolated surface normal
+    /// * `tangent` - Interpolated tangent along the u texture coordinate
+    /// * `uv`, `uv_dx`, `uv_dy` - Texture coordinates and their derivatives, see `sample`
+    pub fn sample_normal(
+        &self,
+        normal: Vector3<f32>,
+        tangent: Vector3<f32>,
+        uv: Vector2<f32>,
+        uv_dx: Vector2<f32>,
+        uv_dy: Vector2<f32>,
+    ) -> Vector3<f32> {
+        let n = normal.try_normalize(f32::EPSILON).unwrap_or(normal);
+        let t = orthonormal_tangent(tangent, n);
+        if t == Vector3::zeros() {
+            return n;
+        }
+
+        let c = self.sample(uv, uv_dx, uv_dy);
+        let m = Vector3::new(c.r, c.g, c.b) * 2.0 - Vector3::repeat(1.0);
+        let perturbed = t * m.x + n.cross(&t) * m.y + n * m.z;
+        return perturbed.try_normalize(f32::EPSILON).unwrap_or(n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(v: u8) -> Color {
+        return Color {
+            r: v,
+            g: v,
+            b: v,
+            a: 255,
+        };
+    }
+
+    /// Checkerboard of black and white texels
+    fn checkerboard(size: usize) -> Texture {
+        let texels: Vec<Color> = (0..size * size)
+            .map(|i| {
+                if (i / size + i % size).is_multiple_of(2) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .map(gray)
+            .collect();
+        return Texture::new(size, size, &texels);
+    }
+
+    #[test]
+    fn test_generate_mipmaps_averages_down_to_one_texel() {
+        let mut texture = checkerboard(8);
+
+        texture.generate_mipmaps();
+
+        assert_eq!(texture.level_count(), 4);
+        let last = texture.sample_level(Vector2::new(0.3, 0.7), 3);
+        assert!((last.r - 0.5).abs() < 1e-6);
+        assert_eq!(last.a, 1.0);
+    }
+
+    #[test]
+    fn test_srgb_texels_are_sampled_as_linear() {
+        let texels = [gray(0), gray(188), gray(255), gray(188)];
+        let mut srgb = Texture::with_color_space(2, 2, &texels, ColorSpace::Srgb);
+        let linear = Texture::new(2, 2, &texels);
+
+        srgb.generate_mipmaps();
+
+        let texel = Vector2::new(0.75, 0.75);
+        assert!((srgb.sample_level(texel, 0).r - 0.5029).abs() < 1e-3);
+        assert!((linear.sample_level(texel, 0).r - 188.0 / 255.0).abs() < 1e-6);
+        // The mip level averages the linear intensities
+        let average = (1.0 + 2.0 * srgb.sample_level(texel, 0).r) / 4.0;
+        assert!((srgb.sample_level(texel, 1).r - average).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_generate_mipmaps_handles_odd_sizes() {
+        let mut texture = Texture::new(3, 1, &[gray(0), gray(0), gray(255)]);
+
+        texture.generate_mipmaps();
+
+        assert_eq!(texture.level_count(), 2);
+        assert_eq!(texture.levels[1].width, 1);
+        assert!((texture.levels[1].texels[0].r - 1.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sample_normal_tilts_toward_tangent() {
+        let color = |r: u8, g: u8, b: u8| Color { r, g, b, a: 255 };
+        let flat = Texture::new(1, 1, &[color(128, 128, 255)]);
+        let tilted = Texture::new(1, 1, &[color(218, 128, 218)]);
+        let (n, t) = (Vector3::new(0.0, 0.0, 2.0), Vector3::new(1.0, 0.0, 0.3));
+        let (uv, d) = (Vector2::new(0.5, 0.5), Vector2::zeros());
+
+        let unchanged = flat.sample_normal(n, t, uv, d, d);
+        let perturbed = tilted.sample_normal(n, t, uv, d, d);
+
+        assert!((unchanged - Vector3::z()).norm() < 0.01);
+        assert!((perturbed - Vector3::new(1.0, 0.0, 1.0).normalize()).norm() < 0.01);
+        assert_eq!(
+            tilted.sample_normal(n, Vector3::zeros(), uv, d, d),
+            Vector3::z()
+        );
+    }
+
+    #[test]
+    fn test_sample_selects_level_from_footprint() {
+        let mut texture = checkerboard(8);
+        texture.generate_mipmaps();
+        let uv = Vector2::new(1.0 / 16.0, 1.0 - 1.0 / 16.0);
+
+        // One texel per pixel samples the full resolution image at a texel center
+        let step = Vector2::new(1.0 / 8.0, 0.0);
+        let full = texture.sample(uv, step, step.yx());
+        assert_eq!(full.r, 1.0);
+
+        // The whole texture in one pixel averages the checkerboard
+        let step = Vector2::new(1.0, 0.0);
+        let far = texture.sample(uv, step, step.yx());
+        assert!((far.r - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sample_blends_between_levels() {
+        let mut texture = checkerboard(8);
+        texture.generate_mipmaps();
+        let uv = Vector2::new(1.0 / 16.0, 1.0 - 1.0 / 16.0);
+
+        // A footprint of 1.5 texels is between the first two levels
+        let step = Vector2::new(1.5 / 8.0, 0.0);
+        let c = texture.sample(uv, step, step.yx());
+        let lod = 1.5f32.log2();
+        let expected =
+            texture.sample_level(uv, 0).r * (1.0 - lod) + texture.sample_level(uv, 1).r * lod;
+        assert!((c.r - expected).abs() < 1e-6);
+    }
+}
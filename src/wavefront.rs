@@ -0,0 +1,569 @@
+// Streaming loader for Wavefront OBJ files
+//
+// The faces of an OBJ file index into the lists of positions, texture coordinates and normals
+// stated before them. The reader keeps only these lists and yields the faces of each `f`
+// statement as soon as its line has been parsed, so the source text and the faces of a large
+// file never need to be in memory at once. The faces can be drawn and dropped as they are
+// read, or collected into a `Mesh`.
+//
+// Besides the geometry only the materials are read: `load_obj` looks up the `usemtl` names
+// in the MTL libraries of the file, see `read_mtl`. Groups, smoothing groups, lines and
+// points are skipped.
+//
+// `load_obj` is not available on wasm32, where there is no file system. Use `read_obj` instead.
+
+use core::{Face, FloatColor, Material, Mesh, Vertex, NO_MATERIAL};
+use io_util::invalid_data;
+use na::{Vector2, Vector3, Vector4};
+use std::collections::{HashMap, VecDeque};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::BufReader;
+use std::io::{self, BufRead};
+use std::mem;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+/// Position, uv and normal index of a face corner
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// Faces read one statement at a time from an OBJ source
+///
+/// Yields the triangles of the faces in file order. Faces with more than three corners are
+/// split into a fan of triangles, corners without a normal get the normal of the face.
+/// Vertex colors appended to the positions (`v x y z r g b`) are read, other vertices are
+/// white. The tangents are zero, see `Mesh::compute_tangents`. Reading stops at the first
+/// error, which is yielded with its line number.
+///
+/// The material id of a face is the index of its `usemtl` name in `material_names`, faces
+/// before the first `usemtl` statement have no material.
+pub struct ObjFaces<R: BufRead> {
+    reader: R,
+    line: String,
+    line_number: usize,
+    positions: Vec<Vector3<f32>>,
+    colors: Vec<FloatColor>,
+    tex_coords: Vec<Vector2<f32>>,
+    normals: Vec<Vector3<f32>>,
+    material_libraries: Vec<String>,
+    material_names: Vec<String>,
+    /// Material id of the following faces
+    material_id: usize,
+    /// Triangles of the last face that have not been yielded yet
+    pending: VecDeque<Face<Vector4<f32>>>,
+    failed: bool,
+}
+
+impl<R: BufRead> ObjFaces<R> {
+    /// Start reading the faces of an OBJ source
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Source of the OBJ text, e.g. a `BufReader` of a file
+    pub fn new(reader: R) -> ObjFaces<R> {
+        return ObjFaces {
+            reader,
+            line: String::new(),
+            line_number: 0,
+            positions: Vec::new(),
+            colors: Vec::new(),
+            tex_coords: Vec::new(),
+            normals: Vec::new(),
+            material_libraries: Vec::new(),
+            material_names: Vec::new(),
+            material_id: NO_MATERIAL,
+            pending: VecDeque::new(),
+            failed: false,
+        };
+    }
+
+    /// Paths of the MTL libraries of the `mtllib` statements read so far, relative to the
+    /// OBJ file
+    pub fn material_libraries(&self) -> &[String] {
+        return &self.material_libraries;
+    }
+
+    /// Names of the `usemtl` statements read so far in the order of their first use
+    pub fn material_names(&self) -> &[String] {
+        return &self.material_names;
+    }
+
+    fn invalid(&self, message: &str) -> io::Error {
+        return invalid_data(&format!("Line {}: {}", self.line_number, message));
+    }
+
+    /// Parse the statement of a line, the triangles of a face are added to `pending`
+    fn parse_line(&mut self, line: &str) -> io::Result<()> {
+        let line = line.split('#').next().unwrap_or("");
+        let mut args = line.split_whitespace();
+        let keyword = match args.next() {
+            Some(k) => k,
+            None => return Ok(()),
+        };
+        let numbers: Vec<&str> = args.collect();
+        let float = |s: &str| s.parse::<f32>().ok();
+
+        match keyword {
+            "v" => {
+                // v x y z [w] [r g b]
+                let values: Option<Vec<f32>> = numbers.iter().map(|&s| float(s)).collect();
+                let v = match values {
+                    Some(ref v) if v.len() >= 3 => v,
+                    _ => return Err(self.invalid("Expected a position")),
+                };
+                let color = match v.len() {
+                    6 => Some(&v[3..6]),
+                    7 => Some(&v[4..7]),
+                    _ => None,
+                };
+                self.positions.push(Vector3::new(v[0], v[1], v[2]));
+                self.colors
+                    .push(color.map_or(FloatColor::WHITE, |c| FloatColor {
+                        r: c[0],
+                        g: c[1],
+                        b: c[2],
+                        a: 1.0,
+                    }));
+            }
+            "vt" => {
+                let u = numbers.first().and_then(|&s| float(s));
+                // The v coordinate is optional for 1D textures
+                let v = numbers.get(1).map_or(Some(0.0), |&s| float(s));
+                match (u, v) {
+                    (Some(u), Some(v)) => self.tex_coords.push(Vector2::new(u, v)),
+                    _ => return Err(self.invalid("Expected a texture coordinate")),
+                }
+            }
+            "vn" => {
+                let values: Option<Vec<f32>> = numbers.iter().map(|&s| float(s)).collect();
+                match values {
+                    Some(ref n) if n.len() == 3 => {
+                        self.normals.push(Vector3::new(n[0], n[1], n[2]))
+                    }
+                    _ => return Err(self.invalid("Expected a normal")),
+                }
+            }
+            "f" => {
+                let corners = numbers
+                    .iter()
+                    .map(|&s| self.corner(s))
+                    .collect::<io::Result<Vec<_>>>()?;
+                if corners.len() < 3 {
+                    return Err(self.invalid("Face has fewer than three corners"));
+                }
+                self.add_face(&corners);
+            }
+            "mtllib" => {
+                let libraries = numbers.iter().map(|name| name.to_string());
+                self.material_libraries.extend(libraries);
+            }
+            "usemtl" => {
+                let name = numbers.join(" ");
+                self.material_id = match self.material_names.iter().position(|n| *n == name) {
+                    Some(id) => id,
+                    None => {
+                        self.material_names.push(name);
+                        self.material_names.len() - 1
+                    }
+                };
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    /// Position, uv and normal index of a face corner `p`, `p/t`, `p//n` or `p/t/n`
+    fn corner(&self, s: &str) -> io::Result<Corner> {
+        let mut parts = s.split('/');
+        let resolve = |part: Option<&str>, len: usize| -> io::Result<Option<usize>> {
+            let part = match part {
+                Some(p) if !p.is_empty() => p,
+                _ => return Ok(None),
+            };
+            let index: isize = part
+                .parse()
+                .map_err(|_| self.invalid("Expected a vertex index"))?;
+            // Indices start from 1, negative indices count back from the last element
+            let resolved = if index < 0 {
+                len as isize + index
+            } else {
+                index - 1
+            };
+            if !(0..len as isize).contains(&resolved) {
+                return Err(self.invalid("Vertex index out of range"));
+            }
+            return Ok(Some(resolved as usize));
+        };
+        let position = resolve(parts.next(), self.positions.len())?;
+        let uv = resolve(parts.next(), self.tex_coords.len())?;
+        let normal = resolve(parts.next(), self.normals.len())?;
+        return match position {
+            Some(p) => Ok((p, uv, normal)),
+            None => Err(self.invalid("Expected a vertex index")),
+        };
+    }
+
+    /// Split a face into a fan of triangles and queue them
+    fn add_face(&mut self, corners: &[Corner]) {
+        let (a, b, c) = (
+            self.positions[corners[0].0],
+            self.positions[corners[1].0],
+            self.positions[corners[2].0],
+        );
+        let face_normal = (b - a)
+            .cross(&(c - a))
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::zeros);
+        let vertex = |(pi, ti, ni): Corner| {
+            let p = self.positions[pi];
+            return Vertex {
+                position: Vector4::new(p.x, p.y, p.z, 1.0),
+                color: self.colors[pi],
+                normal: ni.map_or(face_normal, |ni| self.normals[ni]),
+                uv: ti.map_or(Vector2::zeros(), |ti| self.tex_coords[ti]),
+                tangent: Vector3::zeros(),
+            };
+        };
+        let triangles: Vec<_> = (1..corners.len() - 1)
+            .map(|i| Face {
+                v0: vertex(corners[0]),
+                v1: vertex(corners[i]),
+                v2: vertex(corners[i + 1]),
+                material_id: self.material_id,
+            })
+            .collect();
+        self.pending.extend(triangles);
+    }
+}
+
+impl<R: BufRead> Iterator for ObjFaces<R> {
+    type Item = io::Result<Face<Vector4<f32>>>;
+
+    fn next(&mut self) -> Option<io::Result<Face<Vector4<f32>>>> {
+        while self.pending.is_empty() && !self.failed {
+            self.line.clear();
+            self.line_number += 1;
+            let result = match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    // The line buffer is reused, lend it out while the statement is parsed
+                    let line = mem::take(&mut self.line);
+                    let result = self.parse_line(&line);
+                    self.line = line;
+                    result
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+        return self.pending.pop_front().map(Ok);
+    }
+}
+
+/// Collect the faces of an OBJ source into a mesh
+///
+/// `materials` returns the materials of the libraries of the source by name, the faces of
+/// the `usemtl` names it does not define get no material.
+fn collect_mesh<R: BufRead, F>(mut faces: ObjFaces<R>, materials: F) -> io::Result<Mesh>
+where
+    F: FnOnce(&[String]) -> HashMap<String, Material>,
+{
+    let mut mesh = Mesh::new();
+    for face in faces.by_ref() {
+        mesh.faces.push(face?);
+    }
+
+    let defined = materials(faces.material_libraries());
+    let ids: Vec<usize> = faces
+        .material_names()
+        .iter()
+        .map(|name| match defined.get(name) {
+            Some(&material) => mesh.add_material(material),
+            None => NO_MATERIAL,
+        })
+        .collect();
+    for face in mesh.faces.iter_mut() {
+        face.material_id = ids.get(face.material_id).copied().unwrap_or(NO_MATERIAL);
+    }
+    mesh.compute_tangents();
+    return Ok(mesh);
+}
+
+/// Read a mesh from an OBJ source without holding the whole source in memory
+///
+/// The faces are added to the mesh as they are read, see `ObjFaces`, and the tangents are
+/// computed from the texture coordinates at the end. The material libraries are not read,
+/// so the faces have no material.
+///
+/// # Arguments
+///
+/// * `reader` - Source of the OBJ text
+pub fn read_obj<R: BufRead>(reader: R) -> io::Result<Mesh> {
+    return collect_mesh(ObjFaces::new(reader), |_| HashMap::new());
+}
+
+/// Load a mesh from an OBJ file with the materials of its MTL libraries, see `read_obj`
+///
+/// The libraries are looked up relative to the directory of the file. Libraries that cannot
+/// be read are skipped, the faces of the materials they define then have no material.
+///
+/// # Arguments
+///
+/// * `path` - Path of the file to load
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_obj<P: AsRef<Path>>(path: P) -> io::Result<Mesh> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let faces = ObjFaces::new(BufReader::new(File::open(path)?));
+    return collect_mesh(faces, |libraries| {
+        let mut materials = HashMap::new();
+        for library in libraries {
+            let file = File::open(dir.join(library));
+            if let Ok(defined) = file.and_then(|f| read_mtl(BufReader::new(f))) {
+                materials.extend(defined);
+            }
+        }
+        return materials;
+    });
+}
+
+/// Read the materials of an MTL library by name
+///
+/// The ambient (`Ka`), diffuse (`Kd`) and specular (`Ks`) colors, the specular exponent
+/// (`Ns`) and the opacity (`d`, or `Tr` for the transparency) are read, the opacity is the
+/// alpha of the three colors. Colors that are not given as RGB are black. Texture maps and
+/// the other statements are skipped.
+///
+/// # Arguments
+///
+/// * `reader` - Source of the MTL text
+pub fn read_mtl<R: BufRead>(reader: R) -> io::Result<HashMap<String, Material>> {
+    /// Values of the material that is being read, the alpha is applied at its end
+    #[derive(Default)]
+    struct Values {
+        ambient: [f32; 3],
+        diffuse: [f32; 3],
+        specular: [f32; 3],
+        shininess: f32,
+        alpha: Option<f32>,
+    }
+    let finish = |values: &Values| {
+        let alpha = values.alpha.unwrap_or(1.0);
+        let color = |[r, g, b]: [f32; 3]| FloatColor { r, g, b, a: alpha }.into_color();
+        return Material {
+            ambient: color(values.ambient),
+            diffuse: color(values.diffuse),
+            specular: color(values.specular),
+            shininess: values.shininess,
+            normal_map: None,
+        };
+    };
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Values)> = None;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let invalid = |message: &str| invalid_data(&format!("Line {}: {}", index + 1, message));
+        let mut args = line.split('#').next().unwrap_or("").split_whitespace();
+        let keyword = match args.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let rest: Vec<&str> = args.collect();
+        if keyword == "newmtl" {
+            if let Some((name, values)) = current.take() {
+                materials.insert(name, finish(&values));
+            }
+            current = Some((rest.join(" "), Values::default()));
+            continue;
+        }
+        let values = match current {
+            Some((_, ref mut values)) => values,
+            None => continue,
+        };
+        let numbers: Option<Vec<f32>> = rest.iter().map(|s| s.parse().ok()).collect();
+        let rgb = || match numbers {
+            Some(ref n) if n.len() == 3 => Ok([n[0], n[1], n[2]]),
+            Some(ref n) if n.len() == 1 => Ok([n[0]; 3]),
+            // Spectral and CIE XYZ colors
+            None if !rest.is_empty() => Ok([0.0; 3]),
+            _ => Err(invalid("Expected a color")),
+        };
+        let number = || match numbers {
+            Some(ref n) if n.len() == 1 => Ok(n[0]),
+            _ => Err(invalid("Expected a number")),
+        };
+        match keyword {
+            "Ka" => values.ambient = rgb()?,
+            "Kd" => values.diffuse = rgb()?,
+            "Ks" => values.specular = rgb()?,
+            "Ns" => values.shininess = number()?,
+            "d" => values.alpha = Some(number()?),
+            "Tr" => values.alpha = Some(1.0 - number()?),
+            _ => {}
+        }
+    }
+    if let Some((name, values)) = current {
+        materials.insert(name, finish(&values));
+    }
+    return Ok(materials);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_polygons_with_relative_indices_and_colors() {
+        let source = "# quad and a triangle\n\
+                      v 0 0 0 1 0 0\n\
+                      v 1 0 0\n\
+                      v 1 1 0\n\
+                      v 0 1 0\n\
+                      vt 0.5 0.25\n\
+                      vn 0 0 1\n\
+                      f 1/1/1 2/1/1 3/1/1 4/1/1\n\
+                      usemtl ignored\n\
+                      f -4 -2 -1\n";
+
+        let mesh = read_obj(source.as_bytes()).unwrap();
+
+        assert_eq!(mesh.faces.len(), 3);
+        let quad = &mesh.faces[1];
+        assert_eq!(quad.v0.position, Vector4::new(0.0, 0.0, 0.0, 1.0));
+        assert_eq!(quad.v2.position, Vector4::new(0.0, 1.0, 0.0, 1.0));
+        assert_eq!(quad.v0.uv, Vector2::new(0.5, 0.25));
+        assert_eq!((quad.v0.color.r, quad.v0.color.g), (1.0, 0.0));
+        assert_eq!((quad.v1.color.r, quad.v1.color.g), (1.0, 1.0));
+        let triangle = &mesh.faces[2];
+        assert_eq!(triangle.v1.position, Vector4::new(1.0, 1.0, 0.0, 1.0));
+        assert_eq!(triangle.v0.normal, Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_faces_are_yielded_before_the_rest_is_read() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nf 1 2 7\nf 1 2 3\n";
+        let mut faces = ObjFaces::new(source.as_bytes());
+
+        assert!(faces.next().unwrap().is_ok());
+        match faces.next() {
+            Some(Err(e)) => assert!(e.to_string().starts_with("Line 5")),
+            _ => panic!("Expected an error for the index out of range"),
+        }
+        assert!(faces.next().is_none());
+    }
+
+    #[test]
+    fn test_faces_refer_to_the_used_material_names() {
+        let source = "mtllib first.mtl\n\
+                      v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                      f 1 2 3\n\
+                      usemtl red paint\nf 1 2 3\n\
+                      usemtl blue\nf 1 2 3\n\
+                      mtllib second.mtl\n\
+                      usemtl red paint\nf 1 2 3\n";
+        let mut faces = ObjFaces::new(source.as_bytes());
+
+        let ids: Vec<usize> = faces.by_ref().map(|f| f.unwrap().material_id).collect();
+
+        assert_eq!(ids, vec![NO_MATERIAL, 0, 1, 0]);
+        assert_eq!(faces.material_names(), ["red paint", "blue"]);
+        assert_eq!(faces.material_libraries(), ["first.mtl", "second.mtl"]);
+    }
+
+    #[test]
+    fn test_read_material_colors_and_opacity() {
+        let source = "newmtl red\n\
+                      Ka 0.5 0 0\n\
+                      Kd 1 0 0 # comment\n\
+                      Ks spectral lamp.rfl\n\
+                      Ns 10\n\
+                      d 0.5\n\
+                      map_Kd red.png\n\
+                      newmtl gray\n\
+                      Kd 0.5\n\
+                      Tr 0.25\n";
+
+        let materials = read_mtl(source.as_bytes()).unwrap();
+
+        let red = materials["red"];
+        assert_eq!(
+            red.diffuse,
+            FloatColor {
+                r: 1.0,
+                g: 0.0,
+                b: 0.0,
+                a: 0.5
+            }
+            .into_color()
+        );
+        assert_eq!(
+            red.ambient,
+            FloatColor {
+                r: 0.5,
+                g: 0.0,
+                b: 0.0,
+                a: 0.5
+            }
+            .into_color()
+        );
+        assert_eq!(
+            red.specular,
+            FloatColor {
+                a: 0.5,
+                ..FloatColor::BLACK
+            }
+            .into_color()
+        );
+        assert_eq!(red.shininess, 10.0);
+        let gray = materials["gray"];
+        assert_eq!(
+            gray.diffuse,
+            FloatColor {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+                a: 0.75
+            }
+            .into_color()
+        );
+        assert_eq!(gray.shininess, 0.0);
+
+        let error = read_mtl("newmtl broken\nNs high\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().starts_with("Line 2"));
+    }
+
+    #[test]
+    fn test_load_obj_with_the_materials_of_its_libraries() {
+        let dir = std::env::temp_dir().join(format!("wavefront_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("paint.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+        let source = "mtllib paint.mtl missing.mtl\n\
+                      v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                      usemtl red\nf 1 2 3\n\
+                      usemtl undefined\nf 1 2 3\n";
+        std::fs::write(dir.join("model.obj"), source).unwrap();
+
+        let mesh = load_obj(dir.join("model.obj"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mesh = mesh.unwrap();
+        assert_eq!(mesh.materials.len(), 1);
+        assert_eq!(
+            mesh.materials[0].diffuse,
+            FloatColor {
+                r: 1.0,
+                g: 0.0,
+                b: 0.0,
+                a: 1.0
+            }
+            .into_color()
+        );
+        assert_eq!(mesh.faces[0].material_id, 0);
+        assert_eq!(mesh.faces[1].material_id, NO_MATERIAL);
+    }
+}